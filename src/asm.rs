@@ -0,0 +1,791 @@
+use crate::cpu::PROGRAM_START;
+use crate::instruction::Instruction;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Instruction mnemonics, used to tell invalid operands from unknown instructions
+const MNEMONICS: [&str; 29] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE",
+    "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP",
+    "PLANE", "AUDIO", "PITCH",
+];
+
+/// Operand keywords, which cannot be used as symbol names
+const KEYWORDS: [&str; 9] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+
+/// Error returned when a source cannot be assembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// The file holding the error, None for the source given to `assemble` without a path
+    pub file: Option<PathBuf>,
+    /// The line of the error, starting at 1
+    pub line: usize,
+    /// The description of the error
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}: {}", file.display(), self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl error::Error for AsmError {}
+
+/// Position of a line in the sources
+#[derive(Debug, Clone)]
+struct Location {
+    /// The file holding the line, if any
+    file: Option<PathBuf>,
+    /// The line number, starting at 1
+    line: usize,
+}
+
+impl Location {
+    /// Builds an error located at this line
+    ///
+    /// # Parameters
+    ///
+    /// - `message`: The description of the error
+    ///
+    /// # Returns
+    ///
+    /// The `AsmError`
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+/// Operand of an instruction, the values being evaluated once every label is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    /// Register Vx
+    Register(u8),
+    /// Registers Vx to Vy (XO-CHIP)
+    Range(u8, u8),
+    /// Memory at I: `[I]`
+    IndirectI,
+    /// A keyword: I, DT, ST, K, F, HF, B or R
+    Keyword(&'static str),
+    /// A 16 bits address: `LONG expression` (XO-CHIP)
+    Long(String),
+    /// A number, symbol or sum of them
+    Value(String),
+}
+
+/// Statement emitting bytes, whose size is known in the first pass
+#[derive(Debug)]
+enum Statement {
+    /// An instruction with its operands
+    Instruction(String, Vec<Operand>),
+    /// `DB` bytes
+    Bytes(Vec<String>),
+    /// `DW` big endian words
+    Words(Vec<String>),
+}
+
+impl Statement {
+    /// Gets the number of bytes emitted by the statement
+    ///
+    /// # Returns
+    ///
+    /// The size of the statement, in bytes
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(mnemonic, operands) => {
+                match (mnemonic.as_str(), operands.as_slice()) {
+                    ("LD", [Operand::Keyword("I"), Operand::Long(_)]) => 4,
+                    _ => 2,
+                }
+            }
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => values.len() * 2,
+        }
+    }
+}
+
+/// Assembler state shared by the source and its included files
+#[derive(Default)]
+struct Assembler {
+    /// Statements to encode in the second pass, with their location
+    statements: Vec<(Location, Statement)>,
+    /// Value of the labels and constants
+    symbols: HashMap<String, i64>,
+    /// Address of the next statement
+    address: usize,
+    /// Files being assembled, to detect recursive includes
+    include_stack: Vec<PathBuf>,
+}
+
+/// Assembles a source into a ROM loadable with `Chip8::load_rom`.
+///
+/// The source uses the mnemonics of the CPU documentation (`LD V1, #0A`, `DRW V0, V1, 5`,
+/// `LD [I], V3`, ...), case insensitively, one instruction per line:
+///
+/// ```text
+/// ; Draws the digit 7 in the top left corner
+/// digit = 7
+///
+/// start:
+///     LD V0, digit
+///     LD F, V0
+///     DRW V1, V1, 5       ; V1 is 0 at reset
+/// loop:
+///     JP loop
+///
+/// sprite:
+///     DB #3C, %01000010   ; '#' hexadecimal, '%' binary, else decimal
+///     DW #0102, sprite + 1
+///     INCLUDE "more.asm"
+/// ```
+///
+/// - `;` starts a comment.
+/// - `name:` defines a label holding the address of the next statement, the program starting at
+///   `PROGRAM_START`.
+/// - `name = value` defines a constant, which can only use the symbols defined before it.
+/// - Values are numbers (`#FF` or `0xFF` hexadecimal, `%1010` or `0b1010` binary, decimal) and
+///   symbols, added or subtracted (`sprite + 5`). Bytes can be negative (`ADD V0, -1`).
+/// - `DB` and `DW` emit bytes and big endian words.
+/// - `INCLUDE "file"` assembles another file at this point, relative to the including file.
+///
+/// # Parameters
+///
+/// - `source`: The assembly source
+/// - `path`: The path of the source, used in error messages and to find the included files
+///   (which are relative to the current directory without it)
+///
+/// # Returns
+///
+/// The ROM bytes, or an `AsmError` locating the first error
+pub fn assemble(source: &str, path: Option<&Path>) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler {
+        address: PROGRAM_START as usize,
+        ..Assembler::default()
+    };
+    if let Some(path) = path {
+        assembler
+            .include_stack
+            .push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    }
+    assembler.parse(source, path)?;
+    assembler.encode()
+}
+
+impl Assembler {
+    /// First pass: parses a source, defining the symbols and recording the statements
+    ///
+    /// # Parameters
+    ///
+    /// - `source`: The assembly source
+    /// - `path`: The path of the source, if any
+    ///
+    /// # Returns
+    ///
+    /// An `AsmError` if a line is invalid
+    fn parse(&mut self, source: &str, path: Option<&Path>) -> Result<(), AsmError> {
+        for (index, line) in source.lines().enumerate() {
+            let location = Location {
+                file: path.map(Path::to_path_buf),
+                line: index + 1,
+            };
+            self.parse_line(line, &location)?;
+        }
+        Ok(())
+    }
+
+    /// Parses a line of a source
+    ///
+    /// # Parameters
+    ///
+    /// - `line`: The line
+    /// - `location`: The location of the line
+    ///
+    /// # Returns
+    ///
+    /// An `AsmError` if the line is invalid
+    fn parse_line(&mut self, line: &str, location: &Location) -> Result<(), AsmError> {
+        let error = |message| location.error(message);
+        let mut line = strip_comment(line).trim();
+
+        // Labels
+        while let Some(colon) = line.find(':') {
+            let name = line[..colon].trim();
+            if !is_symbol(name) {
+                break;
+            }
+            self.define(name, self.address as i64).map_err(error)?;
+            line = line[colon + 1..].trim();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        // Constants
+        if let Some(equal) = line.find('=') {
+            let name = line[..equal].trim();
+            if is_symbol(name) {
+                let value = self.evaluate(&line[equal + 1..]).map_err(error)?;
+                return self.define(name, value).map_err(error);
+            }
+        }
+
+        let (mnemonic, operands) = match line.find(char::is_whitespace) {
+            Some(space) => (&line[..space], line[space..].trim()),
+            None => (line, ""),
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let statement = match mnemonic.as_str() {
+            "INCLUDE" => return self.include(operands, location),
+            "DB" => Statement::Bytes(split_operands(operands).map_err(error)?),
+            "DW" => Statement::Words(split_operands(operands).map_err(error)?),
+            _ => {
+                let operands = match operands {
+                    "" => Vec::new(),
+                    _ => split_operands(operands)
+                        .map_err(error)?
+                        .iter()
+                        .map(|operand| parse_operand(operand))
+                        .collect(),
+                };
+                Statement::Instruction(mnemonic, operands)
+            }
+        };
+
+        self.address += statement.size();
+        if self.address > 0x10000 {
+            return Err(error(String::from(
+                "the program does not fit in the 64 KiB address space",
+            )));
+        }
+        self.statements.push((location.clone(), statement));
+        Ok(())
+    }
+
+    /// Assembles an included file
+    ///
+    /// # Parameters
+    ///
+    /// - `operand`: The quoted path of the file, relative to the including file
+    /// - `location`: The location of the `INCLUDE` line
+    ///
+    /// # Returns
+    ///
+    /// An `AsmError` located at the `INCLUDE` line if the file cannot be read or includes
+    /// itself, or located in the included file if it is invalid
+    fn include(&mut self, operand: &str, location: &Location) -> Result<(), AsmError> {
+        let error = |message| location.error(message);
+        let name = operand
+            .strip_prefix('"')
+            .and_then(|operand| operand.strip_suffix('"'))
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| error(format!("expected a quoted file name, found '{}'", operand)))?;
+        let path = match location.file.as_ref().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        let source = fs::read_to_string(&path)
+            .map_err(|io_error| error(format!("cannot read '{}': {}", path.display(), io_error)))?;
+
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if self.include_stack.contains(&canonical) {
+            return Err(error(format!("'{}' includes itself", path.display())));
+        }
+        self.include_stack.push(canonical);
+        let result = self.parse(&source, Some(&path));
+        self.include_stack.pop();
+        result
+    }
+
+    /// Defines a label or constant
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: The name of the symbol
+    /// - `value`: The value of the symbol
+    ///
+    /// # Returns
+    ///
+    /// An error message if the name is reserved or already defined
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if parse_operand(name) != Operand::Value(name.to_string())
+            || MNEMONICS.contains(&name.to_ascii_uppercase().as_str())
+        {
+            return Err(format!("'{}' is a reserved name", name));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(format!("symbol '{}' is already defined", name));
+        }
+        Ok(())
+    }
+
+    /// Evaluates a value: numbers and symbols added or subtracted
+    ///
+    /// # Parameters
+    ///
+    /// - `expression`: The value
+    ///
+    /// # Returns
+    ///
+    /// The value, or an error message if it is invalid or uses an undefined symbol
+    fn evaluate(&self, expression: &str) -> Result<i64, String> {
+        let expression = expression.trim();
+        if expression.is_empty() {
+            return Err(String::from("missing value"));
+        }
+
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut rest = expression;
+        loop {
+            rest = rest.trim_start();
+            if let Some(stripped) = rest.strip_prefix('-') {
+                sign = -sign;
+                rest = stripped;
+                continue;
+            }
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = rest[..end].trim();
+            let value = match parse_number(term) {
+                Some(value) => value,
+                None if is_symbol(term) => *self
+                    .symbols
+                    .get(term)
+                    .ok_or_else(|| format!("undefined symbol '{}'", term))?,
+                None => return Err(format!("invalid value '{}'", expression)),
+            };
+            total = total
+                .checked_add(sign * value)
+                .ok_or_else(|| format!("value '{}' is too large", expression))?;
+
+            rest = &rest[end..];
+            match rest.chars().next() {
+                Some('+') => sign = 1,
+                Some('-') => sign = -1,
+                _ => return Ok(total),
+            }
+            rest = &rest[1..];
+        }
+    }
+
+    /// Evaluates a value that must fit in a range
+    ///
+    /// # Parameters
+    ///
+    /// - `expression`: The value
+    /// - `min`: The smallest valid value
+    /// - `max`: The largest valid value
+    /// - `what`: The name of the operand, for error messages
+    ///
+    /// # Returns
+    ///
+    /// The value, or an error message if it is invalid or out of range
+    fn evaluate_in(&self, expression: &str, min: i64, max: i64, what: &str) -> Result<u16, String> {
+        let value = self.evaluate(expression)?;
+        if value < min || value > max {
+            let expression = expression.trim();
+            let shown = match parse_number(expression) {
+                Some(_) => format!("'{}'", expression),
+                None => format!("'{}' ({})", expression, value),
+            };
+            return Err(format!(
+                "{} {} is out of range ({} to {})",
+                what, shown, min, max
+            ));
+        }
+        Ok((value & 0xFFFF) as u16)
+    }
+
+    /// Second pass: encodes the statements, every symbol being known
+    ///
+    /// # Returns
+    ///
+    /// The ROM bytes, or an `AsmError` locating the first error
+    fn encode(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for (location, statement) in &self.statements {
+            let error = |message| location.error(message);
+            match statement {
+                Statement::Instruction(mnemonic, operands) => {
+                    let instruction = self.encode_instruction(mnemonic, operands).map_err(error)?;
+                    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                    if let [_, Operand::Long(nnnn)] = operands.as_slice() {
+                        let nnnn = self
+                            .evaluate_in(nnnn, 0, 0xFFFF, "address")
+                            .map_err(error)?;
+                        rom.extend_from_slice(&nnnn.to_be_bytes());
+                    }
+                }
+                Statement::Bytes(values) => {
+                    for value in values {
+                        let byte = self
+                            .evaluate_in(value, -0x80, 0xFF, "byte")
+                            .map_err(error)?;
+                        rom.push(byte as u8);
+                    }
+                }
+                Statement::Words(values) => {
+                    for value in values {
+                        let word = self
+                            .evaluate_in(value, -0x8000, 0xFFFF, "word")
+                            .map_err(error)?;
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    /// Encodes an instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `mnemonic`: The upper case mnemonic
+    /// - `operands`: The operands
+    ///
+    /// # Returns
+    ///
+    /// The instruction, or an error message if the mnemonic is unknown or the operands invalid
+    fn encode_instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+    ) -> Result<Instruction, String> {
+        use Operand::{IndirectI, Keyword, Long, Range, Register, Value};
+
+        let address = |value: &str| self.evaluate_in(value, 0, 0xFFF, "address");
+        let byte = |value: &str| {
+            self.evaluate_in(value, -0x80, 0xFF, "byte")
+                .map(|kk| kk as u8)
+        };
+        let nibble = |value: &str| self.evaluate_in(value, 0, 0xF, "nibble").map(|n| n as u8);
+
+        let instruction = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Value(n)]) => Instruction::Scd(nibble(n)?),
+            ("SCU", [Value(n)]) => Instruction::Scu(nibble(n)?),
+            ("SCR", []) => Instruction::Scr,
+            ("SCL", []) => Instruction::Scl,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("JP", [Value(nnn)]) => Instruction::Jp(address(nnn)?),
+            ("JP", [Register(0), Value(nnn)]) => Instruction::JpV0(address(nnn)?),
+            ("CALL", [Value(nnn)]) => Instruction::Call(address(nnn)?),
+            ("SE", [Register(x), Value(kk)]) => Instruction::SeByte(*x, byte(kk)?),
+            ("SE", [Register(x), Register(y)]) => Instruction::SeReg(*x, *y),
+            ("SNE", [Register(x), Value(kk)]) => Instruction::SneByte(*x, byte(kk)?),
+            ("SNE", [Register(x), Register(y)]) => Instruction::SneReg(*x, *y),
+            ("LD", [Register(x), Value(kk)]) => Instruction::LdByte(*x, byte(kk)?),
+            ("LD", [Register(x), Register(y)]) => Instruction::LdReg(*x, *y),
+            ("LD", [Keyword("I"), Value(nnn)]) => Instruction::LdI(address(nnn)?),
+            ("LD", [Keyword("I"), Long(_)]) => Instruction::LdILong,
+            ("LD", [Register(x), Keyword("DT")]) => Instruction::LdVxDt(*x),
+            ("LD", [Register(x), Keyword("K")]) => Instruction::LdVxK(*x),
+            ("LD", [Keyword("DT"), Register(x)]) => Instruction::LdDtVx(*x),
+            ("LD", [Keyword("ST"), Register(x)]) => Instruction::LdStVx(*x),
+            ("LD", [Keyword("F"), Register(x)]) => Instruction::LdF(*x),
+            ("LD", [Keyword("HF"), Register(x)]) => Instruction::LdHf(*x),
+            ("LD", [Keyword("B"), Register(x)]) => Instruction::LdB(*x),
+            ("LD", [IndirectI, Register(x)]) => Instruction::Store(*x),
+            ("LD", [Register(x), IndirectI]) => Instruction::Load(*x),
+            ("LD", [IndirectI, Range(x, y)]) => Instruction::StoreRange(*x, *y),
+            ("LD", [Range(x, y), IndirectI]) => Instruction::LoadRange(*x, *y),
+            ("LD", [Keyword("R"), Register(x)]) => Instruction::StoreFlags(*x),
+            ("LD", [Register(x), Keyword("R")]) => Instruction::LoadFlags(*x),
+            ("ADD", [Register(x), Value(kk)]) => Instruction::AddByte(*x, byte(kk)?),
+            ("ADD", [Register(x), Register(y)]) => Instruction::AddReg(*x, *y),
+            ("ADD", [Keyword("I"), Register(x)]) => Instruction::AddI(*x),
+            ("OR", [Register(x), Register(y)]) => Instruction::Or(*x, *y),
+            ("AND", [Register(x), Register(y)]) => Instruction::And(*x, *y),
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub(*x, *y),
+            ("SUBN", [Register(x), Register(y)]) => Instruction::Subn(*x, *y),
+            ("SHR", [Register(x), Register(y)]) => Instruction::Shr(*x, *y),
+            ("SHR", [Register(x)]) => Instruction::Shr(*x, *x),
+            ("SHL", [Register(x), Register(y)]) => Instruction::Shl(*x, *y),
+            ("SHL", [Register(x)]) => Instruction::Shl(*x, *x),
+            ("RND", [Register(x), Value(kk)]) => Instruction::Rnd(*x, byte(kk)?),
+            ("DRW", [Register(x), Register(y), Value(n)]) => Instruction::Drw(*x, *y, nibble(n)?),
+            ("SKP", [Register(x)]) => Instruction::Skp(*x),
+            ("SKNP", [Register(x)]) => Instruction::Sknp(*x),
+            ("PLANE", [Value(n)]) => Instruction::Plane(nibble(n)?),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [Register(x)]) => Instruction::Pitch(*x),
+            _ if MNEMONICS.contains(&mnemonic) => {
+                return Err(format!("invalid operands for '{}'", mnemonic))
+            }
+            _ => return Err(format!("unknown instruction '{}'", mnemonic)),
+        };
+        Ok(instruction)
+    }
+}
+
+/// Removes the comment of a line, a `;` in a quoted file name being kept
+///
+/// # Parameters
+///
+/// - `line`: The line
+///
+/// # Returns
+///
+/// The line without its comment
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits comma separated operands
+///
+/// # Parameters
+///
+/// - `operands`: The operands
+///
+/// # Returns
+///
+/// The trimmed operands, or an error message if one of them is empty
+fn split_operands(operands: &str) -> Result<Vec<String>, String> {
+    operands
+        .split(',')
+        .map(|operand| match operand.trim() {
+            "" => Err(String::from("missing operand")),
+            operand => Ok(operand.to_string()),
+        })
+        .collect()
+}
+
+/// Parses an operand
+///
+/// # Parameters
+///
+/// - `operand`: The trimmed operand
+///
+/// # Returns
+///
+/// The operand, anything else than a register or keyword being a value
+fn parse_operand(operand: &str) -> Operand {
+    let upper = operand.to_ascii_uppercase();
+    if let Some(x) = parse_register(&upper) {
+        return Operand::Register(x);
+    }
+    if let Some((x, y)) = upper.split_once('-') {
+        if let (Some(x), Some(y)) = (parse_register(x.trim()), parse_register(y.trim())) {
+            return Operand::Range(x, y);
+        }
+    }
+    if upper.replace(' ', "") == "[I]" {
+        return Operand::IndirectI;
+    }
+    if let Some(keyword) = KEYWORDS.iter().find(|&&keyword| keyword == upper) {
+        return Operand::Keyword(keyword);
+    }
+    if upper.starts_with("LONG") && upper[4..].starts_with(char::is_whitespace) {
+        return Operand::Long(operand[4..].trim().to_string());
+    }
+    Operand::Value(operand.to_string())
+}
+
+/// Parses a register name: V0 to VF
+///
+/// # Parameters
+///
+/// - `name`: The upper case name
+///
+/// # Returns
+///
+/// The register number, or None if the name is not a register
+fn parse_register(name: &str) -> Option<u8> {
+    let digit = name.strip_prefix('V')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Parses a number: `#FF` or `0xFF` hexadecimal, `%1010` or `0b1010` binary, else decimal
+///
+/// # Parameters
+///
+/// - `text`: The number
+///
+/// # Returns
+///
+/// The number, or None if the text is not a number
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(digits) = lower.strip_prefix('#') {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix('%') {
+        (digits, 2)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        (digits, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Checks whether a text is a valid symbol name: a letter or `_` followed by letters, digits,
+/// `_` or `.`
+///
+/// # Parameters
+///
+/// - `name`: The text
+///
+/// # Returns
+///
+/// true if the text is a valid symbol name
+fn is_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction;
+
+    /// Assembles a source expected to fail and returns the line and message of the error
+    fn assemble_error(source: &str) -> (usize, String) {
+        let error = assemble(source, None).unwrap_err();
+        assert_eq!(error.file, None);
+        (error.line, error.message)
+    }
+
+    #[test]
+    fn program_with_labels_and_constants_assembles() {
+        let source = "\
+            SPEED = 3\n\
+            start:\n\
+                LD V0, SPEED + 1 ; comment\n\
+                LD I, LONG sprite\n\
+                JP start\n\
+            sprite: DB #80, %01000000, -1\n\
+                DW 0x1234\n";
+
+        assert_eq!(
+            assemble(source, None).unwrap(),
+            [0x60, 0x04, 0xF0, 0x00, 0x02, 0x08, 0x12, 0x00, 0x80, 0x40, 0xFF, 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    fn unknown_mnemonic_is_located() {
+        assert_eq!(
+            assemble_error("CLS\n\n    FOO V0\n"),
+            (3, String::from("unknown instruction 'FOO'"))
+        );
+    }
+
+    #[test]
+    fn invalid_operands_are_located() {
+        assert_eq!(
+            assemble_error("CLS\nLD K, V0\n"),
+            (2, String::from("invalid operands for 'LD'"))
+        );
+    }
+
+    #[test]
+    fn undefined_label_is_located() {
+        assert_eq!(
+            assemble_error("start:\n    CLS\n    JP strat\n    JP start\n"),
+            (3, String::from("undefined symbol 'strat'"))
+        );
+    }
+
+    #[test]
+    fn duplicate_label_is_located() {
+        assert_eq!(
+            assemble_error("start: CLS\nstart: RET\n"),
+            (2, String::from("symbol 'start' is already defined"))
+        );
+    }
+
+    #[test]
+    fn reserved_name_is_rejected() {
+        assert_eq!(
+            assemble_error("DT = 3\n"),
+            (1, String::from("'DT' is a reserved name"))
+        );
+    }
+
+    #[test]
+    fn out_of_range_immediates_are_located() {
+        assert_eq!(
+            assemble_error("CLS\nLD V0, 256\n"),
+            (2, String::from("byte '256' is out of range (-128 to 255)"))
+        );
+        assert_eq!(
+            assemble_error("JP #1000\n"),
+            (
+                1,
+                String::from("address '#1000' is out of range (0 to 4095)")
+            )
+        );
+        assert_eq!(
+            assemble_error("TOP = 15\nCLS\nDRW V0, V1, TOP + 1\n"),
+            (
+                3,
+                String::from("nibble 'TOP + 1' (16) is out of range (0 to 15)")
+            )
+        );
+        assert_eq!(
+            assemble_error("DW 65536\n"),
+            (
+                1,
+                String::from("word '65536' is out of range (-32768 to 65535)")
+            )
+        );
+    }
+
+    #[test]
+    fn error_is_displayed_with_its_line() {
+        let error = assemble("CLS\nFOO\n", None).unwrap_err();
+        assert_eq!(error.to_string(), "line 2: unknown instruction 'FOO'");
+
+        let error = assemble("CLS\nFOO\n", Some(Path::new("game.asm"))).unwrap_err();
+        assert_eq!(error.to_string(), "game.asm:2: unknown instruction 'FOO'");
+    }
+
+    #[test]
+    fn every_instruction_assembles_from_its_mnemonic() {
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = instruction::decode(opcode) {
+                let source = match instruction {
+                    Instruction::LdILong => String::from("LD I, LONG #1234"),
+                    _ => instruction.to_string(),
+                };
+                let rom = assemble(&source, None).unwrap();
+                assert_eq!(rom[..2], instruction.encode().to_be_bytes(), "{}", source);
+            }
+        }
+    }
+}
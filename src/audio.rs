@@ -0,0 +1,353 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Number of audio samples per second
+pub const SAMPLE_RATE: u32 = 44100;
+/// Number of audio samples rendered per frame (60 frames per second)
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+/// Amplitude of the tone, in [0.0, 1.0]
+const VOLUME: f32 = 0.25;
+
+/// Destination of the sound produced by the Chip8, such as an audio device or a file
+pub trait AudioSink {
+    /// Writes mono samples, in [-1.0, 1.0], played at `SAMPLE_RATE` samples per second
+    ///
+    /// # Parameters
+    ///
+    /// - `samples`: The samples to play
+    fn write_samples(&mut self, samples: &[f32]);
+
+    /// Flushes the samples written so far, once the emulation is over
+    ///
+    /// # Returns
+    ///
+    /// The first error that occurred while writing samples, if any
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Tone generator, playing the audio pattern buffer while the sound timer is active.
+///
+/// The 128 1-bit samples of the pattern are played in a loop at 4000 * 2 ^ ((pitch - 64) / 48)
+/// samples per second. The default pattern (alternating groups of 4 ones and 4 zeros, played
+/// at the default pitch) produces a 500Hz square wave.
+pub struct Audio {
+    /// Destination of the generated samples
+    sink: Option<Box<dyn AudioSink>>,
+    /// Position in the audio pattern, in pattern samples
+    position: f64,
+    /// Samples of the current frame
+    samples: Vec<f32>,
+}
+
+impl Default for Audio {
+    fn default() -> Audio {
+        Audio::new()
+    }
+}
+
+impl Audio {
+    /// Creates and returns a new `Audio` struct, without sink.
+    ///
+    /// # Returns
+    ///
+    /// A new `Audio` struct.
+    pub fn new() -> Audio {
+        Audio {
+            sink: None,
+            position: 0.0,
+            samples: Vec::with_capacity(SAMPLES_PER_FRAME),
+        }
+    }
+
+    /// Sets the destination of the generated samples
+    ///
+    /// # Parameters
+    ///
+    /// - `sink`: The audio sink, or None to stop generating samples
+    pub fn set_sink(&mut self, sink: Option<Box<dyn AudioSink>>) {
+        self.sink = sink;
+    }
+
+    /// Finishes the audio sink, if any
+    ///
+    /// # Returns
+    ///
+    /// The first error that occurred in the audio sink, if any
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.sink.as_mut() {
+            Some(sink) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Renders the samples of one frame and writes them to the audio sink
+    ///
+    /// # Parameters
+    ///
+    /// - `active`: Is the tone playing (sound timer > 0)?
+    /// - `pattern`: The audio pattern buffer, 128 1-bit samples
+    /// - `pitch`: The audio pattern playback pitch
+    pub fn render_frame(&mut self, active: bool, pattern: &[u8; 16], pitch: u8) {
+        let sink = match self.sink.as_mut() {
+            Some(sink) => sink,
+            None => return,
+        };
+
+        self.samples.clear();
+        if active {
+            let rate = 4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0);
+            let step = rate / SAMPLE_RATE as f64;
+            for _ in 0..SAMPLES_PER_FRAME {
+                let bit = self.position as usize % 128;
+                let on = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                self.samples.push(if on { VOLUME } else { -VOLUME });
+                self.position = (self.position + step) % 128.0;
+            }
+        } else {
+            self.samples.resize(SAMPLES_PER_FRAME, 0.0);
+            self.position = 0.0;
+        }
+        sink.write_samples(&self.samples);
+    }
+}
+
+/// Audio sink writing the samples to a 16 bits mono PCM WAV file
+pub struct WavSink<W: Write + Seek> {
+    /// Destination of the WAV file
+    writer: W,
+    /// Number of samples written so far
+    sample_count: u32,
+    /// First error that occurred while writing samples
+    error: Option<io::Error>,
+}
+
+impl WavSink<BufWriter<File>> {
+    /// Creates a WAV file and returns an audio sink writing to it
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: The path of the WAV file
+    ///
+    /// # Returns
+    ///
+    /// A new `WavSink` struct, or the error that prevented the file creation
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<WavSink<BufWriter<File>>> {
+        WavSink::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    /// Creates and returns an audio sink writing a WAV file to the given writer
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The destination of the WAV file
+    ///
+    /// # Returns
+    ///
+    /// A new `WavSink` struct, or the error that occurred while writing the WAV header
+    pub fn new(writer: W) -> io::Result<WavSink<W>> {
+        let mut sink = WavSink {
+            writer,
+            sample_count: 0,
+            error: None,
+        };
+        sink.write_header()?;
+        Ok(sink)
+    }
+
+    /// Writes the WAV header, with the sizes matching the samples written so far
+    ///
+    /// # Returns
+    ///
+    /// The error that occurred while writing the header, if any
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_size = self.sample_count * 2;
+        let w = &mut self.writer;
+        w.seek(SeekFrom::Start(0))?;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(36 + data_size).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        // Format chunk: PCM, mono, 16 bits
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        w.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        w.write_all(&2u16.to_le_bytes())?;
+        w.write_all(&16u16.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_size.to_le_bytes())?;
+        w.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn write_samples(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            if let Err(error) = self.writer.write_all(&value.to_le_bytes()) {
+                self.error = Some(error);
+                return;
+            }
+            self.sample_count += 1;
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        // Best effort: errors are reported by `finish`
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{DEFAULT_AUDIO_PATTERN, DEFAULT_PITCH};
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    /// In-memory WAV file, shared between a test and the `WavSink` writing to it
+    #[derive(Clone, Default)]
+    struct SharedCursor(Rc<RefCell<Cursor<Vec<u8>>>>);
+
+    impl Write for SharedCursor {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.borrow_mut().flush()
+        }
+    }
+
+    impl Seek for SharedCursor {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.borrow_mut().seek(pos)
+        }
+    }
+
+    impl SharedCursor {
+        /// Returns a copy of the bytes written so far
+        fn bytes(&self) -> Vec<u8> {
+            self.0.borrow().get_ref().clone()
+        }
+    }
+
+    /// Renders the given frames (tone active, pattern, pitch) into a WAV file
+    fn render(frames: &[(bool, [u8; 16], u8)]) -> Vec<u8> {
+        let cursor = SharedCursor::default();
+        let mut audio = Audio::new();
+        audio.set_sink(Some(Box::new(WavSink::new(cursor.clone()).unwrap())));
+        for (active, pattern, pitch) in frames {
+            audio.render_frame(*active, pattern, *pitch);
+        }
+        audio.finish().unwrap();
+        cursor.bytes()
+    }
+
+    /// Reads a little endian u32 at the given offset of a WAV file
+    fn read_u32(wav: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            wav[offset],
+            wav[offset + 1],
+            wav[offset + 2],
+            wav[offset + 3],
+        ])
+    }
+
+    /// Returns the samples of a WAV file, after its 44 bytes header
+    fn read_samples(wav: &[u8]) -> Vec<i16> {
+        wav[44..]
+            .chunks(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    /// Returns the number of samples before the first change of sign
+    fn leading_run(samples: &[i16]) -> usize {
+        samples
+            .iter()
+            .take_while(|&&sample| sample.signum() == samples[0].signum())
+            .count()
+    }
+
+    #[test]
+    fn header_sizes_are_patched_on_finish() {
+        let cursor = SharedCursor::default();
+        let mut sink = WavSink::new(cursor.clone()).unwrap();
+        let header = cursor.bytes();
+        assert_eq!(header.len(), 44);
+        assert_eq!(read_u32(&header, 4), 36);
+        assert_eq!(read_u32(&header, 40), 0);
+
+        sink.write_samples(&[0.0; 3 * SAMPLES_PER_FRAME]);
+        sink.finish().unwrap();
+        let wav = cursor.bytes();
+        let data_size = 3 * SAMPLES_PER_FRAME as u32 * 2;
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(read_u32(&wav, 4), 36 + data_size);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(read_u32(&wav, 24), SAMPLE_RATE);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(read_u32(&wav, 40), data_size);
+        assert_eq!(wav.len(), 44 + data_size as usize);
+    }
+
+    #[test]
+    fn frames_without_sound_timer_are_silent() {
+        let wav = render(&[(false, DEFAULT_AUDIO_PATTERN, DEFAULT_PITCH); 2]);
+        let samples = read_samples(&wav);
+        assert_eq!(samples.len(), 2 * SAMPLES_PER_FRAME);
+        assert!(samples.iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn sound_timer_plays_the_default_500hz_tone() {
+        let wav = render(&[(true, DEFAULT_AUDIO_PATTERN, DEFAULT_PITCH)]);
+        let samples = read_samples(&wav);
+        let amplitude = (VOLUME * i16::MAX as f32) as i16;
+        assert_eq!(samples.len(), SAMPLES_PER_FRAME);
+        assert!(samples
+            .iter()
+            .all(|&sample| sample == amplitude || sample == -amplitude));
+        // 4 ones at 4000 samples per second last 44.1 output samples
+        assert_eq!(samples[0], amplitude);
+        assert_eq!(leading_run(&samples), 45);
+        assert_eq!(samples[45], -amplitude);
+    }
+
+    #[test]
+    fn sound_timer_plays_the_xo_chip_pattern_at_its_pitch() {
+        let mut pattern = [0; 16];
+        pattern[0] = 0xFF;
+        // Pitch 16: 2000 samples per second, so 8 ones last 176.4 output samples
+        let wav = render(&[(true, pattern, 16)]);
+        let samples = read_samples(&wav);
+        assert_eq!(leading_run(&samples), 177);
+        assert!(samples[177..].iter().all(|&sample| sample < 0));
+
+        let wav = render(&[(false, pattern, 16), (true, pattern, 16)]);
+        let samples = read_samples(&wav);
+        assert!(samples[..SAMPLES_PER_FRAME].iter().all(|&s| s == 0));
+        assert_eq!(leading_run(&samples[SAMPLES_PER_FRAME..]), 177);
+    }
+}
@@ -1,8 +1,40 @@
 use crate::cpu;
-use crate::display;
 use crate::display::Display;
-use crate::keyboard::Keyboard;
-use crate::memory::Memory;
+use crate::keyboard::{self, Keyboard};
+use crate::memory::{self, AddressError, Memory};
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::watch::{Access, WatchHit, WatchList};
+use std::error;
+use std::fmt;
+
+/// Default audio pattern pitch, which plays the pattern at 4000 samples per second (XO-CHIP)
+pub const DEFAULT_PITCH: u8 = 64;
+/// Default audio pattern, a square wave which plays a 500Hz tone at the default pitch
+pub const DEFAULT_AUDIO_PATTERN: [u8; 16] = [0xF0; 16];
+
+/// Error returned when a ROM is too large to fit in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomTooLarge {
+    /// Size of the ROM, in bytes
+    pub size: usize,
+    /// Maximum size of a ROM, in bytes
+    pub max_size: usize,
+}
+
+impl fmt::Display for RomTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ROM is {} bytes long but only {} bytes fit in memory ({:#05X}-{:#05X})",
+            self.size,
+            self.max_size,
+            cpu::PROGRAM_START,
+            cpu::PROGRAM_START as usize + self.max_size - 1
+        )
+    }
+}
+
+impl error::Error for RomTooLarge {}
 
 /// Chip8 communication Bus struct
 pub struct Bus {
@@ -16,6 +48,18 @@ pub struct Bus {
     dt: u8,
     /// Sound timer
     st: u8,
+    /// Audio pattern buffer, 128 1-bit samples (XO-CHIP)
+    audio_pattern: [u8; 16],
+    /// Audio pattern playback pitch (XO-CHIP)
+    pitch: u8,
+    /// Watchpoints checked on each memory access, if any
+    watch: Option<WatchList>,
+}
+
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus::new()
+    }
 }
 
 impl Bus {
@@ -25,12 +69,28 @@ impl Bus {
     ///
     /// A new `Bus` struct.
     pub fn new() -> Bus {
+        Bus::with_memory_size(memory::MEMORY_SIZE)
+    }
+
+    /// Creates and returns a new `Bus` struct with a memory of the given size.
+    ///
+    /// # Parameters
+    ///
+    /// - `memory_size`: The memory size, in bytes
+    ///
+    /// # Returns
+    ///
+    /// A new `Bus` struct.
+    pub fn with_memory_size(memory_size: usize) -> Bus {
         Bus {
-            mem: Memory::new(),
+            mem: Memory::with_size(memory_size),
             display: Display::new(),
             keyboard: Keyboard::new(),
             dt: 0,
             st: 0,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            pitch: DEFAULT_PITCH,
+            watch: None,
         }
     }
 
@@ -39,11 +99,24 @@ impl Bus {
     /// # Parameters
     ///
     /// - `buffer`: The bytes of the ROM to load in memory
-    pub fn load_rom(&mut self, buffer: &Vec<u8>) {
-        for i in 0..buffer.len() {
-            self.mem
-                .write_byte(cpu::PROGRAM_START + (i as u16), buffer[i]);
+    ///
+    /// # Returns
+    ///
+    /// A `RomTooLarge` error if the ROM does not fit in memory
+    pub fn load_rom(&mut self, buffer: &[u8]) -> Result<(), RomTooLarge> {
+        let max_size = self.mem.size() - cpu::PROGRAM_START as usize;
+        if buffer.len() > max_size {
+            return Err(RomTooLarge {
+                size: buffer.len(),
+                max_size,
+            });
         }
+
+        for (i, byte) in buffer.iter().enumerate() {
+            // Cannot fail: the ROM size has been checked
+            let _ = self.mem.write_byte(cpu::PROGRAM_START + (i as u16), *byte);
+        }
+        Ok(())
     }
 
     /// Writes the given byte in memory at the giver address
@@ -52,11 +125,43 @@ impl Bus {
     ///
     /// - `address`: The memory address where to write the byte
     /// - `value` : The byte to write
-    pub fn mem_write_byte(&mut self, address: u16, value: u8) {
-        self.mem.write_byte(address, value);
+    ///
+    /// # Returns
+    ///
+    /// An `AddressError` if `address` is outside of the memory
+    pub fn mem_write_byte(&mut self, address: u16, value: u8) -> Result<(), AddressError> {
+        match &mut self.watch {
+            Some(watch) => {
+                let old_value = self.mem.read_byte(address)?;
+                self.mem.write_byte(address, value)?;
+                watch.record(address, Access::Write, old_value, value);
+                Ok(())
+            }
+            None => self.mem.write_byte(address, value),
+        }
+    }
+
+    /// Reads and returns the bytes at the given address, as a data access checked by the
+    /// watchpoints
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The memory address of the byte to read
+    ///
+    /// # Returns
+    ///
+    /// The memory value at address `address`, or an `AddressError` if `address` is outside of
+    /// the memory
+    pub fn mem_read_byte(&mut self, address: u16) -> Result<u8, AddressError> {
+        let value = self.mem.read_byte(address)?;
+        if let Some(watch) = &mut self.watch {
+            watch.record(address, Access::Read, value, value);
+        }
+        Ok(value)
     }
 
-    /// Reads and returns the bytes at the given address
+    /// Reads and returns the bytes at the given address, without checking the watchpoints: for
+    /// instruction fetches and inspection
     ///
     /// # Parameters
     ///
@@ -64,10 +169,78 @@ impl Bus {
     ///
     /// # Returns
     ///
-    /// The memory value at address `address`
-    pub fn mem_read_byte(&self, address: u16) -> u8 {
+    /// The memory value at address `address`, or an `AddressError` if `address` is outside of
+    /// the memory
+    pub fn mem_peek_byte(&self, address: u16) -> Result<u8, AddressError> {
         self.mem.read_byte(address)
     }
+
+    /// Writes the given byte in memory at the given address, without checking the watchpoints:
+    /// for inspection tools
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The memory address where to write the byte
+    /// - `value` : The byte to write
+    ///
+    /// # Returns
+    ///
+    /// An `AddressError` if `address` is outside of the memory
+    pub fn mem_poke_byte(&mut self, address: u16, value: u8) -> Result<(), AddressError> {
+        self.mem.write_byte(address, value)
+    }
+
+    /// Replaces the watchpoints checked on each memory access
+    ///
+    /// # Parameters
+    ///
+    /// - `watch`: The watchpoints, None to stop checking accesses
+    pub fn set_watch_list(&mut self, watch: Option<WatchList>) {
+        self.watch = watch;
+    }
+
+    /// Takes the watch list out of the bus, to move it to another one
+    ///
+    /// # Returns
+    ///
+    /// The watch list, if any
+    pub fn take_watch_list(&mut self) -> Option<WatchList> {
+        self.watch.take()
+    }
+
+    /// Gets the watchpoints checked on each memory access
+    ///
+    /// # Returns
+    ///
+    /// The watch list, if any
+    pub fn get_watch_list(&self) -> Option<&WatchList> {
+        self.watch.as_ref()
+    }
+
+    /// Sets the instruction to which the next watched accesses are attributed
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the instruction
+    /// - `opcode`: The opcode of the instruction
+    pub fn set_watch_instruction(&mut self, pc: u16, opcode: u16) {
+        if let Some(watch) = &mut self.watch {
+            watch.set_instruction(pc, opcode);
+        }
+    }
+
+    /// Takes the watched accesses recorded since the last call
+    ///
+    /// # Returns
+    ///
+    /// The hits, oldest first
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        match &mut self.watch {
+            Some(watch) => watch.take_hits(),
+            None => Vec::new(),
+        }
+    }
+
     /// Updates the frame buffer to display the given byte (which is part of a sprite)
     ///
     /// # Parameters
@@ -75,44 +248,160 @@ impl Bus {
     /// - `x`: The x coordinate of the sprite part
     /// - `y`: The y coordinate of the sprite part
     /// - `value`: The value sprite part
+    /// - `clip`: Are the pixels outside of the display clipped, instead of wrapping around?
+    /// - `plane`: The plane to draw in (0x1 or 0x2)
     ///
     /// # Returns
     ///
     /// `true` if the operation erased any pixel
-    pub fn draw_byte(&mut self, x: usize, y: usize, value: u8) -> bool {
-        self.display.draw_byte(x, y, value)
+    pub fn draw_byte(&mut self, x: usize, y: usize, value: u8, clip: bool, plane: u8) -> bool {
+        self.display.draw_byte(x, y, value, clip, plane)
+    }
+
+    /// Gets the display planes affected by drawing, clearing and scrolling (XO-CHIP)
+    ///
+    /// # Returns
+    ///
+    /// The selected planes, as a bit mask: 0x1 for the first plane, 0x2 for the second one
+    pub fn get_selected_planes(&self) -> u8 {
+        self.display.selected_planes()
+    }
+
+    /// Selects the display planes affected by drawing, clearing and scrolling (XO-CHIP)
+    ///
+    /// # Parameters
+    ///
+    /// - `planes`: The planes to select, as a bit mask (0x0 to 0x3)
+    pub fn select_planes(&mut self, planes: u8) {
+        self.display.select_planes(planes);
     }
 
     /// Gets the display buffer
     ///
     /// # Returns
     ///
-    /// The display buffer
-    pub fn get_display_buffer(&mut self) -> [u32; display::WIDTH * display::HEIGHT] {
+    /// The display buffer, `get_display_width() * get_display_height()` pixels long
+    pub fn get_display_buffer(&self) -> &[u32] {
         self.display.get_display_buffer()
     }
 
-    /// Clears the display
+    /// Checks whether the display is in the high resolution mode (SUPER-CHIP)
+    ///
+    /// # Returns
+    ///
+    /// `true` for the 128x64 mode, `false` for the 64x32 mode
+    pub fn is_display_hires(&self) -> bool {
+        self.display.is_hires()
+    }
+
+    /// Gets the display width in the current resolution mode
+    ///
+    /// # Returns
+    ///
+    /// The display width, in pixels
+    pub fn get_display_width(&self) -> usize {
+        self.display.width()
+    }
+
+    /// Gets the display height in the current resolution mode
+    ///
+    /// # Returns
+    ///
+    /// The display height, in pixels
+    pub fn get_display_height(&self) -> usize {
+        self.display.height()
+    }
+
+    /// Clears the selected planes of the display
     pub fn clear_display(&mut self) {
         self.display.clear();
     }
 
-    /// Sets keyboard pressed key
+    /// Switches the display between low and high resolution modes, which clears it
+    ///
+    /// # Parameters
+    ///
+    /// - `hires`: `true` for the 128x64 mode, `false` for the 64x32 mode
+    pub fn set_display_hires(&mut self, hires: bool) {
+        self.display.set_hires(hires);
+    }
+
+    /// Scrolls the display content up
+    ///
+    /// # Parameters
+    ///
+    /// - `n`: The number of rows to scroll
+    pub fn scroll_display_up(&mut self, n: usize) {
+        self.display.scroll_up(n);
+    }
+
+    /// Scrolls the display content down
+    ///
+    /// # Parameters
+    ///
+    /// - `n`: The number of rows to scroll
+    pub fn scroll_display_down(&mut self, n: usize) {
+        self.display.scroll_down(n);
+    }
+
+    /// Scrolls the display content right
+    ///
+    /// # Parameters
+    ///
+    /// - `n`: The number of columns to scroll
+    pub fn scroll_display_right(&mut self, n: usize) {
+        self.display.scroll_right(n);
+    }
+
+    /// Scrolls the display content left
     ///
     /// # Parameters
     ///
-    /// - `key`: The value of the pressed key (an u8 or None)
-    pub fn set_pressed_key(&mut self, key: Option<u8>) {
-        self.keyboard.set_pressed_key(key)
+    /// - `n`: The number of columns to scroll
+    pub fn scroll_display_left(&mut self, n: usize) {
+        self.display.scroll_left(n);
     }
 
-    /// Gets keyboard pressed key
+    /// Sets the state of a keyboard key
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: The value of the key (0x0 to 0xF)
+    /// - `pressed`: `true` if the key is down, `false` if it is up
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keyboard.set_key(key, pressed);
+    }
+
+    /// Gets the state of every keyboard key
+    ///
+    /// # Returns
+    ///
+    /// The state of each key, `true` when the key is down
+    pub fn get_keys(&self) -> [bool; keyboard::KEY_COUNT] {
+        self.keyboard.get_keys()
+    }
+
+    /// Takes the press edge of the lowest key pressed since its last press was taken
+    ///
+    /// # Returns
+    ///
+    /// The value of the pressed key, or None if no key has been pressed
+    pub fn take_pressed_key(&mut self) -> Option<u8> {
+        self.keyboard.take_pressed_key()
+    }
+
+    /// Takes the release edge of the lowest key released since its last release was taken
     ///
     /// # Returns
     ///
-    /// The value of the pressed key (an u8 or None)
-    pub fn get_pressed_key(&self) -> Option<u8> {
-        self.keyboard.get_pressed_key()
+    /// The value of the released key, or None if no key has been released
+    pub fn take_released_key(&mut self) -> Option<u8> {
+        self.keyboard.take_released_key()
+    }
+
+    /// Forgets every keyboard press and release edge not taken yet
+    pub fn clear_key_edges(&mut self) {
+        self.keyboard.clear_edges();
     }
 
     /// Is the given key currently pressed?
@@ -164,6 +453,78 @@ impl Bus {
         self.st
     }
 
+    /// Sets the audio pattern buffer (XO-CHIP)
+    ///
+    /// # Parameters
+    ///
+    /// - `pattern`: 128 1-bit samples, most significant bit first
+    pub fn set_audio_pattern(&mut self, pattern: [u8; 16]) {
+        self.audio_pattern = pattern;
+    }
+
+    /// Gets the audio pattern buffer (XO-CHIP)
+    ///
+    /// # Returns
+    ///
+    /// 128 1-bit samples, most significant bit first
+    pub fn get_audio_pattern(&self) -> [u8; 16] {
+        self.audio_pattern
+    }
+
+    /// Sets the audio pattern playback pitch (XO-CHIP)
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The pitch, the playback rate being 4000 * 2 ^ ((pitch - 64) / 48) Hz
+    pub fn set_pitch(&mut self, value: u8) {
+        self.pitch = value;
+    }
+
+    /// Gets the audio pattern playback pitch (XO-CHIP)
+    ///
+    /// # Returns
+    ///
+    /// The pitch, the playback rate being 4000 * 2 ^ ((pitch - 64) / 48) Hz
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Writes the memory, display, keyboard, timers and audio settings in a save state
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The save state writer
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.mem.save_state(writer);
+        self.display.save_state(writer);
+        self.keyboard.save_state(writer);
+        writer.write_u8(self.dt);
+        writer.write_u8(self.st);
+        writer.write_bytes(&self.audio_pattern);
+        writer.write_u8(self.pitch);
+    }
+
+    /// Restores the memory, display, keyboard, timers and audio settings from a save state
+    /// written by `save_state`
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The save state reader
+    ///
+    /// # Returns
+    ///
+    /// A `StateError` if the save state is invalid, in which case the bus is partially restored
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.mem.load_state(reader)?;
+        self.display.load_state(reader)?;
+        self.keyboard.load_state(reader)?;
+        self.dt = reader.read_u8()?;
+        self.st = reader.read_u8()?;
+        self.audio_pattern.copy_from_slice(reader.read_bytes(16)?);
+        self.pitch = reader.read_u8()?;
+        Ok(())
+    }
+
     /// Decrements delay timer
     pub fn dec_dt(&mut self) {
         if self.dt > 0 {
@@ -178,3 +539,100 @@ impl Bus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watch::{WatchKind, Watchpoint};
+
+    /// Creates a bus watching the given range of addresses
+    fn watched_bus(start: u16, end: u16, kind: WatchKind) -> Bus {
+        let mut bus = Bus::new();
+        bus.set_watch_list(Some(WatchList::new(vec![Watchpoint { start, end, kind }])));
+        bus.set_watch_instruction(0x200, 0xF155);
+        bus
+    }
+
+    #[test]
+    fn read_watchpoint_reports_data_reads_only() {
+        let mut bus = watched_bus(0x300, 0x301, WatchKind::Read);
+        bus.mem_poke_byte(0x301, 0x42).unwrap();
+
+        bus.mem_write_byte(0x300, 0x01).unwrap();
+        bus.mem_peek_byte(0x300).unwrap();
+        bus.mem_read_byte(0x302).unwrap();
+        assert_eq!(bus.take_watch_hits(), []);
+
+        assert_eq!(bus.mem_read_byte(0x301), Ok(0x42));
+        assert_eq!(
+            bus.take_watch_hits(),
+            [WatchHit {
+                pc: 0x200,
+                opcode: 0xF155,
+                address: 0x301,
+                access: Access::Read,
+                old_value: 0x42,
+                new_value: 0x42,
+            }]
+        );
+        assert_eq!(bus.take_watch_hits(), []);
+    }
+
+    #[test]
+    fn write_watchpoint_reports_writes_only() {
+        let mut bus = watched_bus(0x300, 0x300, WatchKind::Write);
+        bus.mem_poke_byte(0x300, 0x07).unwrap();
+
+        bus.mem_read_byte(0x300).unwrap();
+        bus.mem_write_byte(0x2FF, 0x01).unwrap();
+        assert_eq!(bus.take_watch_hits(), []);
+
+        bus.mem_write_byte(0x300, 0x08).unwrap();
+        assert_eq!(
+            bus.take_watch_hits(),
+            [WatchHit {
+                pc: 0x200,
+                opcode: 0xF155,
+                address: 0x300,
+                access: Access::Write,
+                old_value: 0x07,
+                new_value: 0x08,
+            }]
+        );
+    }
+
+    #[test]
+    fn write_value_watchpoint_reports_writes_of_the_value_only() {
+        let mut bus = watched_bus(0x300, 0x3FF, WatchKind::WriteValue(0xFF));
+
+        bus.mem_write_byte(0x310, 0xFE).unwrap();
+        bus.mem_read_byte(0x310).unwrap();
+        assert_eq!(bus.take_watch_hits(), []);
+
+        bus.mem_write_byte(0x310, 0xFF).unwrap();
+        bus.mem_write_byte(0x3FF, 0xFF).unwrap();
+        let hits = bus.take_watch_hits();
+        assert_eq!(hits.len(), 2);
+        assert_eq!((hits[0].address, hits[0].old_value), (0x310, 0xFE));
+        assert_eq!((hits[1].address, hits[1].old_value), (0x3FF, 0x00));
+    }
+
+    #[test]
+    fn read_write_watchpoint_reports_both_accesses() {
+        let mut bus = watched_bus(0x300, 0x300, WatchKind::ReadWrite);
+
+        bus.mem_write_byte(0x300, 0x01).unwrap();
+        bus.mem_read_byte(0x300).unwrap();
+        let accesses: Vec<Access> = bus.take_watch_hits().iter().map(|hit| hit.access).collect();
+        assert_eq!(accesses, [Access::Write, Access::Read]);
+    }
+
+    #[test]
+    fn accesses_are_not_recorded_without_watch_list() {
+        let mut bus = Bus::new();
+
+        bus.mem_write_byte(0x300, 0x01).unwrap();
+        bus.mem_read_byte(0x300).unwrap();
+        assert_eq!(bus.take_watch_hits(), []);
+    }
+}
@@ -1,15 +1,105 @@
-use crate::bus::Bus;
-use crate::cpu::CPU;
+use crate::audio::{Audio, AudioSink};
+use crate::bus::{Bus, RomTooLarge};
+use crate::cpu::{CpuError, CPU};
+#[cfg(feature = "window")]
 use crate::display;
-use minifb::{Key, Window, WindowOptions};
-use std::time::{Duration, Instant};
+#[cfg(feature = "window")]
+use crate::gdb::GdbServer;
+use crate::headless::{self, HeadlessConfig, HeadlessError};
+use crate::keyboard::KEY_COUNT;
+use crate::keymap::Keymap;
+use crate::memory::AddressError;
+#[cfg(feature = "window")]
+use crate::movie::LiveInput;
+use crate::movie::{self, Divergence, InputSource};
+use crate::png::{self, Palette};
+use crate::quirks::Quirks;
+#[cfg(feature = "window")]
+use crate::rewind::Rewind;
+use crate::rewind::DEFAULT_REWIND_BUDGET;
+use crate::rng::Rng;
+use crate::scheduler::Scheduler;
+use crate::state::{StateError, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use crate::trace::{TraceEntry, Tracer};
+use crate::watch::{WatchHit, WatchList, Watchpoint};
+#[cfg(feature = "window")]
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "window")]
+use std::thread;
+use std::time::Instant;
 
-/// Delay between two CPU cycles
-const CPU_CYLE_TIME: u64 = 1000 / 500;
-/// Delay between two display refresh (60fps)
-const DISPLAY_TIME: u64 = 1000 / 60;
-/// Delay between two pressed key checks
-const KEY_TIME: u64 = 1000 / 5;
+/// Settings of the window frontend started by `Chip8::run`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunConfig {
+    /// Number of instructions executed per second
+    pub cpu_hz: u32,
+    /// Size of a Chip8 pixel in the window, in screen pixels
+    pub scale: usize,
+    /// Start the emulation paused (Space toggles the pause)
+    pub paused: bool,
+    /// Mapping from the computer keyboard to the Chip8 keyboard
+    pub keymap: Keymap,
+    /// Path prefix of the save state slot files: F1 to F9 load the states `<prefix>.s1` to
+    /// `<prefix>.s9`, Shift + F1 to F9 save them. Slots are disabled without a prefix.
+    pub state_path: Option<PathBuf>,
+    /// Memory budget of the rewind history, in bytes (0 disables rewinding). The emulation runs
+    /// backwards while Backspace is held.
+    pub rewind_budget: usize,
+    /// Pause the emulation right after an instruction whose access a watchpoint reports, instead
+    /// of only logging the access on the standard error
+    pub pause_on_watch: bool,
+}
+
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        RunConfig {
+            cpu_hz: 500,
+            scale: 10,
+            paused: false,
+            keymap: Keymap::default(),
+            state_path: None,
+            rewind_budget: DEFAULT_REWIND_BUDGET,
+            pause_on_watch: false,
+        }
+    }
+}
+
+/// Error that stopped the window frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunError {
+    /// An instruction could not be executed
+    Cpu(CpuError),
+    /// The replayed movie diverged from its recording
+    Divergence(Divergence),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Cpu(error) => error.fmt(f),
+            RunError::Divergence(divergence) => divergence.fmt(f),
+        }
+    }
+}
+
+impl error::Error for RunError {}
+
+impl From<CpuError> for RunError {
+    fn from(error: CpuError) -> RunError {
+        RunError::Cpu(error)
+    }
+}
+
+impl From<Divergence> for RunError {
+    fn from(divergence: Divergence) -> RunError {
+        RunError::Divergence(divergence)
+    }
+}
 
 /// Chip8 Virtual Machine struct
 pub struct Chip8 {
@@ -17,6 +107,19 @@ pub struct Chip8 {
     cpu: CPU,
     /// The Chip8 communication bus
     bus: Bus,
+    /// The tone generator, driven by the sound timer
+    audio: Audio,
+    /// The execution trace, if any
+    tracer: Option<Tracer>,
+    /// The debugger server controlling the window frontend, if any
+    #[cfg(feature = "window")]
+    gdb: Option<GdbServer>,
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
 }
 
 impl Chip8 {
@@ -26,110 +129,1316 @@ impl Chip8 {
     ///
     /// A new `Chip8` struct.
     pub fn new() -> Chip8 {
+        Chip8::with_quirks(Quirks::default())
+    }
+
+    /// Creates and returns a new `Chip8` struct running ambiguous instructions with the given
+    /// quirks.
+    ///
+    /// # Parameters
+    ///
+    /// - `quirks`: The behaviour of the ambiguous instructions
+    ///
+    /// # Returns
+    ///
+    /// A new `Chip8` struct.
+    pub fn with_quirks(quirks: Quirks) -> Chip8 {
         Chip8 {
-            bus: Bus::new(),
-            cpu: CPU::new(),
+            bus: Bus::with_memory_size(quirks.memory_size),
+            cpu: CPU::with_quirks(quirks),
+            audio: Audio::new(),
+            tracer: None,
+            #[cfg(feature = "window")]
+            gdb: None,
         }
     }
 
+    /// Replaces the random number generator of the `RND` instruction, e.g. with a seeded one to
+    /// make the emulation reproducible
+    ///
+    /// # Parameters
+    ///
+    /// - `rng`: The random number generator
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.cpu.set_rng(rng);
+    }
+
     /// Load a ROM in Chip8 memory
     ///
     /// # Parameters
     ///
     /// - `buffer`: The bytes of the ROM to load in memory
-    pub fn load_rom(&mut self, buffer: &Vec<u8>) {
-        self.bus.load_rom(buffer);
+    ///
+    /// # Returns
+    ///
+    /// A `RomTooLarge` error if the ROM does not fit in memory
+    pub fn load_rom(&mut self, buffer: &[u8]) -> Result<(), RomTooLarge> {
+        self.bus.load_rom(buffer)
+    }
+
+    /// Runs a single CPU cycle : fetches / decodes / runs the instruction at PC
+    ///
+    /// # Returns
+    ///
+    /// A `CpuError` if the instruction could not be executed
+    pub fn step_instruction(&mut self) -> Result<(), CpuError> {
+        let pc = self.cpu.get_pc();
+        let traced = match &mut self.tracer {
+            Some(tracer) => {
+                let opcode = u16::from_be_bytes([
+                    self.bus.mem_peek_byte(pc).unwrap_or(0),
+                    self.bus.mem_peek_byte(pc.wrapping_add(1)).unwrap_or(0),
+                ]);
+                tracer.next_instruction(pc, opcode).then_some(opcode)
+            }
+            None => None,
+        };
+
+        self.cpu.cycle(&mut self.bus)?;
+
+        if let (Some(opcode), Some(tracer)) = (traced, &mut self.tracer) {
+            let mut registers = [0; 16];
+            for (x, register) in registers.iter_mut().enumerate() {
+                *register = self.cpu.get_register(x as u8);
+            }
+            tracer.log(&TraceEntry {
+                pc,
+                opcode,
+                registers,
+                i: self.cpu.get_i(),
+                sp: self.cpu.get_stack().len() as u8,
+                dt: self.bus.get_dt(),
+                st: self.bus.get_st(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs one frame: `cycles_per_frame` CPU cycles followed by one timers tick.
+    /// Frames are meant to be run at 60Hz, the timers frequency.
+    ///
+    /// # Parameters
+    ///
+    /// - `cycles_per_frame`: The number of instructions to run during the frame
+    ///
+    /// # Returns
+    ///
+    /// A `CpuError` if an instruction could not be executed, in which case the timers are not
+    /// updated
+    pub fn run_frame(&mut self, cycles_per_frame: u32) -> Result<(), CpuError> {
+        for _ in 0..cycles_per_frame {
+            self.step_instruction()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Runs one frame like `run_frame`, but stops right after the first instruction that makes
+    /// a watched memory access, so that the machine state is the one of that instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `cycles_per_frame`: The number of instructions to run during the frame
+    ///
+    /// # Returns
+    ///
+    /// The number of instructions left to run to complete the frame (0 once the timers have
+    /// ticked) and the watched accesses of the last instruction, or a `CpuError` if an
+    /// instruction could not be executed
+    pub fn run_frame_until_watch(
+        &mut self,
+        cycles_per_frame: u32,
+    ) -> Result<(u32, Vec<WatchHit>), CpuError> {
+        for executed in 1..=cycles_per_frame {
+            self.step_instruction()?;
+            let hits = self.take_watch_hits();
+            if !hits.is_empty() {
+                let left = cycles_per_frame - executed;
+                if left == 0 {
+                    self.tick_timers();
+                }
+                return Ok((left, hits));
+            }
+        }
+        self.tick_timers();
+        Ok((0, Vec::new()))
+    }
+
+    /// Renders one frame of sound, then decrements the delay and sound timers, which count down
+    /// at 60Hz
+    pub fn tick_timers(&mut self) {
+        self.audio.render_frame(
+            self.bus.get_st() > 0,
+            &self.bus.get_audio_pattern(),
+            self.bus.get_pitch(),
+        );
+        self.bus.dec_dt();
+        self.bus.dec_st();
+    }
+
+    /// Sets the destination of the sound: a tone is played while the sound timer is active
+    ///
+    /// # Parameters
+    ///
+    /// - `sink`: The audio sink, which receives one frame of samples per timers tick
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio.set_sink(Some(sink));
+    }
+
+    /// Sets the GDB server through which a debugger client controls the window frontend
+    ///
+    /// # Parameters
+    ///
+    /// - `gdb`: The server, or None to run without one
+    #[cfg(feature = "window")]
+    pub fn set_gdb_server(&mut self, gdb: Option<GdbServer>) {
+        self.gdb = gdb;
+    }
+
+    /// Sets the execution trace, which logs the executed instructions and the registers after
+    /// them
+    ///
+    /// # Parameters
+    ///
+    /// - `tracer`: The execution trace, or None to stop tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Flushes the execution trace, if any, once the emulation is over
+    ///
+    /// # Returns
+    ///
+    /// The first error that occurred while writing the trace, if any
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        match &mut self.tracer {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Finishes the audio sink, if any, once the emulation is over
+    ///
+    /// # Returns
+    ///
+    /// The first error that occurred in the audio sink, if any
+    pub fn finish_audio(&mut self) -> io::Result<()> {
+        self.audio.finish()
+    }
+
+    /// Captures the whole machine state: CPU registers and stack, random number generator,
+    /// memory, display, keyboard, timers and audio settings.
+    ///
+    /// The save state starts with the `STATE_MAGIC` magic number followed by the
+    /// `STATE_VERSION` format version, as a little endian 16 bits value.
+    ///
+    /// # Returns
+    ///
+    /// The save state, to be restored by `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&STATE_MAGIC);
+        writer.write_u16(STATE_VERSION);
+        self.cpu.save_state(&mut writer);
+        self.bus.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Restores the whole machine state from a save state written by `save_state`.
+    ///
+    /// A save state carries its own quirk profile and memory size: they replace the ones the
+    /// machine was created with, so that the state runs as it did when it was captured.
+    ///
+    /// # Parameters
+    ///
+    /// - `state`: The save state
+    ///
+    /// # Returns
+    ///
+    /// A `StateError` if the save state is invalid, in which case the machine is unchanged
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state);
+        if reader.read_bytes(STATE_MAGIC.len()) != Ok(&STATE_MAGIC[..]) {
+            return Err(StateError::InvalidMagic);
+        }
+        let version = reader.read_u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        // Restore into copies, so that the machine is unchanged if the state is invalid
+        let mut cpu = self.cpu.clone();
+        cpu.load_state(&mut reader)?;
+        let mut bus = Bus::new();
+        bus.load_state(&mut reader)?;
+        reader.finish()?;
+
+        // Watchpoints are a debugging setting, not part of the machine state
+        bus.set_watch_list(self.bus.take_watch_list());
+        self.cpu = cpu;
+        self.bus = bus;
+        Ok(())
+    }
+
+    /// Has the program exited with the SUPER-CHIP `EXIT` instruction?
+    ///
+    /// # Returns
+    ///
+    /// `true` if the program has exited, in which case instructions are no longer executed
+    pub fn has_exited(&self) -> bool {
+        self.cpu.has_exited()
+    }
+
+    /// Gets the program counter
+    ///
+    /// # Returns
+    ///
+    /// The address of the next instruction to run
+    pub fn get_pc(&self) -> u16 {
+        self.cpu.get_pc()
+    }
+
+    /// Sets the program counter
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the next instruction to run
+    pub fn set_pc(&mut self, pc: u16) {
+        self.cpu.set_pc(pc);
+    }
+
+    /// Gets the memory address register
+    ///
+    /// # Returns
+    ///
+    /// The value of I
+    pub fn get_i(&self) -> u16 {
+        self.cpu.get_i()
+    }
+
+    /// Sets the memory address register
+    ///
+    /// # Parameters
+    ///
+    /// - `i`: The value of I
+    pub fn set_i(&mut self, i: u16) {
+        self.cpu.set_i(i);
+    }
+
+    /// Gets the value of a register
+    ///
+    /// # Parameters
+    ///
+    /// - `x`: The register id, from 0x0 to 0xF; only the low nibble is used
+    ///
+    /// # Returns
+    ///
+    /// The value of Vx
+    pub fn get_register(&self, x: u8) -> u8 {
+        self.cpu.get_register(x)
+    }
+
+    /// Sets the value of a register
+    ///
+    /// # Parameters
+    ///
+    /// - `x`: The register id, from 0x0 to 0xF; only the low nibble is used
+    /// - `value`: The value of Vx
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.cpu.set_register(x, value);
+    }
+
+    /// Gets the return addresses pushed on the stack by `CALL`
+    ///
+    /// # Returns
+    ///
+    /// The stack, from the oldest to the latest return address: its length is the stack pointer
+    pub fn get_stack(&self) -> &[u16] {
+        self.cpu.get_stack()
+    }
+
+    /// Reads a byte of memory
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The address of the byte
+    ///
+    /// # Returns
+    ///
+    /// The byte, or an `AddressError` if the address is outside of the memory
+    pub fn read_memory(&self, address: u16) -> Result<u8, AddressError> {
+        self.bus.mem_peek_byte(address)
+    }
+
+    /// Writes a byte of memory, without checking the watchpoints
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The address of the byte
+    /// - `value`: The byte to write
+    ///
+    /// # Returns
+    ///
+    /// An `AddressError` if the address is outside of the memory
+    pub fn write_memory(&mut self, address: u16, value: u8) -> Result<(), AddressError> {
+        self.bus.mem_poke_byte(address, value)
+    }
+
+    /// Replaces the watchpoints checked on each memory access done by an instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `watchpoints`: The watchpoints, none to stop checking accesses
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        let watch = if watchpoints.is_empty() {
+            None
+        } else {
+            Some(WatchList::new(watchpoints))
+        };
+        self.bus.set_watch_list(watch);
+    }
+
+    /// Gets the watchpoints checked on each memory access done by an instruction
+    ///
+    /// # Returns
+    ///
+    /// The watchpoints
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        match self.bus.get_watch_list() {
+            Some(watch) => watch.get_watchpoints(),
+            None => &[],
+        }
+    }
+
+    /// Takes the watched accesses recorded since the last call
+    ///
+    /// # Returns
+    ///
+    /// The accesses, oldest first, with the instruction that did them
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.bus.take_watch_hits()
+    }
+
+    /// Gets the display buffer, one `0xRRGGBB` color per pixel, row by row
+    ///
+    /// # Returns
+    ///
+    /// The display buffer, `get_display_width() * get_display_height()` pixels long
+    pub fn get_display_buffer(&self) -> &[u32] {
+        self.bus.get_display_buffer()
+    }
+
+    /// Gets the display width, which depends on the resolution mode
+    ///
+    /// # Returns
+    ///
+    /// The display width, in pixels: 64, or 128 in high resolution mode
+    pub fn get_display_width(&self) -> usize {
+        self.bus.get_display_width()
+    }
+
+    /// Gets the display height, which depends on the resolution mode
+    ///
+    /// # Returns
+    ///
+    /// The display height, in pixels: 32, or 64 in high resolution mode
+    pub fn get_display_height(&self) -> usize {
+        self.bus.get_display_height()
+    }
+
+    /// Gets a hash of the display, which changes whenever a pixel or the resolution changes
+    ///
+    /// # Returns
+    ///
+    /// The 64 bits FNV-1a hash of the display size and buffer
+    pub fn get_display_hash(&self) -> u64 {
+        movie::hash_display(
+            self.get_display_width(),
+            self.get_display_height(),
+            self.get_display_buffer(),
+        )
+    }
+
+    /// Sets the state of a keyboard key
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: The value of the key (0x0 to 0xF)
+    /// - `pressed`: `true` if the key is down, `false` if it is up
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.bus.set_key(key, pressed);
     }
 
-    /// Run the Chip8
-    pub fn run(&mut self) {
+    /// Sets the state of every keyboard key
+    ///
+    /// # Parameters
+    ///
+    /// - `keys`: The state of each key, `true` when the key is down
+    pub fn set_keys(&mut self, keys: [bool; KEY_COUNT]) {
+        for (key, pressed) in keys.iter().enumerate() {
+            self.bus.set_key(key as u8, *pressed);
+        }
+    }
+
+    /// Gets the state of every keyboard key
+    ///
+    /// # Returns
+    ///
+    /// The state of each key, `true` when the key is down
+    pub fn get_keys(&self) -> [bool; KEY_COUNT] {
+        self.bus.get_keys()
+    }
+
+    /// Gets delay timer value
+    ///
+    /// # Returns
+    ///
+    /// The value of the delay timer
+    pub fn get_dt(&self) -> u8 {
+        self.bus.get_dt()
+    }
+
+    /// Sets delay timer value
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The value of the delay timer
+    pub fn set_dt(&mut self, value: u8) {
+        self.bus.set_dt(value);
+    }
+
+    /// Gets sound timer value
+    ///
+    /// # Returns
+    ///
+    /// The value of the sound timer
+    pub fn get_st(&self) -> u8 {
+        self.bus.get_st()
+    }
+
+    /// Sets sound timer value
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The value of the sound timer
+    pub fn set_st(&mut self, value: u8) {
+        self.bus.set_st(value);
+    }
+
+    /// Encodes the display as a PNG image
+    ///
+    /// # Parameters
+    ///
+    /// - `scale`: The size of a Chip8 pixel in the image, in image pixels
+    /// - `palette`: The colors of the image
+    ///
+    /// # Returns
+    ///
+    /// The bytes of the PNG file
+    pub fn screenshot(&self, scale: usize, palette: &Palette) -> Vec<u8> {
+        png::encode(
+            self.get_display_width(),
+            self.get_display_height(),
+            self.get_display_buffer(),
+            scale,
+            palette,
+        )
+    }
+
+    /// Run the Chip8 without a window, as fast as possible, for a number of frames or until the
+    /// program exits, then write a screenshot of the display. The keypad state of each frame
+    /// comes from an input source, which is given the keys of the config script as host keys.
+    ///
+    /// # Parameters
+    ///
+    /// - `config`: The headless frontend settings
+    /// - `input`: The source of the keypad state of each frame
+    ///
+    /// # Returns
+    ///
+    /// The `HeadlessError` that stopped the emulation, if any
+    pub fn run_headless(
+        &mut self,
+        config: &HeadlessConfig,
+        input: &mut dyn InputSource,
+    ) -> Result<(), HeadlessError> {
+        let mut scheduler = Scheduler::new(config.cpu_hz.max(1), Instant::now());
+        for frame in 0..config.frames {
+            if self.has_exited() || input.is_finished() {
+                break;
+            }
+            let keys = input.next_frame(config.keys.keys_at(frame));
+            self.set_keys(keys);
+            self.run_frame(scheduler.next_frame_cycles())?;
+            input.end_frame(self.get_display_hash())?;
+
+            // Log the watched accesses of the frame
+            for hit in self.take_watch_hits() {
+                eprintln!("watchpoint: {}", hit);
+            }
+
+            if let (Some(path), Some(every)) = (&config.screenshot, config.every) {
+                if (frame + 1) % every.max(1) == 0 {
+                    self.write_screenshot(&headless::numbered_path(path, frame + 1), config)?;
+                }
+            }
+        }
+
+        match (&config.screenshot, config.every) {
+            (Some(path), None) => self.write_screenshot(path, config),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes a screenshot of the display for `run_headless`
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: The path of the PNG file
+    /// - `config`: The headless frontend settings, giving the scale and palette
+    ///
+    /// # Returns
+    ///
+    /// A `HeadlessError` if the file could not be written
+    fn write_screenshot(&self, path: &Path, config: &HeadlessConfig) -> Result<(), HeadlessError> {
+        fs::write(path, self.screenshot(config.scale, &config.palette)).map_err(|error| {
+            HeadlessError::Screenshot {
+                path: path.to_path_buf(),
+                error,
+            }
+        })
+    }
+
+    /// Run the Chip8 in a window, which is closed with the Escape key or when the program exits.
+    /// The Space key pauses and resumes the emulation, Backspace runs it backwards while held,
+    /// F1 to F9 load save state slots and Shift + F1 to F9 save them. With a GDB server, the
+    /// emulation is stopped while the debugger client requests it.
+    ///
+    /// # Parameters
+    ///
+    /// - `config`: The window frontend settings
+    ///
+    /// # Returns
+    ///
+    /// The `RunError` that stopped the emulation, if any
+    #[cfg(feature = "window")]
+    pub fn run(&mut self, config: &RunConfig) -> Result<(), RunError> {
+        self.run_with_input(config, &mut LiveInput)
+    }
+
+    /// Run the Chip8 in a window like `run`, the keypad state of each frame coming from an input
+    /// source. The window is also closed when the source runs out of frames. Save state loading
+    /// and rewinding are disabled when the source does not allow them.
+    ///
+    /// # Parameters
+    ///
+    /// - `config`: The window frontend settings
+    /// - `input`: The source of the keypad state of each frame
+    ///
+    /// # Returns
+    ///
+    /// The `RunError` that stopped the emulation, if any
+    #[cfg(feature = "window")]
+    pub fn run_with_input(
+        &mut self,
+        config: &RunConfig,
+        input: &mut dyn InputSource,
+    ) -> Result<(), RunError> {
         // Create display window
         let mut window = Window::new(
             "Chip8",
-            display::WIDTH * 10,
-            display::HEIGHT * 10,
+            display::WIDTH * config.scale,
+            display::HEIGHT * config.scale,
             WindowOptions::default(),
         )
         .unwrap();
 
-        // Initialize timers
-        let mut last_key_time = Instant::now();
-        let mut last_cpu_cyle_time = Instant::now();
-        let mut last_display_time = Instant::now();
+        let mut scheduler = Scheduler::new(config.cpu_hz.max(1), Instant::now());
+        let mut rewind = Rewind::new(config.rewind_budget);
+        rewind.push(self.save_state());
+        let mut paused = config.paused;
+        let mut status = String::new();
+        let mut debugger_stopped = false;
+        // Instructions left to run in the frame interrupted by a watchpoint
+        let mut frame_left = 0;
+        set_window_title(&mut window, paused, &status);
 
         // Chip8 loop
-        while window.is_open() && !window.is_key_down(Key::Escape) {
-            // Get pressed key, if any
-            let keys_pressed = window.get_keys();
-            let key = match keys_pressed {
-                Some(keys) => {
-                    if !keys.is_empty() {
-                        Some(keys[0])
+        while window.is_open()
+            && !window.is_key_down(Key::Escape)
+            && !self.has_exited()
+            && !input.is_finished()
+        {
+            thread::sleep(scheduler.time_until_next_frame(Instant::now()));
+
+            if window.is_key_pressed(Key::Space, KeyRepeat::No) {
+                paused = !paused;
+                set_window_title(&mut window, paused, &status);
+            }
+
+            // Answer the debugger client
+            if let Some(mut gdb) = self.gdb.take() {
+                gdb.poll(self);
+                let stopped = gdb.is_stopped();
+                self.gdb = Some(gdb);
+                if stopped != debugger_stopped {
+                    debugger_stopped = stopped;
+                    status = if stopped {
+                        String::from("stopped by the debugger")
                     } else {
-                        None
-                    }
+                        String::new()
+                    };
+                    set_window_title(&mut window, paused, &status);
                 }
-                None => None,
-            };
+            }
 
-            // Update pressed key
-            if Instant::now() - last_key_time >= Duration::from_millis(KEY_TIME) {
-                self.bus.set_pressed_key(get_key_code(key));
-                last_key_time = Instant::now();
+            // Save or load a state slot
+            if let (Some(state_path), Some(slot)) = (&config.state_path, pressed_slot(&window)) {
+                let mut path = state_path.clone().into_os_string();
+                path.push(format!(".s{}", slot));
+                let path = PathBuf::from(path);
+                let shift =
+                    window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                status = if shift {
+                    match fs::write(&path, self.save_state()) {
+                        Ok(()) => format!("state {} saved", slot),
+                        Err(error) => format!("cannot save state {}: {}", slot, error),
+                    }
+                } else if !input.allows_state_changes() {
+                    String::from("cannot load states while playing or recording a movie")
+                } else {
+                    match fs::read(&path).map(|state| self.load_state(&state)) {
+                        Ok(Ok(())) => {
+                            frame_left = 0;
+                            format!("state {} loaded", slot)
+                        }
+                        Ok(Err(error)) => format!("cannot load state {}: {}", slot, error),
+                        Err(error) => format!("cannot load state {}: {}", slot, error),
+                    }
+                };
+                set_window_title(&mut window, paused, &status);
             }
 
-            // Run CPU cycle
-            if Instant::now() - last_cpu_cyle_time >= Duration::from_millis(CPU_CYLE_TIME) {
-                self.cpu.cycle(&mut self.bus);
-                last_cpu_cyle_time = Instant::now();
+            // Get the keys currently pressed
+            let mut host_keys = [false; KEY_COUNT];
+            for key in window.get_keys().unwrap_or_default() {
+                if let Some(key_code) = config.keymap.get_key_code(&format!("{:?}", key)) {
+                    host_keys[key_code as usize] = true;
+                }
             }
 
-            // Refresh display
-            if Instant::now() - last_display_time >= Duration::from_millis(DISPLAY_TIME) {
-                let display_buffer = self.bus.get_display_buffer();
-                window
-                    .update_with_buffer(&display_buffer, display::WIDTH, display::HEIGHT)
-                    .unwrap();
-                last_display_time = Instant::now();
-                // Update delay and sound timers
-                self.bus.dec_dt();
-                self.bus.dec_st();
+            // Run the frames due, the scheduler keeping its pace while paused. While the rewind
+            // key is held, each frame steps back instead.
+            let rewinding = window.is_key_down(Key::Backspace) && input.allows_state_changes();
+            for _ in 0..scheduler.frames_due(Instant::now()) {
+                let stopped = self.gdb.as_ref().is_some_and(GdbServer::is_stopped);
+                if paused || stopped || input.is_finished() {
+                    continue;
+                }
+                if rewinding {
+                    if let Some(state) = rewind.pop() {
+                        self.load_state(state)
+                            .expect("rewind states are valid save states");
+                        frame_left = 0;
+                    }
+                } else {
+                    // Finish the frame interrupted by a watchpoint before starting a new one
+                    let cycles = if frame_left > 0 {
+                        frame_left
+                    } else {
+                        let keys = input.next_frame(host_keys);
+                        self.set_keys(keys);
+                        scheduler.next_frame_cycles()
+                    };
+                    let hits = match self.gdb.take() {
+                        Some(mut gdb) => {
+                            let result = gdb.run_frame(self, cycles);
+                            self.gdb = Some(gdb);
+                            result?;
+                            self.take_watch_hits()
+                        }
+                        None if config.pause_on_watch => {
+                            let (left, hits) = self.run_frame_until_watch(cycles)?;
+                            frame_left = left;
+                            hits
+                        }
+                        None => {
+                            self.run_frame(cycles)?;
+                            self.take_watch_hits()
+                        }
+                    };
+                    if frame_left == 0 {
+                        input.end_frame(self.get_display_hash())?;
+                        rewind.push(self.save_state());
+                    }
+
+                    // Log the watched accesses, pausing right after the instruction that did
+                    // them with `pause_on_watch`
+                    for hit in &hits {
+                        eprintln!("watchpoint: {}", hit);
+                    }
+                    if let (Some(hit), true) = (hits.last(), config.pause_on_watch) {
+                        paused = true;
+                        status = hit.to_string();
+                        set_window_title(&mut window, paused, &status);
+                    }
+                }
             }
+
+            // Refresh display
+            window
+                .update_with_buffer(
+                    self.bus.get_display_buffer(),
+                    self.bus.get_display_width(),
+                    self.bus.get_display_height(),
+                )
+                .unwrap();
         }
+
+        Ok(())
     }
 }
 
-/// Gets Chip8 hexa key code associated to the computer keyboard pressed key
+/// Sets the window title according to the emulation state
 ///
-/// # Parameter
+/// # Parameters
 ///
-/// - `key`: The code of the key pressed on the computer keyboard
+/// - `window`: The emulator window
+/// - `paused`: Is the emulation paused?
+/// - `status`: The result of the last frontend action, if any
+#[cfg(feature = "window")]
+fn set_window_title(window: &mut Window, paused: bool, status: &str) {
+    let mut title = String::from("Chip8");
+    if paused {
+        title.push_str(" (paused)");
+    }
+    if !status.is_empty() {
+        title.push_str(" - ");
+        title.push_str(status);
+    }
+    window.set_title(&title);
+}
+
+/// Gets the save state slot whose key (F1 to F9) has just been pressed
+///
+/// # Parameters
+///
+/// - `window`: The emulator window
 ///
 /// # Returns
 ///
-/// The code of the associated Chip8 keyboard key.
-fn get_key_code(key: Option<Key>) -> Option<u8> {
-    match key {
-        Some(Key::A) => Some(0x1),
-        Some(Key::Z) => Some(0x2),
-        Some(Key::E) => Some(0x3),
-        Some(Key::R) => Some(0xC),
-
-        Some(Key::Q) => Some(0x4),
-        Some(Key::S) => Some(0x5),
-        Some(Key::D) => Some(0x6),
-        Some(Key::F) => Some(0xD),
-
-        Some(Key::U) => Some(0x7),
-        Some(Key::I) => Some(0x8),
-        Some(Key::O) => Some(0x9),
-        Some(Key::P) => Some(0xE),
-
-        Some(Key::J) => Some(0xA),
-        Some(Key::K) => Some(0x0),
-        Some(Key::L) => Some(0xB),
-        Some(Key::M) => Some(0xF),
-
-        _ => None,
+/// The slot number (1 to 9), if any
+#[cfg(feature = "window")]
+fn pressed_slot(window: &Window) -> Option<u8> {
+    const SLOT_KEYS: [Key; 9] = [
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+        Key::F9,
+    ];
+    SLOT_KEYS
+        .iter()
+        .position(|key| window.is_key_pressed(*key, KeyRepeat::No))
+        .map(|index| index as u8 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a machine with a program loaded at `PROGRAM_START`
+    fn chip8_with(program: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(program).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn step_instruction_runs_one_opcode() {
+        // LD V3, #2A; ADD V3, #01
+        let mut chip8 = chip8_with(&[0x63, 0x2A, 0x73, 0x01]);
+
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.get_register(3), 0x2A);
+        assert_eq!(chip8.get_pc(), 0x202);
+
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.get_register(3), 0x2B);
+        assert_eq!(chip8.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn run_frame_runs_the_cycles_then_ticks_the_timers() {
+        // LD V0, #05; LD DT, V0; LD ST, V0; JP #206
+        let mut chip8 = chip8_with(&[0x60, 0x05, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06]);
+
+        chip8.run_frame(3).unwrap();
+        assert_eq!(chip8.get_pc(), 0x206);
+        assert_eq!(chip8.get_dt(), 4);
+        assert_eq!(chip8.get_st(), 4);
+    }
+
+    #[test]
+    fn tick_timers_decrements_down_to_zero() {
+        let mut chip8 = Chip8::new();
+        chip8.set_dt(2);
+        chip8.set_st(1);
+
+        chip8.tick_timers();
+        assert_eq!((chip8.get_dt(), chip8.get_st()), (1, 0));
+        chip8.tick_timers();
+        assert_eq!((chip8.get_dt(), chip8.get_st()), (0, 0));
+        chip8.tick_timers();
+        assert_eq!((chip8.get_dt(), chip8.get_st()), (0, 0));
+    }
+
+    #[test]
+    fn key_wait_blocks_until_a_key_is_pressed() {
+        // LD V3, K
+        let mut chip8 = chip8_with(&[0xF3, 0x0A]);
+
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.get_pc(), 0x200);
+
+        chip8.set_key(0x7, true);
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.get_register(3), 0x7);
+        assert_eq!(chip8.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn key_wait_ignores_a_key_held_before_the_wait() {
+        // LD V3, K
+        let mut chip8 = chip8_with(&[0xF3, 0x0A]);
+        chip8.set_key(0x7, true);
+
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.get_pc(), 0x200);
+    }
+
+    #[test]
+    fn key_wait_on_release_completes_when_the_key_is_released() {
+        let quirks = Quirks {
+            key_wait_on_release: true,
+            ..Quirks::default()
+        };
+        let mut chip8 = Chip8::with_quirks(quirks);
+        // LD V3, K
+        chip8.load_rom(&[0xF3, 0x0A]).unwrap();
+
+        chip8.step_instruction().unwrap();
+        chip8.set_key(0x7, true);
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.get_pc(), 0x200);
+
+        chip8.set_key(0x7, false);
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.get_register(3), 0x7);
+        assert_eq!(chip8.get_pc(), 0x202);
+    }
+
+    /// Creates a machine with the given quirks, then runs `cycles` instructions of a program
+    fn run_with(quirks: Quirks, program: &[u8], cycles: u32) -> Chip8 {
+        let mut chip8 = Chip8::with_quirks(quirks);
+        chip8.load_rom(program).unwrap();
+        for _ in 0..cycles {
+            chip8.step_instruction().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn shift_uses_vy_quirk() {
+        // LD V1, #05; LD V2, #0C; SHR V1, V2
+        let program = [0x61, 0x05, 0x62, 0x0C, 0x81, 0x26];
+
+        let chip8 = run_with(Quirks::default(), &program, 3);
+        assert_eq!((chip8.get_register(1), chip8.get_register(0xF)), (0x02, 1));
+
+        let chip8 = run_with(Quirks::cosmac_vip(), &program, 3);
+        assert_eq!((chip8.get_register(1), chip8.get_register(0xF)), (0x06, 0));
+    }
+
+    #[test]
+    fn index_increment_quirk() {
+        // LD I, #300; LD [I], V2
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+
+        assert_eq!(run_with(Quirks::default(), &program, 2).get_i(), 0x300);
+        assert_eq!(run_with(Quirks::chip48(), &program, 2).get_i(), 0x302);
+        assert_eq!(run_with(Quirks::cosmac_vip(), &program, 2).get_i(), 0x303);
+    }
+
+    #[test]
+    fn jump_uses_vx_quirk() {
+        // LD V0, #10; LD V3, #20; JP V0, #340
+        let program = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x40];
+
+        assert_eq!(run_with(Quirks::default(), &program, 3).get_pc(), 0x350);
+        assert_eq!(run_with(Quirks::chip48(), &program, 3).get_pc(), 0x360);
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk() {
+        // LD VF, #07; LD V1, #03; LD V2, #04; OR V1, V2
+        let program = [0x6F, 0x07, 0x61, 0x03, 0x62, 0x04, 0x81, 0x21];
+
+        let chip8 = run_with(Quirks::default(), &program, 4);
+        assert_eq!(
+            (chip8.get_register(1), chip8.get_register(0xF)),
+            (0x07, 0x07)
+        );
+
+        let chip8 = run_with(Quirks::cosmac_vip(), &program, 4);
+        assert_eq!(
+            (chip8.get_register(1), chip8.get_register(0xF)),
+            (0x07, 0x00)
+        );
+    }
+
+    #[test]
+    fn clip_sprites_quirk() {
+        // LD V0, #3C; LD V1, #00; LD I, #208; DRW V0, V1, 1; then an 8 pixels wide sprite
+        let program = [0x60, 0x3C, 0x61, 0x00, 0xA2, 0x08, 0xD0, 0x11, 0xFF];
+
+        let chip8 = run_with(Quirks::default(), &program, 4);
+        assert_eq!(first_lit_column(&chip8), Some(0));
+
+        let chip8 = run_with(Quirks::cosmac_vip(), &program, 4);
+        assert_eq!(first_lit_column(&chip8), Some(60));
+    }
+
+    /// HIGH; LD I, #20C; LD V0, #00; LD V1, #3C; DRW V0, V1, 8; DRW V0, V1, 8; then an 8 rows
+    /// sprite, drawn half off the bottom of the display
+    const CLIPPED_SPRITE: [u8; 20] = [
+        0x00, 0xFF, 0xA2, 0x0C, 0x60, 0x00, 0x61, 0x3C, 0xD0, 0x18, 0xD0, 0x18, 0xFF, 0xFF, 0xFF,
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+
+    #[test]
+    fn hires_drw_counts_collided_and_clipped_rows() {
+        let mut chip8 = Chip8::with_quirks(Quirks::superchip());
+        chip8.load_rom(&CLIPPED_SPRITE).unwrap();
+
+        chip8.run_frame(5).unwrap();
+        assert_eq!(chip8.get_register(0xF), 4);
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.get_register(0xF), 8);
+    }
+
+    #[test]
+    fn drw_sets_vf_to_one_on_collision_without_the_quirk() {
+        let quirks = Quirks {
+            count_collision_rows: false,
+            ..Quirks::superchip()
+        };
+        let mut chip8 = Chip8::with_quirks(quirks);
+        chip8.load_rom(&CLIPPED_SPRITE).unwrap();
+
+        chip8.run_frame(5).unwrap();
+        assert_eq!(chip8.get_register(0xF), 0);
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.get_register(0xF), 1);
+    }
+
+    /// Gets the (x, y) coordinates of the lit pixels, row by row
+    fn lit_pixels(chip8: &Chip8) -> Vec<(usize, usize)> {
+        let width = chip8.get_display_width();
+        chip8
+            .get_display_buffer()
+            .iter()
+            .enumerate()
+            .filter(|&(_, &color)| color != crate::display::PALETTE[0])
+            .map(|(index, _)| (index % width, index / width))
+            .collect()
+    }
+
+    #[test]
+    fn switching_the_resolution_clears_the_display() {
+        // LD V0, #00; LD I, #20C; DRW V0, V0, 1; HIGH; DRW V0, V0, 1; LOW; then a one pixel sprite
+        let program = [
+            0x60, 0x00, 0xA2, 0x0C, 0xD0, 0x01, 0x00, 0xFF, 0xD0, 0x01, 0x00, 0xFE, 0x80,
+        ];
+        let mut chip8 = chip8_with(&program);
+        chip8.run_frame(3).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![(0, 0)]);
+
+        chip8.step_instruction().unwrap();
+        assert_eq!(
+            (chip8.get_display_width(), chip8.get_display_height()),
+            (128, 64)
+        );
+        assert_eq!(lit_pixels(&chip8), Vec::new());
+
+        chip8.step_instruction().unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![(0, 0)]);
+        chip8.step_instruction().unwrap();
+        assert_eq!(
+            (chip8.get_display_width(), chip8.get_display_height()),
+            (64, 32)
+        );
+        assert_eq!(lit_pixels(&chip8), Vec::new());
+    }
+
+    #[test]
+    fn scroll_down_moves_the_display_by_n_rows() {
+        // LD V0, #03; LD I, #208; DRW V0, V0, 1; SCD 2; then a one pixel sprite
+        let program = [0x60, 0x03, 0xA2, 0x08, 0xD0, 0x01, 0x00, 0xC2, 0x80];
+        let mut chip8 = chip8_with(&program);
+        chip8.run_frame(3).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![(3, 3)]);
+
+        chip8.step_instruction().unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![(3, 5)]);
+    }
+
+    #[test]
+    fn drw_with_n_zero_draws_a_16x16_sprite() {
+        // HIGH; LD V0, #00; LD I, #208; DRW V0, V0, 0; then 16 rows of 2 bytes, lighting the
+        // first and last pixels of each row
+        let mut program = vec![0x00, 0xFF, 0x60, 0x00, 0xA2, 0x08, 0xD0, 0x00];
+        for _ in 0..16 {
+            program.extend_from_slice(&[0x80, 0x01]);
+        }
+        let mut chip8 = chip8_with(&program);
+        chip8.run_frame(4).unwrap();
+
+        let expected: Vec<_> = (0..16).flat_map(|y| vec![(0, y), (15, y)]).collect();
+        assert_eq!(lit_pixels(&chip8), expected);
+        assert_eq!(chip8.get_register(0xF), 0);
+    }
+
+    /// Gets the column of the first lit pixel of the top display row
+    fn first_lit_column(chip8: &Chip8) -> Option<usize> {
+        chip8.get_display_buffer()[..chip8.get_display_width()]
+            .iter()
+            .position(|&color| color != crate::display::PALETTE[0])
+    }
+
+    #[test]
+    fn lores_scroll_right_moves_half_as_many_pixels_with_the_quirk() {
+        // LD V0, #00; LD I, #208; DRW V0, V0, 1; SCR; then a one pixel sprite
+        let program = [0x60, 0x00, 0xA2, 0x08, 0xD0, 0x01, 0x00, 0xFB, 0x80];
+        let mut chip8 = Chip8::with_quirks(Quirks::superchip());
+        chip8.load_rom(&program).unwrap();
+        chip8.run_frame(4).unwrap();
+        assert_eq!(first_lit_column(&chip8), Some(2));
+
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&program).unwrap();
+        chip8.run_frame(4).unwrap();
+        assert_eq!(first_lit_column(&chip8), Some(4));
+    }
+
+    #[test]
+    fn register_ranges_are_saved_and_loaded_in_both_directions() {
+        // LD V1, #11; LD V2, #22; LD V3, #33; LD I, #300; LD [I], V1-V3; LD I, #310;
+        // LD [I], V3-V1; LD I, #300; LD V5-V7, [I]; LD V9-V7, [I]
+        let program = [
+            0x61, 0x11, 0x62, 0x22, 0x63, 0x33, 0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x53, 0x12,
+            0xA3, 0x00, 0x55, 0x73, 0x59, 0x73,
+        ];
+        let chip8 = run_with(Quirks::xo_chip(), &program, 10);
+
+        let memory = |address| chip8.read_memory(address).unwrap();
+        assert_eq!(
+            [memory(0x300), memory(0x301), memory(0x302)],
+            [0x11, 0x22, 0x33]
+        );
+        assert_eq!(
+            [memory(0x310), memory(0x311), memory(0x312)],
+            [0x33, 0x22, 0x11]
+        );
+        let registers: Vec<_> = (5..=9).map(|x| chip8.get_register(x)).collect();
+        assert_eq!(registers, [0x11, 0x22, 0x33, 0x22, 0x11]);
+        assert_eq!(chip8.get_i(), 0x300);
+    }
+
+    #[test]
+    fn long_load_is_four_bytes_long_and_skipped_as_such() {
+        // LD I, LONG #1234; LD V0, #00; SE V0, #00; LD I, LONG #5678; LD V1, #01
+        let program = [
+            0xF0, 0x00, 0x12, 0x34, 0x60, 0x00, 0x30, 0x00, 0xF0, 0x00, 0x56, 0x78, 0x61, 0x01,
+        ];
+        let mut chip8 = chip8_with(&program);
+
+        chip8.step_instruction().unwrap();
+        assert_eq!((chip8.get_i(), chip8.get_pc()), (0x1234, 0x204));
+
+        chip8.run_frame(2).unwrap();
+        assert_eq!(chip8.get_pc(), 0x20C);
+        chip8.step_instruction().unwrap();
+        assert_eq!((chip8.get_i(), chip8.get_register(1)), (0x1234, 0x01));
+    }
+
+    #[test]
+    fn drw_on_both_planes_draws_one_sprite_per_plane() {
+        // PLANE 3; LD V0, #00; LD I, #208; DRW V0, V0, 1; then the sprites of planes 1 and 2
+        let program = [0xF3, 0x01, 0x60, 0x00, 0xA2, 0x08, 0xD0, 0x01, 0x80, 0xC0];
+        let chip8 = run_with(Quirks::xo_chip(), &program, 4);
+
+        let palette = crate::display::PALETTE;
+        assert_eq!(
+            chip8.get_display_buffer()[..3],
+            [palette[3], palette[2], palette[0]]
+        );
+    }
+
+    #[test]
+    fn scroll_up_moves_the_display_by_n_rows() {
+        // LD V0, #03; LD I, #208; DRW V0, V0, 1; SCU 2; then a one pixel sprite
+        let program = [0x60, 0x03, 0xA2, 0x08, 0xD0, 0x01, 0x00, 0xD2, 0x80];
+        let mut chip8 = Chip8::with_quirks(Quirks::xo_chip());
+        chip8.load_rom(&program).unwrap();
+        chip8.run_frame(3).unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![(3, 3)]);
+
+        chip8.step_instruction().unwrap();
+        assert_eq!(lit_pixels(&chip8), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn xo_chip_memory_is_addressed_past_0xfff() {
+        // LD V0, #5A; LD I, LONG #2000; LD [I], V0
+        let program = [0x60, 0x5A, 0xF0, 0x00, 0x20, 0x00, 0xF0, 0x55];
+
+        let chip8 = run_with(Quirks::xo_chip(), &program, 3);
+        assert_eq!(chip8.read_memory(0x2000), Ok(0x5A));
+
+        let mut chip8 = run_with(Quirks::default(), &program, 2);
+        assert_eq!(
+            chip8.step_instruction(),
+            Err(CpuError::InvalidAddress {
+                pc: 0x206,
+                opcode: 0xF055,
+                address: 0x2000
+            })
+        );
+    }
+
+    #[test]
+    fn run_frame_until_watch_stops_after_the_watched_access() {
+        // LD V0, #01; LD V1, #02; LD I, #300; LD [I], V0; LD V2, #03; JP #20A
+        let program = [
+            0x60, 0x01, 0x61, 0x02, 0xA3, 0x00, 0xF0, 0x55, 0x62, 0x03, 0x12, 0x0A,
+        ];
+        let mut chip8 = chip8_with(&program);
+        chip8.set_watchpoints(vec!["300".parse().unwrap()]);
+        chip8.set_dt(5);
+
+        let (left, hits) = chip8.run_frame_until_watch(10).unwrap();
+        assert_eq!(left, 6);
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].pc, hits[0].opcode), (0x206, 0xF055));
+        assert_eq!(chip8.get_pc(), 0x208);
+        assert_eq!(chip8.get_register(2), 0);
+        assert_eq!(chip8.get_dt(), 5);
+
+        // The rest of the frame runs without watched access, then the timers tick
+        assert_eq!(chip8.run_frame_until_watch(left).unwrap(), (0, Vec::new()));
+        assert_eq!(chip8.get_register(2), 3);
+        assert_eq!(chip8.get_dt(), 4);
+    }
+
+    #[test]
+    fn call_with_a_full_stack_overflows() {
+        // CALL #200
+        let mut chip8 = chip8_with(&[0x22, 0x00]);
+        for _ in 0..16 {
+            chip8.step_instruction().unwrap();
+        }
+
+        assert_eq!(
+            chip8.step_instruction(),
+            Err(CpuError::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200
+            })
+        );
+    }
+
+    #[test]
+    fn ret_with_an_empty_stack_underflows() {
+        // RET
+        let mut chip8 = chip8_with(&[0x00, 0xEE]);
+
+        assert_eq!(
+            chip8.step_instruction(),
+            Err(CpuError::StackUnderflow {
+                pc: 0x200,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_opcode_is_reported() {
+        // LD V0, #01; 5xy1 is not an instruction
+        let mut chip8 = chip8_with(&[0x60, 0x01, 0x51, 0x21]);
+        chip8.set_dt(3);
+
+        assert_eq!(
+            chip8.run_frame(2),
+            Err(CpuError::UnknownOpcode {
+                pc: 0x202,
+                opcode: 0x5121
+            })
+        );
+        // The failed frame does not tick the timers
+        assert_eq!(chip8.get_dt(), 3);
+    }
+
+    #[test]
+    fn access_outside_of_the_memory_is_an_invalid_address() {
+        // LD I, #FFF; LD [I], V1
+        let mut chip8 = chip8_with(&[0xAF, 0xFF, 0xF1, 0x55]);
+        chip8.step_instruction().unwrap();
+
+        assert_eq!(
+            chip8.step_instruction(),
+            Err(CpuError::InvalidAddress {
+                pc: 0x202,
+                opcode: 0xF155,
+                address: 0x1000
+            })
+        );
+    }
+
+    #[test]
+    fn fetch_outside_of_the_memory_is_an_invalid_address() {
+        let mut chip8 = Chip8::new();
+        chip8.set_pc(0xFFF);
+
+        assert_eq!(
+            chip8.step_instruction(),
+            Err(CpuError::InvalidAddress {
+                pc: 0xFFF,
+                opcode: 0,
+                address: 0x1000
+            })
+        );
+    }
+
+    #[test]
+    fn rnd_masks_a_seeded_random_byte() {
+        // RND V1, #0F; RND V2, #F0
+        let program = [0xC1, 0x0F, 0xC2, 0xF0];
+        let mut rng = Rng::from_seed(0);
+        let first = rng.next_u8();
+        let second = rng.next_u8();
+
+        let mut chip8 = Chip8::new();
+        chip8.set_rng(Rng::from_seed(0));
+        chip8.load_rom(&program).unwrap();
+        chip8.step_instruction().unwrap();
+        chip8.step_instruction().unwrap();
+        assert_eq!(chip8.get_register(1), 0x02);
+        assert_eq!(chip8.get_register(1), first & 0x0F);
+        assert_eq!(chip8.get_register(2), second & 0xF0);
     }
 }
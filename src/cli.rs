@@ -0,0 +1,400 @@
+use chip8::{HeadlessConfig, Quirks, RunConfig, TraceFilter, Watchpoint};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Command line help
+pub const USAGE: &str = "\
+Usage: chip8 [run] [OPTIONS] <ROM>
+       chip8 disasm [--linear] <ROM>
+       chip8 asm [-o <ROM>] <SOURCE>
+       chip8 debug [--cpu-hz <HZ>] [--quirks <PRESET>] [--seed <SEED>] <ROM>
+
+Commands:
+  disasm <ROM>       Disassemble a ROM by following its execution flow, printing source with
+                     labels, sprites and data directives; '--linear' prints a plain listing
+                     of every word instead
+  asm <SOURCE>       Assemble a source file into a ROM, written to '-o <ROM>' [default: the
+                     source path with the '.ch8' extension]
+  debug <ROM>        Run a ROM in a console debugger with breakpoints, stepping and memory
+                     inspection ('help' lists its commands)
+
+Arguments:
+  <ROM>              Path of the CHIP-8 ROM to run
+
+Options:
+  --cpu-hz <HZ>      Number of instructions executed per second [default: 500]
+  --scale <SCALE>    Size of a CHIP-8 pixel in the window, in screen pixels [default: 10]
+  --paused           Start the emulation paused (Space toggles the pause)
+  --keymap <KEYMAP>  Key mapping: qwerty, azerty or the path of a key mapping file
+                     [default: ~/.config/chip8/keymap.cfg if it exists, else qwerty]
+  --rewind <MIB>     Memory used to rewind the emulation (Backspace), 0 disables it
+                     [default: 16]
+  --seed <SEED>      Seed of the random number generator, to make runs reproducible
+                     [default: random]
+  --record <FILE>    Record the keypad inputs to a movie file
+  --replay <FILE>    Replay a movie file, checking that the display matches the recording
+  --wav <FILE>       Record the sound to a WAV file
+  --watch <SPEC>     Log the instructions accessing memory: <ADDR>[-<END>][:r|w|rw|=<VALUE>]
+                     in hexadecimal, e.g. '300-302:w' or '3A0:=FF' [default kind: w]
+                     (repeatable)
+  --watch-pause      Pause the emulation when a watched access happens
+  --gdb <PORT>       Listen for a GDB remote protocol client on 127.0.0.1:<PORT>, the
+                     emulation being stopped while the client requests it
+  --trace <FILE>     Log every executed instruction with the registers after it to a file,
+                     '-' for the standard output
+  --trace-pc <RANGE> Only trace the instructions in an address range: <START>[-<END>] in
+                     hexadecimal, e.g. '200-2FF'
+  --trace-ops <OPS>  Only trace some opcode families, the first hexadecimal digit of the
+                     opcodes, e.g. '8,D,F'
+  --trace-limit <N>  Stop tracing after the first N executed instructions
+  --headless         Run without a window, as fast as possible, then exit
+  --frames <N>       Number of frames run by '--headless', at 60 frames per second
+                     [default: 600]
+  --screenshot <PNG> Write the display after the last headless frame to a PNG file, at the
+                     '--scale' size
+  --every <K>        Write a screenshot every K headless frames instead, the frame number
+                     being appended to the file name, e.g. 'out-000060.png'
+  --palette <COLORS> Colors of the screenshots: 2 to 4 comma separated RRGGBB colors for the
+                     background and the planes, e.g. '000000,FFFFFF'
+  --keys <SCRIPT>    Keys pressed during a headless run: comma separated <FRAME>:<KEYS> steps,
+                     e.g. '0:5,10:,60:46' holds 5 from frame 0, nothing from frame 10, then
+                     4 and 6 from frame 60
+  --quirks <PRESET>  Behaviour of the ambiguous instructions: cosmac-vip, chip-48, superchip
+                     or xo-chip [default: shifts ignore Vy, Fx55/Fx65 leave I unchanged,
+                     Bnnn uses V0, logic operations keep VF, sprites wrap]
+  -h, --help         Print this help";
+
+/// Action requested on the command line
+pub enum Command {
+    /// Print the command line help
+    Help,
+    /// Run a ROM in the emulator window
+    Run(Box<Options>),
+    /// Disassemble a ROM
+    Disasm {
+        /// Path of the ROM to disassemble
+        rom: PathBuf,
+        /// Whether every word is listed as an instruction, instead of following the execution
+        /// flow
+        linear: bool,
+    },
+    /// Run a ROM in the console debugger
+    Debug {
+        /// Path of the ROM to debug
+        rom: PathBuf,
+        /// Behaviour of the ambiguous instructions
+        quirks: Quirks,
+        /// Seed of the random number generator
+        seed: Option<u64>,
+        /// Number of instructions executed per second, which paces the timers
+        cpu_hz: u32,
+    },
+    /// Assemble a source file into a ROM
+    Asm {
+        /// Path of the source to assemble
+        source: PathBuf,
+        /// Path of the ROM to write
+        output: PathBuf,
+    },
+}
+
+/// Options of the `Run` command
+pub struct Options {
+    /// Path of the ROM to run
+    pub rom: PathBuf,
+    /// Behaviour of the ambiguous instructions
+    pub quirks: Quirks,
+    /// Key mapping preset name or key mapping file path
+    pub keymap: Option<String>,
+    /// Path of the WAV file recording the sound
+    pub wav: Option<PathBuf>,
+    /// Seed of the random number generator
+    pub seed: Option<u64>,
+    /// Path of the movie file to record
+    pub record: Option<PathBuf>,
+    /// Path of the movie file to replay
+    pub replay: Option<PathBuf>,
+    /// Watched memory ranges
+    pub watchpoints: Vec<Watchpoint>,
+    /// Local port of the GDB server
+    pub gdb_port: Option<u16>,
+    /// Path of the execution trace file, `-` for the standard output
+    pub trace: Option<PathBuf>,
+    /// Instructions logged in the execution trace
+    pub trace_filter: TraceFilter,
+    /// Headless frontend settings, None to run in a window
+    pub headless: Option<HeadlessConfig>,
+    /// Window frontend settings
+    pub config: RunConfig,
+}
+
+/// Parses the command line arguments
+///
+/// # Parameters
+///
+/// - `args`: The command line arguments, without the program name
+///
+/// # Returns
+///
+/// The requested `Command`, or an error message if the arguments are invalid
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some("disasm") => {
+            args.next();
+            parse_disasm(args)
+        }
+        Some("asm") => {
+            args.next();
+            parse_asm(args)
+        }
+        Some("debug") => {
+            args.next();
+            parse_debug(args)
+        }
+        Some("run") => {
+            args.next();
+            parse_run(args)
+        }
+        _ => parse_run(args),
+    }
+}
+
+/// Parses the arguments of the `disasm` command
+///
+/// # Parameters
+///
+/// - `args`: The command arguments, without the command name
+///
+/// # Returns
+///
+/// The `Disasm` command, or an error message if the arguments are invalid
+fn parse_disasm<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut rom = None;
+    let mut linear = false;
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--linear" => linear = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    match rom {
+        Some(rom) => Ok(Command::Disasm { rom, linear }),
+        None => Err(String::from("missing ROM path")),
+    }
+}
+
+/// Parses the arguments of the `asm` command
+///
+/// # Parameters
+///
+/// - `args`: The command arguments, without the command name
+///
+/// # Returns
+///
+/// The `Asm` command, or an error message if the arguments are invalid
+fn parse_asm<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut source = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-o" | "--output" => output = Some(parse_value(&arg, args.next())?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    match source {
+        Some(source) => {
+            let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+            Ok(Command::Asm { source, output })
+        }
+        None => Err(String::from("missing source path")),
+    }
+}
+
+/// Parses the arguments of the `debug` command
+///
+/// # Parameters
+///
+/// - `args`: The command arguments, without the command name
+///
+/// # Returns
+///
+/// The `Debug` command, or an error message if the arguments are invalid
+fn parse_debug<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut rom = None;
+    let mut quirks = Quirks::default();
+    let mut seed = None;
+    let mut cpu_hz = RunConfig::default().cpu_hz;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--cpu-hz" => cpu_hz = parse_value(&arg, args.next())?,
+            "--quirks" => quirks = parse_value(&arg, args.next())?,
+            "--seed" => seed = Some(parse_value(&arg, args.next())?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    if cpu_hz == 0 {
+        return Err(String::from("'--cpu-hz' must be greater than 0"));
+    }
+    match rom {
+        Some(rom) => Ok(Command::Debug {
+            rom,
+            quirks,
+            seed,
+            cpu_hz,
+        }),
+        None => Err(String::from("missing ROM path")),
+    }
+}
+
+/// Parses the arguments of the `Run` command
+///
+/// # Parameters
+///
+/// - `args`: The command line arguments
+///
+/// # Returns
+///
+/// The `Run` command, or an error message if the arguments are invalid
+fn parse_run<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut rom = None;
+    let mut quirks = Quirks::default();
+    let mut keymap = None;
+    let mut wav = None;
+    let mut seed = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut watchpoints = Vec::new();
+    let mut gdb_port = None;
+    let mut trace = None;
+    let mut trace_filter = TraceFilter::default();
+    let mut headless = false;
+    let mut headless_config = HeadlessConfig::default();
+    let mut config = RunConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--cpu-hz" => config.cpu_hz = parse_value(&arg, args.next())?,
+            "--scale" => config.scale = parse_value(&arg, args.next())?,
+            "--paused" => config.paused = true,
+            "--quirks" => quirks = parse_value(&arg, args.next())?,
+            "--keymap" => keymap = Some(parse_value(&arg, args.next())?),
+            "--rewind" => {
+                let mebibytes: usize = parse_value(&arg, args.next())?;
+                config.rewind_budget = mebibytes.saturating_mul(1024 * 1024);
+            }
+            "--seed" => seed = Some(parse_value(&arg, args.next())?),
+            "--record" => record = Some(parse_value(&arg, args.next())?),
+            "--replay" => replay = Some(parse_value(&arg, args.next())?),
+            "--wav" => wav = Some(parse_value(&arg, args.next())?),
+            "--watch" => watchpoints.push(parse_value(&arg, args.next())?),
+            "--watch-pause" => config.pause_on_watch = true,
+            "--gdb" => gdb_port = Some(parse_value(&arg, args.next())?),
+            "--trace" => trace = Some(parse_value(&arg, args.next())?),
+            "--trace-pc" => trace_filter.pc_range = Some(parse_value(&arg, args.next())?),
+            "--trace-ops" => trace_filter.families = Some(parse_value(&arg, args.next())?),
+            "--trace-limit" => trace_filter.limit = Some(parse_value(&arg, args.next())?),
+            "--headless" => headless = true,
+            "--frames" => headless_config.frames = parse_value(&arg, args.next())?,
+            "--screenshot" => headless_config.screenshot = Some(parse_value(&arg, args.next())?),
+            "--every" => headless_config.every = Some(parse_value(&arg, args.next())?),
+            "--palette" => headless_config.palette = parse_value(&arg, args.next())?,
+            "--keys" => headless_config.keys = parse_value(&arg, args.next())?,
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    if config.cpu_hz == 0 {
+        return Err(String::from("'--cpu-hz' must be greater than 0"));
+    }
+    if config.scale == 0 {
+        return Err(String::from("'--scale' must be greater than 0"));
+    }
+
+    if trace.is_none() && trace_filter != TraceFilter::default() {
+        return Err(String::from(
+            "'--trace-pc', '--trace-ops' and '--trace-limit' require '--trace'",
+        ));
+    }
+
+    if headless_config.every == Some(0) {
+        return Err(String::from("'--every' must be greater than 0"));
+    }
+    if headless_config.every.is_some() && headless_config.screenshot.is_none() {
+        return Err(String::from("'--every' requires '--screenshot'"));
+    }
+    if headless && gdb_port.is_some() {
+        return Err(String::from("'--gdb' cannot be used with '--headless'"));
+    }
+    let headless = if headless {
+        headless_config.cpu_hz = config.cpu_hz;
+        headless_config.scale = config.scale;
+        Some(headless_config)
+    } else if headless_config != HeadlessConfig::default() {
+        return Err(String::from(
+            "'--frames', '--screenshot', '--every', '--palette' and '--keys' require \
+             '--headless'",
+        ));
+    } else {
+        None
+    };
+
+    if record.is_some() && replay.is_some() {
+        return Err(String::from(
+            "'--record' and '--replay' cannot be used together",
+        ));
+    }
+
+    match rom {
+        Some(rom) => Ok(Command::Run(Box::new(Options {
+            rom,
+            quirks,
+            keymap,
+            wav,
+            seed,
+            record,
+            replay,
+            watchpoints,
+            gdb_port,
+            trace,
+            trace_filter,
+            headless,
+            config,
+        }))),
+        None => Err(String::from("missing ROM path")),
+    }
+}
+
+/// Parses the value of a command line option
+///
+/// # Parameters
+///
+/// - `option`: The name of the option
+/// - `value`: The value following the option, if any
+///
+/// # Returns
+///
+/// The parsed value, or an error message if it is missing or invalid
+fn parse_value<T>(option: &str, value: Option<String>) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = value.ok_or_else(|| format!("missing value for '{}'", option))?;
+    value
+        .parse()
+        .map_err(|error| format!("invalid value '{}' for '{}': {}", value, option, error))
+}
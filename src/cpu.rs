@@ -1,12 +1,108 @@
 use crate::bus::Bus;
-use rand;
-use rand::distributions::{Distribution, Uniform};
-use rand::thread_rng;
+use crate::instruction::{self, Instruction};
+use crate::memory::{self, AddressError};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::Rng;
+use crate::state::{StateError, StateReader, StateWriter};
+use std::error;
+use std::fmt;
 
 /// Address at which ROMS are loaded in memory
 pub const PROGRAM_START: u16 = 0x200;
 
+/// Error raised by the CPU while executing an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /// The opcode does not match any known instruction
+    UnknownOpcode { pc: u16, opcode: u16 },
+    /// A `CALL` was executed with a full stack
+    StackOverflow { pc: u16, opcode: u16 },
+    /// A `RET` was executed with an empty stack
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// The instruction accessed an address outside of the memory
+    InvalidAddress { pc: u16, opcode: u16, address: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown instruction {:#06X} at {:#05X}", opcode, pc)
+            }
+            CpuError::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow on {:#06X} at {:#05X}", opcode, pc)
+            }
+            CpuError::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow on {:#06X} at {:#05X}", opcode, pc)
+            }
+            CpuError::InvalidAddress {
+                pc,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "invalid memory access to {:#06X} by {:#06X} at {:#05X}",
+                address, opcode, pc
+            ),
+        }
+    }
+}
+
+impl error::Error for CpuError {}
+
+/// Reason why an instruction failed, before the PC and opcode are attached to it
+enum Fault {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    InvalidAddress(u16),
+}
+
+impl Fault {
+    /// Builds the `CpuError` raised by the instruction `opcode` located at `pc`
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the faulty instruction
+    /// - `opcode`: The faulty instruction
+    ///
+    /// # Returns
+    ///
+    /// The matching `CpuError`
+    fn at(self, pc: u16, opcode: u16) -> CpuError {
+        match self {
+            Fault::UnknownOpcode => CpuError::UnknownOpcode { pc, opcode },
+            Fault::StackOverflow => CpuError::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => CpuError::StackUnderflow { pc, opcode },
+            Fault::InvalidAddress(address) => CpuError::InvalidAddress {
+                pc,
+                opcode,
+                address,
+            },
+        }
+    }
+}
+
+impl From<AddressError> for Fault {
+    fn from(error: AddressError) -> Fault {
+        Fault::InvalidAddress(error.address)
+    }
+}
+
+/// Progress of the Fx0A instruction, which waits for a key event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyWait {
+    /// No key is being waited for
+    Idle,
+    /// Waiting for a key press
+    Press,
+    /// Waiting for the release of `key` (`key_wait_on_release` quirk)
+    Release { key: u8 },
+}
+
 /// Chip8 CPU struct
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone)]
 pub struct CPU {
     /// CPU registers v0..vf
     vx: [u8; 16],
@@ -19,7 +115,21 @@ pub struct CPU {
     /// Stack
     stack: [u16; 16],
     /// Random number generator
-    rng: rand::rngs::ThreadRng,
+    rng: Rng,
+    /// RPL user flags (SUPER-CHIP)
+    rpl: [u8; 16],
+    /// Has the program exited (SUPER-CHIP 00FD)?
+    exited: bool,
+    /// Progress of the Fx0A key wait
+    key_wait: KeyWait,
+    /// Behaviour of the ambiguous instructions
+    quirks: Quirks,
+}
+
+impl Default for CPU {
+    fn default() -> CPU {
+        CPU::new()
+    }
 }
 
 impl CPU {
@@ -29,14 +139,176 @@ impl CPU {
     ///
     /// A new `CPU` struct.
     pub fn new() -> CPU {
+        CPU::with_quirks(Quirks::default())
+    }
+
+    /// Creates and returns a new `CPU` struct running ambiguous instructions with the given
+    /// quirks.
+    ///
+    /// # Parameters
+    ///
+    /// - `quirks`: The behaviour of the ambiguous instructions
+    ///
+    /// # Returns
+    ///
+    /// A new `CPU` struct.
+    pub fn with_quirks(quirks: Quirks) -> CPU {
         CPU {
             vx: [0; 16],
             i: 0,
             pc: PROGRAM_START,
             sp: 0,
             stack: [0; 16],
-            rng: thread_rng(),
+            rng: Rng::from_entropy(),
+            rpl: [0; 16],
+            exited: false,
+            key_wait: KeyWait::Idle,
+            quirks,
+        }
+    }
+
+    /// Has the program exited with the SUPER-CHIP `EXIT` instruction?
+    ///
+    /// # Returns
+    ///
+    /// `true` if the program has exited, in which case cycles do nothing
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Gets the program counter
+    ///
+    /// # Returns
+    ///
+    /// The address of the next instruction to run
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Sets the program counter
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the next instruction to run
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Gets the memory address register
+    ///
+    /// # Returns
+    ///
+    /// The value of I
+    pub fn get_i(&self) -> u16 {
+        self.i
+    }
+
+    /// Sets the memory address register
+    ///
+    /// # Parameters
+    ///
+    /// - `i`: The value of I
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    /// Gets the value of a register
+    ///
+    /// # Parameters
+    ///
+    /// - `x`: The register id, from 0x0 to 0xF; only the low nibble is used
+    ///
+    /// # Returns
+    ///
+    /// The value of Vx
+    pub fn get_register(&self, x: u8) -> u8 {
+        self.read_register(x & 0xF)
+    }
+
+    /// Sets the value of a register
+    ///
+    /// # Parameters
+    ///
+    /// - `x`: The register id, from 0x0 to 0xF; only the low nibble is used
+    /// - `value`: The value of Vx
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.write_register(x & 0xF, value);
+    }
+
+    /// Gets the return addresses pushed on the stack by `CALL`
+    ///
+    /// # Returns
+    ///
+    /// The stack, from the oldest to the latest return address: its length is the stack pointer
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    /// Replaces the random number generator of the `RND` instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `rng`: The random number generator
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    /// Writes the CPU state in a save state: registers, stack, RPL flags, Fx0A progress, random
+    /// number generator and quirks
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The save state writer
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vx);
+        writer.write_u16(self.i);
+        writer.write_u16(self.pc);
+        writer.write_u8(self.sp);
+        for address in &self.stack {
+            writer.write_u16(*address);
+        }
+        writer.write_bytes(&self.rpl);
+        writer.write_bool(self.exited);
+        match self.key_wait {
+            KeyWait::Idle => writer.write_bytes(&[0, 0]),
+            KeyWait::Press => writer.write_bytes(&[1, 0]),
+            KeyWait::Release { key } => writer.write_bytes(&[2, key]),
         }
+        writer.write_u64(self.rng.get_state());
+        self.quirks.save_state(writer);
+    }
+
+    /// Restores the CPU state from a save state written by `save_state`
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The save state reader
+    ///
+    /// # Returns
+    ///
+    /// A `StateError` if the save state is invalid, in which case the CPU is partially restored
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.vx.copy_from_slice(reader.read_bytes(16)?);
+        self.i = reader.read_u16()?;
+        self.pc = reader.read_u16()?;
+        self.sp = reader.read_u8()?;
+        if self.sp as usize > self.stack.len() {
+            return Err(StateError::InvalidValue("stack pointer"));
+        }
+        for address in self.stack.iter_mut() {
+            *address = reader.read_u16()?;
+        }
+        self.rpl.copy_from_slice(reader.read_bytes(16)?);
+        self.exited = reader.read_bool("exit flag")?;
+        self.key_wait = match (reader.read_u8()?, reader.read_u8()?) {
+            (0, _) => KeyWait::Idle,
+            (1, _) => KeyWait::Press,
+            (2, key) if key <= 0xF => KeyWait::Release { key },
+            _ => return Err(StateError::InvalidValue("key wait state")),
+        };
+        self.rng = Rng::from_seed(reader.read_u64()?);
+        self.quirks = Quirks::load_state(reader)?;
+        Ok(())
     }
 
     /// Performs one CPU cycle : fetches / decodes / runs an instruction
@@ -44,9 +316,23 @@ impl CPU {
     /// # Parameter
     ///
     /// - `bus`: The Chip8 bus
-    pub fn cycle(&mut self, bus: &mut Bus) {
-        let opcode: u16 = self.fetch(bus);
-        self.decode_and_run(opcode, bus);
+    ///
+    /// # Returns
+    ///
+    /// A `CpuError` if the instruction could not be executed
+    pub fn cycle(&mut self, bus: &mut Bus) -> Result<(), CpuError> {
+        if self.exited {
+            return Ok(());
+        }
+        let pc = self.pc;
+        let opcode = match self.fetch(bus) {
+            Ok(opcode) => opcode,
+            // The instruction could not be read: there is no opcode to report
+            Err(error) => return Err(Fault::from(error).at(pc, 0)),
+        };
+        bus.set_watch_instruction(pc, opcode);
+        self.decode_and_run(opcode, bus)
+            .map_err(|fault| fault.at(pc, opcode))
     }
 
     /// Fetches instruction from memory.
@@ -58,11 +344,11 @@ impl CPU {
     /// # Returns
     ///
     /// The instruction at PC
-    fn fetch(&mut self, bus: &mut Bus) -> u16 {
-        let mut opcode: u16 = (bus.mem_read_byte(self.pc) as u16) << 8;
-        opcode |= bus.mem_read_byte(self.pc + 1) as u16;
-        self.pc += 2;
-        opcode
+    fn fetch(&mut self, bus: &mut Bus) -> Result<u16, AddressError> {
+        let mut opcode: u16 = (bus.mem_peek_byte(self.pc)? as u16) << 8;
+        opcode |= bus.mem_peek_byte(self.pc.wrapping_add(1))? as u16;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(opcode)
     }
 
     /// Decodes and runs the input instruction
@@ -75,71 +361,71 @@ impl CPU {
     ///
     /// # Returns
     ///
-    /// A new `CPU` struct.
-    fn decode_and_run(&mut self, opcode: u16, bus: &mut Bus) {
-        let nnn: u16 = opcode & 0x0FFF;
-        let n: u8 = (opcode & 0x000F) as u8;
-        let x: u8 = ((opcode & 0x0F00) >> 8) as u8;
-        let y: u8 = ((opcode & 0x00F0) >> 4) as u8;
-        let kk: u8 = (opcode & 0x00FF) as u8;
-
-        // Decode and execute the instruction
-        match (opcode & 0xF000) >> 12 {
-            0x0 => match kk {
-                0xE0 => self.cls(bus),
-                0xEE => self.ret(),
-                _ => panic!("Unknown instruction {:#X}", opcode),
-            },
+    /// The `Fault` that prevented the instruction from running, if any
+    fn decode_and_run(&mut self, opcode: u16, bus: &mut Bus) -> Result<(), Fault> {
+        let instruction = instruction::decode(opcode).ok_or(Fault::UnknownOpcode)?;
 
-            0x1 => self.jp(nnn),
-            0x2 => self.call(nnn),
-
-            0x3 => self.se_x_kk(x, kk),
-            0x4 => self.sne_x_kk(x, kk),
-            0x5 => self.se_x_y(x, y),
-            0x6 => self.ld_x_kk(x, kk),
-            0x7 => self.add_x_kk(x, kk),
-
-            0x8 => match n {
-                0x0 => self.ld_x_y(x, y),
-                0x1 => self.or_x_y(x, y),
-                0x2 => self.and_x_y(x, y),
-                0x3 => self.xor_x_y(x, y),
-                0x4 => self.add_x_y(x, y),
-                0x5 => self.sub_x_y(x, y),
-                0x6 => self.shr_x(x),
-                0x7 => self.subn_x_y(x, y),
-                0xE => self.shl_x(x),
-                _ => panic!("Unknown instruction {:#X}", opcode),
-            },
+        // Execute the instruction
+        match instruction {
+            Instruction::Cls => self.cls(bus),
+            Instruction::Ret => self.ret()?,
+            Instruction::Scd(n) => self.scd(n, bus),
+            Instruction::Scu(n) => self.scu(n, bus),
+            Instruction::Scr => self.scr(bus),
+            Instruction::Scl => self.scl(bus),
+            Instruction::Exit => self.exit(),
+            Instruction::Low => self.low(bus),
+            Instruction::High => self.high(bus),
 
-            0x9 => self.sne(x, y),
-            0xA => self.ld_i_nnn(nnn),
-            0xB => self.jp_0_nnn(nnn),
-            0xC => self.rnd_x_kk(x, kk),
-            0xD => self.drw(x, y, n, bus),
+            Instruction::Jp(nnn) => self.jp(nnn),
+            Instruction::Call(nnn) => self.call(nnn)?,
 
-            0xE => match kk {
-                0x9E => self.skp_x(x, bus),
-                0xA1 => self.sknp_x(x, bus),
-                _ => panic!("Unknown instruction {:#X}", opcode),
-            },
+            Instruction::SeByte(x, kk) => self.se_x_kk(x, kk, bus),
+            Instruction::SneByte(x, kk) => self.sne_x_kk(x, kk, bus),
+            Instruction::SeReg(x, y) => self.se_x_y(x, y, bus),
+            Instruction::StoreRange(x, y) => self.ld_i_x_y(x, y, bus)?,
+            Instruction::LoadRange(x, y) => self.ld_x_y_i(x, y, bus)?,
+            Instruction::LdByte(x, kk) => self.ld_x_kk(x, kk),
+            Instruction::AddByte(x, kk) => self.add_x_kk(x, kk),
 
-            0xF => match kk {
-                0x07 => self.ld_x_dt(x, bus),
-                0x0A => self.ld_x_press(x, bus),
-                0x15 => self.ld_dt_x(x, bus),
-                0x18 => self.ld_st_x(x, bus),
-                0x1E => self.add_i_x(x),
-                0x29 => self.ld_f_x(x),
-                0x33 => self.ld_b_x(x, bus),
-                0x55 => self.ld_i_x(x, bus),
-                0x65 => self.ld_x_i(x, bus),
-                _ => panic!("Unknown instruction {:#X}", opcode),
-            },
+            Instruction::LdReg(x, y) => self.ld_x_y(x, y),
+            Instruction::Or(x, y) => self.or_x_y(x, y),
+            Instruction::And(x, y) => self.and_x_y(x, y),
+            Instruction::Xor(x, y) => self.xor_x_y(x, y),
+            Instruction::AddReg(x, y) => self.add_x_y(x, y),
+            Instruction::Sub(x, y) => self.sub_x_y(x, y),
+            Instruction::Shr(x, y) => self.shr_x(x, y),
+            Instruction::Subn(x, y) => self.subn_x_y(x, y),
+            Instruction::Shl(x, y) => self.shl_x(x, y),
+
+            Instruction::SneReg(x, y) => self.sne(x, y, bus),
+            Instruction::LdI(nnn) => self.ld_i_nnn(nnn),
+            Instruction::JpV0(nnn) => self.jp_0_nnn(nnn),
+            Instruction::Rnd(x, kk) => self.rnd_x_kk(x, kk),
+            Instruction::Drw(x, y, n) => self.drw(x, y, n, bus)?,
 
-            _ => panic!("Unknown instruction {:#X}", opcode),
+            Instruction::Skp(x) => self.skp_x(x, bus),
+            Instruction::Sknp(x) => self.sknp_x(x, bus),
+
+            Instruction::LdILong => self.ld_i_nnnn(bus)?,
+            Instruction::Plane(n) => self.plane(n, bus),
+            Instruction::Audio => self.audio(bus)?,
+            Instruction::LdVxDt(x) => self.ld_x_dt(x, bus),
+            Instruction::LdVxK(x) => self.ld_x_press(x, bus),
+            Instruction::LdDtVx(x) => self.ld_dt_x(x, bus),
+            Instruction::LdStVx(x) => self.ld_st_x(x, bus),
+            Instruction::AddI(x) => self.add_i_x(x),
+            Instruction::LdF(x) => self.ld_f_x(x),
+            Instruction::LdHf(x) => self.ld_hf_x(x),
+            Instruction::LdB(x) => self.ld_b_x(x, bus)?,
+            Instruction::Pitch(x) => self.pitch_x(x, bus),
+            Instruction::Store(x) => self.ld_i_x(x, bus)?,
+            Instruction::Load(x) => self.ld_x_i(x, bus)?,
+            Instruction::StoreFlags(x) => self.ld_r_x(x),
+            Instruction::LoadFlags(x) => self.ld_x_r(x),
         }
+
+        Ok(())
     }
 
     /// CLS - 00E0: Clear the display.
@@ -147,13 +433,74 @@ impl CPU {
         bus.clear_display();
     }
 
+    /// SCU - 00Dn: Scroll display n lines up (XO-CHIP).
+    fn scu(&mut self, n: u8, bus: &mut Bus) {
+        bus.scroll_display_up(n as usize);
+    }
+
+    /// SCD - 00Cn: Scroll display n lines down (SUPER-CHIP).
+    fn scd(&mut self, n: u8, bus: &mut Bus) {
+        bus.scroll_display_down(n as usize);
+    }
+
+    /// SCR - 00FB: Scroll display 4 pixels right (SUPER-CHIP).
+    fn scr(&mut self, bus: &mut Bus) {
+        let n = self.horizontal_scroll_amount(bus);
+        bus.scroll_display_right(n);
+    }
+
+    /// SCL - 00FC: Scroll display 4 pixels left (SUPER-CHIP).
+    fn scl(&mut self, bus: &mut Bus) {
+        let n = self.horizontal_scroll_amount(bus);
+        bus.scroll_display_left(n);
+    }
+
+    /// Gets the number of pixels scrolled by 00FB / 00FC: 4, or 2 in the 64x32 mode with the
+    /// `halve_lores_scroll` quirk
+    ///
+    /// # Parameters
+    ///
+    /// - `bus`: The Chip8 bus
+    ///
+    /// # Returns
+    ///
+    /// The number of pixels to scroll
+    fn horizontal_scroll_amount(&self, bus: &Bus) -> usize {
+        if self.quirks.halve_lores_scroll && !bus.is_display_hires() {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// EXIT - 00FD: Exit the interpreter (SUPER-CHIP).
+    /// The CPU stops executing instructions.
+    fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    /// LOW - 00FE: Disable high resolution mode (SUPER-CHIP).
+    /// The display is switched to 64x32 pixels and cleared.
+    fn low(&mut self, bus: &mut Bus) {
+        bus.set_display_hires(false);
+    }
+
+    /// HIGH - 00FF: Enable high resolution mode (SUPER-CHIP).
+    /// The display is switched to 128x64 pixels and cleared.
+    fn high(&mut self, bus: &mut Bus) {
+        bus.set_display_hires(true);
+    }
+
     /// RET - 00EE:  Return from a subroutine.
     /// The interpreter sets the program counter to the address at the top of the stack,
     /// then subtracts 1 from the stack pointer.
-    fn ret(&mut self) {
-        // TODO check if "-1" is needed
+    fn ret(&mut self) -> Result<(), Fault> {
+        if self.sp == 0 {
+            return Err(Fault::StackUnderflow);
+        }
         self.pc = self.stack[(self.sp - 1) as usize];
         self.sp -= 1;
+        Ok(())
     }
 
     /// JP - 1nnn: Jump to location nnn.
@@ -165,38 +512,61 @@ impl CPU {
     /// CALL - 2nnn: Call subroutine at nnn.
     /// The interpreter increments the stack pointer, then puts the current PC on the top of
     /// the stack. The PC is then set to nnn.
-    fn call(&mut self, nnn: u16) {
-        // TODO check if increment before or after
+    fn call(&mut self, nnn: u16) -> Result<(), Fault> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(Fault::StackOverflow);
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
         self.pc = nnn;
+        Ok(())
     }
 
     /// SE - 3xkk: Skip next instruction if Vx = kk.
     /// The interpreter compares register Vx to kk, and if they are equal,
     /// increments the program counter by 2.
-    fn se_x_kk(&mut self, x: u8, kk: u8) {
+    fn se_x_kk(&mut self, x: u8, kk: u8, bus: &Bus) {
         if self.read_register(x) == kk {
-            self.pc += 2;
+            self.skip_next_instruction(bus);
         }
     }
 
     /// SNE - 4xkk: Skip next instruction if Vx != kk.
     /// The interpreter compares register Vx to kk, and if they are not equal,
     /// increments the program counter by 2.
-    fn sne_x_kk(&mut self, x: u8, kk: u8) {
+    fn sne_x_kk(&mut self, x: u8, kk: u8, bus: &Bus) {
         if self.read_register(x) != kk {
-            self.pc += 2;
+            self.skip_next_instruction(bus);
         }
     }
 
     /// SE - 5xy0: Skip next instruction if Vx = Vy.
     /// The interpreter compares register Vx to register Vy, and if they are equal,
     /// increments the program counter by 2.
-    fn se_x_y(&mut self, x: u8, y: u8) {
+    fn se_x_y(&mut self, x: u8, y: u8, bus: &Bus) {
         if self.read_register(x) == self.read_register(y) {
-            self.pc += 2;
+            self.skip_next_instruction(bus);
+        }
+    }
+
+    /// LD - 5xy2: Store registers Vx through Vy in memory starting at location I (XO-CHIP).
+    /// The registers are stored in reverse order if x > y. I is not modified.
+    fn ld_i_x_y(&mut self, x: u8, y: u8, bus: &mut Bus) -> Result<(), Fault> {
+        for (offset, v_index) in register_range(x, y).enumerate() {
+            let vx = self.read_register(v_index);
+            bus.mem_write_byte(self.i.wrapping_add(offset as u16), vx)?;
         }
+        Ok(())
+    }
+
+    /// LD - 5xy3: Read registers Vx through Vy from memory starting at location I (XO-CHIP).
+    /// The registers are read in reverse order if x > y. I is not modified.
+    fn ld_x_y_i(&mut self, x: u8, y: u8, bus: &mut Bus) -> Result<(), Fault> {
+        for (offset, v_index) in register_range(x, y).enumerate() {
+            let vx = bus.mem_read_byte(self.i.wrapping_add(offset as u16))?;
+            self.write_register(v_index, vx);
+        }
+        Ok(())
     }
 
     /// LD - 6xkk: Set Vx = kk.
@@ -222,24 +592,37 @@ impl CPU {
     /// Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
     /// A bitwise OR compares the corresponding bits from two values,
     /// and if either bit is 1, then the same bit in the result is also 1. Otherwise, it is 0.
+    /// With the `logic_resets_vf` quirk, VF is set to 0.
     fn or_x_y(&mut self, x: u8, y: u8) {
         self.write_register(x, self.read_register(x) | self.read_register(y));
+        self.reset_vf_after_logic();
     }
 
     /// AND - 8xy2: Set Vx = Vx AND Vy.
     /// Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
     /// A bitwise AND compares the corresponding bits from two values, and if both bits are 1,
     /// then the same bit in the result is also 1. Otherwise, it is 0.
+    /// With the `logic_resets_vf` quirk, VF is set to 0.
     fn and_x_y(&mut self, x: u8, y: u8) {
         self.write_register(x, self.read_register(x) & self.read_register(y));
+        self.reset_vf_after_logic();
     }
 
     /// XOR - 8xy3: Set Vx = Vx XOR Vy.
     /// Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
     /// An exclusive OR compares the corresponding bits from two values, and if the bits are not
     /// both the same, then the corresponding bit in the result is set to 1. Otherwise, it is 0.
+    /// With the `logic_resets_vf` quirk, VF is set to 0.
     fn xor_x_y(&mut self, x: u8, y: u8) {
         self.write_register(x, self.read_register(x) ^ self.read_register(y));
+        self.reset_vf_after_logic();
+    }
+
+    /// Resets VF after a logic operation, if the `logic_resets_vf` quirk is enabled
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.write_register(0xF, 0);
+        }
     }
 
     /// ADD - 8xy4: Set Vx = Vx + Vy, set VF = carry.
@@ -259,49 +642,51 @@ impl CPU {
     }
 
     /// SUB - 8xy5: Set Vx = Vx - Vy, set VF = NOT borrow.
-    /// If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx,
+    /// If Vx >= Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx,
     /// and the results stored in Vx.
     fn sub_x_y(&mut self, x: u8, y: u8) {
-        let vx = self.read_register(x) as i8;
-        let vy = self.read_register(y) as i8;
-        let sub = vx - vy;
-        self.write_register(x, sub as u8);
-        if sub > 0 {
-            self.write_register(0xF, 1);
-        } else {
+        let vx = self.read_register(x);
+        let vy = self.read_register(y);
+        let (sub, borrow) = vx.overflowing_sub(vy);
+        self.write_register(x, sub);
+        if borrow {
             self.write_register(0xF, 0);
+        } else {
+            self.write_register(0xF, 1);
         }
     }
 
     /// SHR - 8xy6: Set Vx = Vx SHR 1.
     /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0.
     /// Then Vx is divided by 2.
-    fn shr_x(&mut self, x: u8) {
-        let vx = self.read_register(x);
+    /// With the `shift_uses_vy` quirk, Vy is shifted instead of Vx and the result stored in Vx.
+    fn shr_x(&mut self, x: u8, y: u8) {
+        let vx = self.read_register(if self.quirks.shift_uses_vy { y } else { x });
         self.write_register(0xF, vx & 0x1);
         self.write_register(x, vx >> 1);
     }
 
     /// SUBN - 8xy7: Set Vx = Vy - Vx, set VF = NOT borrow.
-    /// If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and
+    /// If Vy >= Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and
     /// the results stored in Vx.
     fn subn_x_y(&mut self, x: u8, y: u8) {
-        let vx = self.read_register(x) as i8;
-        let vy = self.read_register(y) as i8;
-        let sub = vy - vx;
-        self.write_register(x, sub as u8);
-        if sub > 0 {
-            self.write_register(0xF, 1);
-        } else {
+        let vx = self.read_register(x);
+        let vy = self.read_register(y);
+        let (sub, borrow) = vy.overflowing_sub(vx);
+        self.write_register(x, sub);
+        if borrow {
             self.write_register(0xF, 0);
+        } else {
+            self.write_register(0xF, 1);
         }
     }
 
     /// SHL - 8xyE: Set Vx = Vx SHL 1.
     /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0.
     /// Then Vx is multiplied by 2.
-    fn shl_x(&mut self, x: u8) {
-        let vx = self.read_register(x);
+    /// With the `shift_uses_vy` quirk, Vy is shifted instead of Vx and the result stored in Vx.
+    fn shl_x(&mut self, x: u8, y: u8) {
+        let vx = self.read_register(if self.quirks.shift_uses_vy { y } else { x });
         self.write_register(0xF, (vx & 0x80) >> 7);
         self.write_register(x, vx << 1);
     }
@@ -309,9 +694,9 @@ impl CPU {
     /// SNE - 9xy0: Skip next instruction if Vx != Vy.
     /// The values of Vx and Vy are compared, and if they are not equal,
     /// the program counter is increased by 2.
-    fn sne(&mut self, x: u8, y: u8) {
+    fn sne(&mut self, x: u8, y: u8, bus: &Bus) {
         if self.read_register(x) != self.read_register(y) {
-            self.pc += 2;
+            self.skip_next_instruction(bus);
         }
     }
 
@@ -321,10 +706,23 @@ impl CPU {
         self.i = nnn;
     }
 
+    /// LD - F000 nnnn: Set I = nnnn (XO-CHIP).
+    /// The value of register I is set to the 16 bits address stored after the instruction.
+    fn ld_i_nnnn(&mut self, bus: &mut Bus) -> Result<(), Fault> {
+        self.i = self.fetch(bus)?;
+        Ok(())
+    }
+
     /// JP - Bnnn: Jump to location nnn + V0.
     /// The program counter is set to nnn plus the value of V0.
+    /// With the `jump_uses_vx` quirk, the instruction is read as Bxnn and Vx is used instead of V0.
     fn jp_0_nnn(&mut self, nnn: u16) {
-        self.pc = (self.read_register(0) as u16) + nnn;
+        let x = if self.quirks.jump_uses_vx {
+            (nnn >> 8) as u8
+        } else {
+            0
+        };
+        self.pc = (self.read_register(x) as u16) + nnn;
     }
 
     /// RND - Cxkk : Set Vx = random byte AND kk.
@@ -332,8 +730,7 @@ impl CPU {
     /// the value kk. The results are stored in Vx. See instruction 8xy2 for more information on
     /// AND.
     fn rnd_x_kk(&mut self, x: u8, kk: u8) {
-        let range = Uniform::from(0..=255);
-        let rnd_byte = range.sample(&mut self.rng);
+        let rnd_byte = self.rng.next_u8();
         self.write_register(x, rnd_byte & kk);
     }
 
@@ -343,24 +740,54 @@ impl CPU {
     /// Sprites are XORed onto the existing screen. If this causes any pixels to be erased,
     /// VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it
     /// is outside the coordinates of the display, it wraps around to the opposite side of the
-    /// screen.
-    fn drw(&mut self, x: u8, y: u8, n: u8, bus: &mut Bus) {
+    /// screen. With the `clip_sprites` quirk, the parts outside of the display are not drawn.
+    /// With the `count_collision_rows` quirk, VF is set in the 128x64 mode to the number of
+    /// sprite rows that collided or were clipped off the bottom of the display (SUPER-CHIP 1.1).
+    /// DRW - Dxy0 (SUPER-CHIP) displays a 16x16 sprite, made of 32 bytes (2 bytes per row).
+    /// When both planes are selected (XO-CHIP), the sprite of the second plane follows the one
+    /// of the first plane in memory.
+    fn drw(&mut self, x: u8, y: u8, n: u8, bus: &mut Bus) -> Result<(), Fault> {
         let mut value;
-        let vx = self.read_register(x);
-        let vy = self.read_register(y);
-        let mut erased = false;
-        for index in 0..n {
-            value = bus.mem_read_byte(self.i + index as u16);
-            if bus.draw_byte(vx as usize, (vy + index) as usize, value) {
-                erased = true;
+        let vx = self.read_register(x) as usize % bus.get_display_width();
+        let vy = self.read_register(y) as usize % bus.get_display_height();
+        let clip = self.quirks.clip_sprites;
+        let (rows, bytes_per_row) = if n == 0 { (16, 2) } else { (n as usize, 1) };
+        let selected_planes = bus.get_selected_planes();
+        let mut collided_rows = [false; 16];
+        // Each selected plane is drawn with its own sprite, stored after the previous one
+        let mut address = self.i;
+        for plane in [0x1, 0x2]
+            .iter()
+            .filter(|&plane| selected_planes & plane != 0)
+        {
+            for (row, collided) in collided_rows.iter_mut().enumerate().take(rows) {
+                for byte in 0..bytes_per_row {
+                    value = bus.mem_read_byte(address)?;
+                    address = address.wrapping_add(1);
+                    if bus.draw_byte(vx + 8 * byte, vy + row, value, clip, *plane) {
+                        *collided = true;
+                    }
+                }
             }
         }
 
-        if erased {
-            self.write_register(0xF, 0x1);
+        let collisions = if self.quirks.count_collision_rows && bus.is_display_hires() {
+            let clipped_rows = if clip {
+                (vy + rows).saturating_sub(bus.get_display_height())
+            } else {
+                0
+            };
+            let visible_rows = rows - clipped_rows;
+            let collided = collided_rows[..visible_rows]
+                .iter()
+                .filter(|&&collided| collided)
+                .count();
+            (collided + clipped_rows) as u8
         } else {
-            self.write_register(0xF, 0x0);
-        }
+            collided_rows.contains(&true) as u8
+        };
+        self.write_register(0xF, collisions);
+        Ok(())
     }
 
     /// SKP - Ex9E : Skip next instruction if key with the value of Vx is pressed.
@@ -369,7 +796,7 @@ impl CPU {
     fn skp_x(&mut self, x: u8, bus: &Bus) {
         let vx = self.read_register(x);
         if bus.is_key_pressed(vx) {
-            self.pc += 2;
+            self.skip_next_instruction(bus);
         }
     }
 
@@ -379,10 +806,27 @@ impl CPU {
     fn sknp_x(&mut self, x: u8, bus: &Bus) {
         let vx = self.read_register(x);
         if !bus.is_key_pressed(vx) {
-            self.pc += 2;
+            self.skip_next_instruction(bus);
         }
     }
 
+    /// PLANE - Fn01 : Select the drawing planes n (XO-CHIP).
+    /// The bit mask n selects the planes affected by CLS, DRW and the scroll instructions.
+    fn plane(&mut self, n: u8, bus: &mut Bus) {
+        bus.select_planes(n);
+    }
+
+    /// AUDIO - F002 : Load the audio pattern buffer from memory location I (XO-CHIP).
+    /// The 16 bytes starting at I are copied into the audio pattern buffer.
+    fn audio(&mut self, bus: &mut Bus) -> Result<(), Fault> {
+        let mut pattern = [0; 16];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = bus.mem_read_byte(self.i.wrapping_add(offset as u16))?;
+        }
+        bus.set_audio_pattern(pattern);
+        Ok(())
+    }
+
     /// LD - Fx07 : Set Vx = delay timer value.
     /// The value of DT is placed into Vx.
     fn ld_x_dt(&mut self, x: u8, bus: &Bus) {
@@ -391,9 +835,41 @@ impl CPU {
 
     /// LD - Fx0A : Wait for a key press, store the value of the key in Vx.
     /// All execution stops until a key is pressed, then the value of that key is stored in Vx.
-    fn ld_x_press(&mut self, x: u8, bus: &Bus) {
-        if let Some(key) = bus.get_pressed_key() {
-            self.write_register(x, key);
+    /// Only keys pressed after the instruction starts are taken into account. With the
+    /// `key_wait_on_release` quirk, execution resumes when the pressed key is released.
+    /// The instruction is run again at each cycle until it completes, timers keep counting down
+    /// in the meantime.
+    fn ld_x_press(&mut self, x: u8, bus: &mut Bus) {
+        self.key_wait = match self.key_wait {
+            KeyWait::Idle => {
+                bus.clear_key_edges();
+                KeyWait::Press
+            }
+            KeyWait::Press => match bus.take_pressed_key() {
+                Some(key) if self.quirks.key_wait_on_release => KeyWait::Release { key },
+                Some(key) => {
+                    self.write_register(x, key);
+                    KeyWait::Idle
+                }
+                None => KeyWait::Press,
+            },
+            KeyWait::Release { key } => {
+                let mut released = false;
+                while let Some(released_key) = bus.take_released_key() {
+                    released |= released_key == key;
+                }
+                if released {
+                    self.write_register(x, key);
+                    KeyWait::Idle
+                } else {
+                    KeyWait::Release { key }
+                }
+            }
+        };
+
+        // Run the instruction again until a key event occurs
+        if self.key_wait != KeyWait::Idle {
+            self.pc = self.pc.wrapping_sub(2);
         }
     }
 
@@ -414,7 +890,7 @@ impl CPU {
     /// ADD - Fx1E : Set I = I + Vx.
     /// The values of I and Vx are added, and the results are stored in I.
     fn add_i_x(&mut self, x: u8) {
-        self.i += self.read_register(x) as u16;
+        self.i = self.i.wrapping_add(self.read_register(x) as u16);
     }
 
     /// LD - Fx29 : Set I = location of sprite for digit Vx.
@@ -422,37 +898,96 @@ impl CPU {
     /// value of Vx.
     fn ld_f_x(&mut self, x: u8) {
         let vx = self.read_register(x) as u16;
-        self.i = vx * 5;
+        self.i = memory::SPRITES_ADDRESS + (vx & 0xF) * memory::SPRITE_SIZE;
+    }
+
+    /// LD - Fx30 : Set I = location of big sprite for digit Vx (SUPER-CHIP).
+    /// The value of I is set to the location for the 8x10 hexadecimal sprite corresponding to
+    /// the value of Vx.
+    fn ld_hf_x(&mut self, x: u8) {
+        let vx = self.read_register(x) as u16;
+        self.i = memory::BIG_SPRITES_ADDRESS + (vx & 0xF) * memory::BIG_SPRITE_SIZE;
     }
 
     /// LD - Fx33 : Store BCD representation of Vx in memory locations I, I+1, and I+2.
     /// The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at
     /// location in I, the tens digit at location I+1, and the ones digit at location I+2.
-    fn ld_b_x(&mut self, x: u8, bus: &mut Bus) {
+    fn ld_b_x(&mut self, x: u8, bus: &mut Bus) -> Result<(), Fault> {
         let vx = self.read_register(x);
-        bus.mem_write_byte(self.i, vx / 100);
-        bus.mem_write_byte(self.i + 1, (vx % 100) / 10);
-        bus.mem_write_byte(self.i + 2, vx % 10);
+        bus.mem_write_byte(self.i, vx / 100)?;
+        bus.mem_write_byte(self.i.wrapping_add(1), (vx % 100) / 10)?;
+        bus.mem_write_byte(self.i.wrapping_add(2), vx % 10)?;
+        Ok(())
     }
 
     /// LD - Fx55 : Store registers V0 through Vx in memory starting at location I.
     /// The interpreter copies the values of registers V0 through Vx into memory, starting at the
-    /// address in I.
-    fn ld_i_x(&mut self, x: u8, bus: &mut Bus) {
+    /// address in I. I is then updated according to the `index_increment` quirk.
+    fn ld_i_x(&mut self, x: u8, bus: &mut Bus) -> Result<(), Fault> {
         for v_index in 0..=x {
             let vx = self.read_register(v_index);
-            bus.mem_write_byte(self.i + v_index as u16, vx);
+            bus.mem_write_byte(self.i.wrapping_add(v_index as u16), vx)?;
         }
+        self.increment_i_after_load_store(x);
+        Ok(())
     }
 
     /// LD - Fx65 : Read registers V0 through Vx from memory starting at location I.
     /// The interpreter reads values from memory starting at location I into registers V0
-    /// through Vx.
-    fn ld_x_i(&mut self, x: u8, bus: &Bus) {
+    /// through Vx. I is then updated according to the `index_increment` quirk.
+    fn ld_x_i(&mut self, x: u8, bus: &mut Bus) -> Result<(), Fault> {
         for v_index in 0..=x {
-            let vx = bus.mem_read_byte(self.i + v_index as u16);
+            let vx = bus.mem_read_byte(self.i.wrapping_add(v_index as u16))?;
             self.write_register(v_index, vx);
         }
+        self.increment_i_after_load_store(x);
+        Ok(())
+    }
+
+    /// PITCH - Fx3A : Set the audio pattern playback pitch = Vx (XO-CHIP).
+    fn pitch_x(&mut self, x: u8, bus: &mut Bus) {
+        bus.set_pitch(self.read_register(x));
+    }
+
+    /// LD - Fx75 : Store registers V0 through Vx in the RPL user flags (SUPER-CHIP).
+    fn ld_r_x(&mut self, x: u8) {
+        for v_index in 0..=x {
+            self.rpl[v_index as usize] = self.read_register(v_index);
+        }
+    }
+
+    /// LD - Fx85 : Read registers V0 through Vx from the RPL user flags (SUPER-CHIP).
+    fn ld_x_r(&mut self, x: u8) {
+        for v_index in 0..=x {
+            self.write_register(v_index, self.rpl[v_index as usize]);
+        }
+    }
+
+    /// Updates I after Fx55 / Fx65 according to the `index_increment` quirk
+    ///
+    /// # Parameters
+    ///
+    /// - `x`: The last register stored or loaded
+    fn increment_i_after_load_store(&mut self, x: u8) {
+        let increment = match self.quirks.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => x as u16,
+            IndexIncrement::ByXPlusOne => x as u16 + 1,
+        };
+        self.i = self.i.wrapping_add(increment);
+    }
+
+    /// Skips the next instruction, which is 4 bytes long if it is F000 nnnn (XO-CHIP).
+    /// F000 is not a CHIP-8 or SUPER-CHIP instruction, so this holds for every quirk profile,
+    /// like the decoding of the XO-CHIP instructions.
+    ///
+    /// # Parameters
+    ///
+    /// - `bus`: The Chip8 bus
+    fn skip_next_instruction(&mut self, bus: &Bus) {
+        let is_long = bus.mem_peek_byte(self.pc) == Ok(0xF0)
+            && bus.mem_peek_byte(self.pc.wrapping_add(1)) == Ok(0x00);
+        self.pc = self.pc.wrapping_add(if is_long { 4 } else { 2 });
     }
 
     /// Writes the given value in the requested register
@@ -478,3 +1013,19 @@ impl CPU {
         self.vx[x as usize]
     }
 }
+
+/// Iterates over the registers from Vx to Vy, in reverse order if x > y
+///
+/// # Parameters
+///
+/// - `x`: The first register id
+/// - `y`: The last register id
+///
+/// # Returns
+///
+/// The register ids, from x to y
+fn register_range(x: u8, y: u8) -> impl Iterator<Item = u8> {
+    let step: i8 = if x <= y { 1 } else { -1 };
+    let count = (x as i8 - y as i8).unsigned_abs() + 1;
+    (0..count).map(move |offset| (x as i8 + step * offset as i8) as u8)
+}
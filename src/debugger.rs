@@ -0,0 +1,953 @@
+use crate::chip8::Chip8;
+use crate::cpu::CpuError;
+use crate::instruction::{self, Instruction};
+use crate::watch::{WatchHit, Watchpoint};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Maximum number of instructions run by `continue`, `next` and `finish` before giving the
+/// prompt back, so that a program waiting for a key or looping forever cannot hang the console
+pub const MAX_RUN_INSTRUCTIONS: u32 = 10_000_000;
+
+/// Number of bytes printed by `x` without a count
+const DEFAULT_EXAMINE_BYTES: u32 = 16;
+
+/// Number of instructions printed by `list` without a count
+const DEFAULT_LIST_INSTRUCTIONS: u32 = 10;
+
+/// Console help
+const HELP: &str = "\
+Commands:
+  break <ADDR> [if <REG> <OP> <VALUE>]  Stop before running the instruction at ADDR, if the
+                                        condition holds: REG is V0-VF or I, OP is ==, !=, <,
+                                        <=, > or >= (alias: b)
+  delete [<ID>]                         Delete a breakpoint, or all of them (alias: d)
+  breakpoints                           List the breakpoints (alias: bl)
+  watch <ADDR>[-<END>][:r|w|rw|=<VALUE>]
+                                        Stop after an instruction reads or writes (or writes
+                                        VALUE to) the memory range [default: w] (alias: w)
+  unwatch [<ID>]                        Delete a watchpoint, or all of them
+  watchpoints                           List the watchpoints (alias: wl)
+  step [<COUNT>]                        Run COUNT instructions [default: 1] (alias: s)
+  next [<COUNT>]                        Like step, but run CALLs until they return (alias: n)
+  continue                              Run until a breakpoint (alias: c)
+  finish                                Run until the current subroutine returns (alias: f)
+  regs                                  Print the registers and timers (alias: r)
+  stack                                 Print the return addresses of the stack (alias: bt)
+  x <ADDR> [<COUNT>]                    Print COUNT bytes of memory [default: 16]
+  list [<ADDR>] [<COUNT>]               Disassemble COUNT instructions [default: from PC, 10]
+                                        (alias: l)
+  key <KEY> [up]                        Press (or release) a keypad key, from 0 to F
+  screen                                Print the display
+  help                                  Print this help (alias: h)
+  quit                                  Quit the debugger (alias: q)
+
+Addresses are hexadecimal. Counts and values are decimal, or hexadecimal with a '#' or '0x'
+prefix. An empty line repeats the last command.";
+
+/// Left operand of a breakpoint condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    /// Register Vx
+    Register(u8),
+    /// Memory address register I
+    I,
+}
+
+/// Comparison of a breakpoint condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Condition on a register value: `<operand> <comparison> <value>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Condition {
+    operand: Operand,
+    comparison: Comparison,
+    value: u16,
+}
+
+impl Condition {
+    /// Checks whether the condition holds
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The debugged machine
+    ///
+    /// # Returns
+    ///
+    /// true if the condition holds
+    fn holds(&self, chip8: &Chip8) -> bool {
+        let actual = match self.operand {
+            Operand::Register(x) => chip8.get_register(x) as u16,
+            Operand::I => chip8.get_i(),
+        };
+        match self.comparison {
+            Comparison::Equal => actual == self.value,
+            Comparison::NotEqual => actual != self.value,
+            Comparison::Less => actual < self.value,
+            Comparison::LessOrEqual => actual <= self.value,
+            Comparison::Greater => actual > self.value,
+            Comparison::GreaterOrEqual => actual >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operand {
+            Operand::Register(x) => write!(f, "V{:X}", x)?,
+            Operand::I => write!(f, "I")?,
+        }
+        let comparison = match self.comparison {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        write!(f, " {} #{:X}", comparison, self.value)
+    }
+}
+
+/// A breakpoint, which stops the execution before the instruction at `address`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Breakpoint {
+    /// Identifier of the breakpoint, given by `delete`
+    id: u32,
+    /// Address of the instruction
+    address: u16,
+    /// Condition to stop, if any
+    condition: Option<Condition>,
+}
+
+/// Reason why the execution stopped
+enum Stop {
+    /// The requested instructions were run
+    Done,
+    /// A breakpoint was reached
+    Breakpoint(Breakpoint),
+    /// The program exited with the SUPER-CHIP `EXIT` instruction
+    Exited,
+    /// An instruction could not be executed
+    Error(CpuError),
+    /// Watched memory accesses happened
+    Watch(Vec<WatchHit>),
+    /// `MAX_RUN_INSTRUCTIONS` were run without stopping
+    Limit,
+}
+
+/// Interactive debugger driven by a line-oriented console.
+///
+/// The debugger runs the machine instruction by instruction, stopping on breakpoints, and
+/// ticks the timers once every `cpu_hz / 60` instructions so that programs see them count down
+/// at the usual pace.
+pub struct Debugger {
+    /// The breakpoints
+    breakpoints: Vec<Breakpoint>,
+    /// Identifier of the next breakpoint
+    next_id: u32,
+    /// Number of instructions between two timers ticks
+    cycles_per_frame: u32,
+    /// Number of instructions run since the last timers tick
+    frame_cycles: u32,
+    /// Last command, repeated by an empty line
+    last_command: String,
+}
+
+impl Debugger {
+    /// Creates and returns a new `Debugger` struct, without breakpoints.
+    ///
+    /// # Parameters
+    ///
+    /// - `cpu_hz`: The number of instructions executed per second, which sets how often the
+    ///   timers are ticked
+    ///
+    /// # Returns
+    ///
+    /// A new `Debugger` struct.
+    pub fn new(cpu_hz: u32) -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            cycles_per_frame: (cpu_hz / 60).max(1),
+            frame_cycles: 0,
+            last_command: String::new(),
+        }
+    }
+
+    /// Runs the console until `quit` or the end of the input
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The machine to debug
+    /// - `input`: The commands, one per line
+    /// - `output`: The console output
+    ///
+    /// # Returns
+    ///
+    /// An `io::Error` if the console cannot be read or written
+    pub fn run<R: BufRead, W: Write>(
+        &mut self,
+        chip8: &mut Chip8,
+        mut input: R,
+        mut output: W,
+    ) -> io::Result<()> {
+        writeln!(output, "{}", format_instruction(chip8, chip8.get_pc()))?;
+        loop {
+            write!(output, "(chip8) ")?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(());
+            }
+
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+            match self.execute_command(chip8, &line, &mut output) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(message) => writeln!(output, "error: {}", message)?,
+            }
+        }
+    }
+
+    /// Executes a console command
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The debugged machine
+    /// - `line`: The command line
+    /// - `output`: The console output
+    ///
+    /// # Returns
+    ///
+    /// false if the debugger must quit, or an error message if the command is invalid
+    fn execute_command<W: Write>(
+        &mut self,
+        chip8: &mut Chip8,
+        line: &str,
+        output: &mut W,
+    ) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        let io_error = |error: io::Error| error.to_string();
+
+        match (command, args.as_slice()) {
+            ("break" | "b", [address, condition @ ..]) => {
+                let address = parse_address(address)?;
+                let condition = match condition {
+                    [] => None,
+                    ["if", register, comparison, value] => {
+                        Some(parse_condition(register, comparison, value)?)
+                    }
+                    _ => return Err(String::from("expected 'if <REG> <OP> <VALUE>'")),
+                };
+                let breakpoint = Breakpoint {
+                    id: self.next_id,
+                    address,
+                    condition,
+                };
+                self.next_id += 1;
+                self.breakpoints.push(breakpoint);
+                writeln!(output, "{}", describe_breakpoint(&breakpoint)).map_err(io_error)?;
+            }
+            ("delete" | "d", []) => self.breakpoints.clear(),
+            ("delete" | "d", [id]) => {
+                let id: u32 = parse_count(id)?;
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+                if self.breakpoints.len() == count {
+                    return Err(format!("no breakpoint {}", id));
+                }
+            }
+            ("breakpoints" | "bl", []) => {
+                if self.breakpoints.is_empty() {
+                    writeln!(output, "no breakpoints").map_err(io_error)?;
+                }
+                for breakpoint in &self.breakpoints {
+                    writeln!(output, "{}", describe_breakpoint(breakpoint)).map_err(io_error)?;
+                }
+            }
+            ("watch" | "w", [spec]) => {
+                let watchpoint: Watchpoint = spec.parse().map_err(|error| format!("{}", error))?;
+                let mut watchpoints = chip8.get_watchpoints().to_vec();
+                watchpoints.push(watchpoint);
+                writeln!(output, "watchpoint {} at {}", watchpoints.len(), watchpoint)
+                    .map_err(io_error)?;
+                chip8.set_watchpoints(watchpoints);
+            }
+            ("unwatch", []) => chip8.set_watchpoints(Vec::new()),
+            ("unwatch", [id]) => {
+                let id: u32 = parse_count(id)?;
+                let mut watchpoints = chip8.get_watchpoints().to_vec();
+                if id == 0 || id as usize > watchpoints.len() {
+                    return Err(format!("no watchpoint {}", id));
+                }
+                watchpoints.remove(id as usize - 1);
+                chip8.set_watchpoints(watchpoints);
+            }
+            ("watchpoints" | "wl", []) => {
+                let watchpoints = chip8.get_watchpoints();
+                if watchpoints.is_empty() {
+                    writeln!(output, "no watchpoints").map_err(io_error)?;
+                }
+                for (index, watchpoint) in watchpoints.iter().enumerate() {
+                    writeln!(output, "watchpoint {} at {}", index + 1, watchpoint)
+                        .map_err(io_error)?;
+                }
+            }
+            ("step" | "s", count @ ([] | [_])) => {
+                let count = count.first().map_or(Ok(1), |count| parse_count(count))?;
+                let stop = self.run_until(chip8, count, |_| false);
+                self.report(chip8, stop, output).map_err(io_error)?;
+            }
+            ("next" | "n", count @ ([] | [_])) => {
+                let count = count.first().map_or(Ok(1), |count| parse_count(count))?;
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.next(chip8);
+                    if !matches!(stop, Stop::Done) {
+                        break;
+                    }
+                }
+                self.report(chip8, stop, output).map_err(io_error)?;
+            }
+            ("continue" | "c", []) => {
+                let stop = self.run_until(chip8, MAX_RUN_INSTRUCTIONS, |_| false);
+                let stop = match stop {
+                    Stop::Done => Stop::Limit,
+                    stop => stop,
+                };
+                self.report(chip8, stop, output).map_err(io_error)?;
+            }
+            ("finish" | "f", []) => {
+                let depth = chip8.get_stack().len();
+                if depth == 0 {
+                    return Err(String::from("not in a subroutine"));
+                }
+                let stop = self.run_until(chip8, MAX_RUN_INSTRUCTIONS, |chip8| {
+                    chip8.get_stack().len() < depth
+                });
+                self.report(chip8, stop, output).map_err(io_error)?;
+            }
+            ("regs" | "r", []) => print_registers(chip8, output).map_err(io_error)?,
+            ("stack" | "bt", []) => {
+                let stack = chip8.get_stack();
+                if stack.is_empty() {
+                    writeln!(output, "the stack is empty").map_err(io_error)?;
+                }
+                for (depth, address) in stack.iter().rev().enumerate() {
+                    writeln!(output, "#{:<2} {:04X}", depth, address).map_err(io_error)?;
+                }
+            }
+            ("x", [address, count @ ..]) if count.len() <= 1 => {
+                let address = parse_address(address)?;
+                let count = count
+                    .first()
+                    .map_or(Ok(DEFAULT_EXAMINE_BYTES), |count| parse_count(count))?;
+                print_memory(chip8, address, count, output).map_err(io_error)?;
+            }
+            ("list" | "l", args) if args.len() <= 2 => {
+                let address = match args.first() {
+                    Some(address) => parse_address(address)?,
+                    None => chip8.get_pc(),
+                };
+                let count = args
+                    .get(1)
+                    .map_or(Ok(DEFAULT_LIST_INSTRUCTIONS), |count| parse_count(count))?;
+                self.print_listing(chip8, address, count, output)
+                    .map_err(io_error)?;
+            }
+            ("key", [key]) => chip8.set_key(parse_key(key)?, true),
+            ("key", [key, "up"]) => chip8.set_key(parse_key(key)?, false),
+            ("screen", []) => print_screen(chip8, output).map_err(io_error)?,
+            ("help" | "h", []) => writeln!(output, "{}", HELP).map_err(io_error)?,
+            ("quit" | "q", []) => return Ok(false),
+            ("", []) => {}
+            (
+                "break" | "b" | "delete" | "d" | "breakpoints" | "bl" | "watch" | "w" | "unwatch"
+                | "watchpoints" | "wl" | "step" | "s" | "next" | "n" | "continue" | "c" | "finish"
+                | "f" | "regs" | "r" | "stack" | "bt" | "x" | "list" | "l" | "key" | "screen"
+                | "help" | "h" | "quit" | "q",
+                _,
+            ) => return Err(format!("invalid arguments for '{}' (see 'help')", command)),
+            _ => return Err(format!("unknown command '{}' (see 'help')", command)),
+        }
+        Ok(true)
+    }
+
+    /// Runs instructions until a condition holds, stopping on breakpoints, watched accesses,
+    /// errors and exit.
+    /// The breakpoints are not checked before the first instruction, so that the execution can
+    /// resume from a breakpoint.
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The debugged machine
+    /// - `max_instructions`: The maximum number of instructions to run
+    /// - `done`: Checked after each instruction, the execution stops when it returns true
+    ///
+    /// # Returns
+    ///
+    /// The reason why the execution stopped, `Done` if `max_instructions` were run
+    fn run_until<F: Fn(&Chip8) -> bool>(
+        &mut self,
+        chip8: &mut Chip8,
+        max_instructions: u32,
+        done: F,
+    ) -> Stop {
+        for count in 0..max_instructions {
+            if chip8.has_exited() {
+                return Stop::Exited;
+            }
+            if count > 0 {
+                if let Some(breakpoint) = self.reached_breakpoint(chip8) {
+                    return Stop::Breakpoint(breakpoint);
+                }
+            }
+            if let Err(error) = chip8.step_instruction() {
+                return Stop::Error(error);
+            }
+            self.frame_cycles += 1;
+            if self.frame_cycles >= self.cycles_per_frame {
+                self.frame_cycles = 0;
+                chip8.tick_timers();
+            }
+            let hits = chip8.take_watch_hits();
+            if !hits.is_empty() {
+                return Stop::Watch(hits);
+            }
+            if done(chip8) {
+                return Stop::Done;
+            }
+        }
+        Stop::Done
+    }
+
+    /// Runs one instruction, or a whole subroutine if the instruction is a `CALL`
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The debugged machine
+    ///
+    /// # Returns
+    ///
+    /// The reason why the execution stopped
+    fn next(&mut self, chip8: &mut Chip8) -> Stop {
+        let pc = chip8.get_pc();
+        match decode_at(chip8, pc) {
+            Some(Instruction::Call(_)) => {
+                let depth = chip8.get_stack().len();
+                let return_address = pc.wrapping_add(2);
+                let stop = self.run_until(chip8, MAX_RUN_INSTRUCTIONS, |chip8| {
+                    chip8.get_pc() == return_address && chip8.get_stack().len() == depth
+                });
+                // Running out of instructions is not the end of the step
+                match (stop, chip8.get_pc() == return_address) {
+                    (Stop::Done, false) => Stop::Limit,
+                    (stop, _) => stop,
+                }
+            }
+            _ => self.run_until(chip8, 1, |_| false),
+        }
+    }
+
+    /// Finds the breakpoint stopping the execution before the instruction at PC
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The debugged machine
+    ///
+    /// # Returns
+    ///
+    /// The first breakpoint at PC whose condition holds, if any
+    fn reached_breakpoint(&self, chip8: &Chip8) -> Option<Breakpoint> {
+        let pc = chip8.get_pc();
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| {
+                breakpoint.address == pc
+                    && breakpoint
+                        .condition
+                        .map_or(true, |condition| condition.holds(chip8))
+            })
+            .copied()
+    }
+
+    /// Prints why the execution stopped, followed by the next instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The debugged machine
+    /// - `stop`: The reason why the execution stopped
+    /// - `output`: The console output
+    ///
+    /// # Returns
+    ///
+    /// An `io::Error` if the console cannot be written
+    fn report<W: Write>(&self, chip8: &Chip8, stop: Stop, output: &mut W) -> io::Result<()> {
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint(breakpoint) => writeln!(output, "breakpoint {}", breakpoint.id)?,
+            Stop::Exited => writeln!(output, "the program has exited")?,
+            Stop::Error(error) => writeln!(output, "error: {}", error)?,
+            Stop::Watch(hits) => {
+                for hit in hits {
+                    writeln!(output, "watchpoint: {}", hit)?;
+                }
+            }
+            Stop::Limit => writeln!(
+                output,
+                "stopped after {} instructions",
+                MAX_RUN_INSTRUCTIONS
+            )?,
+        }
+        writeln!(output, "{}", format_instruction(chip8, chip8.get_pc()))
+    }
+
+    /// Prints the disassembly of instructions, marking PC with `>` and breakpoints with `*`
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The debugged machine
+    /// - `address`: The address of the first instruction
+    /// - `count`: The number of instructions
+    /// - `output`: The console output
+    ///
+    /// # Returns
+    ///
+    /// An `io::Error` if the console cannot be written
+    fn print_listing<W: Write>(
+        &self,
+        chip8: &Chip8,
+        mut address: u16,
+        count: u32,
+        output: &mut W,
+    ) -> io::Result<()> {
+        for _ in 0..count {
+            if chip8.read_memory(address).is_err() {
+                break;
+            }
+            let pc_mark = if address == chip8.get_pc() { '>' } else { ' ' };
+            let breakpoint_mark = if self.breakpoints.iter().any(|b| b.address == address) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(
+                output,
+                "{}{} {}",
+                pc_mark,
+                breakpoint_mark,
+                format_instruction(chip8, address)
+            )?;
+            let size = decode_at(chip8, address).map_or(2, |instruction| instruction.size());
+            address = address.wrapping_add(size);
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the instruction at an address
+///
+/// # Parameters
+///
+/// - `chip8`: The debugged machine
+/// - `address`: The address of the instruction
+///
+/// # Returns
+///
+/// The instruction, or None if the opcode is unknown or cannot be read
+fn decode_at(chip8: &Chip8, address: u16) -> Option<Instruction> {
+    instruction::decode(read_word(chip8, address)?)
+}
+
+/// Reads a big endian word in memory
+///
+/// # Parameters
+///
+/// - `chip8`: The debugged machine
+/// - `address`: The address of the word
+///
+/// # Returns
+///
+/// The word, or None if it is outside of the memory
+fn read_word(chip8: &Chip8, address: u16) -> Option<u16> {
+    let high = chip8.read_memory(address).ok()?;
+    let low = chip8.read_memory(address.wrapping_add(1)).ok()?;
+    Some((high as u16) << 8 | low as u16)
+}
+
+/// Formats the instruction at an address like the disassembler listing
+///
+/// # Parameters
+///
+/// - `chip8`: The debugged machine
+/// - `address`: The address of the instruction
+///
+/// # Returns
+///
+/// The address, raw bytes and mnemonic of the instruction
+fn format_instruction(chip8: &Chip8, address: u16) -> String {
+    let opcode = match read_word(chip8, address) {
+        Some(opcode) => opcode,
+        None => return format!("{:04X}  outside of the memory", address),
+    };
+    match instruction::decode(opcode) {
+        Some(Instruction::LdILong) => match read_word(chip8, address.wrapping_add(2)) {
+            Some(nnnn) => format!(
+                "{:04X}  {:04X} {:04X}  {} #{:04X}",
+                address,
+                opcode,
+                nnnn,
+                Instruction::LdILong,
+                nnnn
+            ),
+            None => format!("{:04X}  {:04X}       DW #{:04X}", address, opcode, opcode),
+        },
+        Some(instruction) => format!("{:04X}  {:04X}       {}", address, opcode, instruction),
+        None => format!("{:04X}  {:04X}       DW #{:04X}", address, opcode, opcode),
+    }
+}
+
+/// Prints the registers and timers
+///
+/// # Parameters
+///
+/// - `chip8`: The debugged machine
+/// - `output`: The console output
+///
+/// # Returns
+///
+/// An `io::Error` if the console cannot be written
+fn print_registers<W: Write>(chip8: &Chip8, output: &mut W) -> io::Result<()> {
+    for row in 0..2 {
+        let registers: Vec<String> = (row * 8..row * 8 + 8)
+            .map(|x| format!("V{:X}={:02X}", x, chip8.get_register(x)))
+            .collect();
+        writeln!(output, "{}", registers.join(" "))?;
+    }
+    writeln!(
+        output,
+        "I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}",
+        chip8.get_i(),
+        chip8.get_pc(),
+        chip8.get_stack().len(),
+        chip8.get_dt(),
+        chip8.get_st()
+    )
+}
+
+/// Prints memory bytes, 16 per line
+///
+/// # Parameters
+///
+/// - `chip8`: The debugged machine
+/// - `address`: The address of the first byte
+/// - `count`: The number of bytes
+/// - `output`: The console output
+///
+/// # Returns
+///
+/// An `io::Error` if the console cannot be written
+fn print_memory<W: Write>(
+    chip8: &Chip8,
+    address: u16,
+    count: u32,
+    output: &mut W,
+) -> io::Result<()> {
+    let mut line = String::new();
+    for offset in 0..count {
+        let current = address as u32 + offset;
+        if current > u16::MAX as u32 {
+            break;
+        }
+        let current = current as u16;
+        let byte = match chip8.read_memory(current) {
+            Ok(byte) => byte,
+            Err(error) => {
+                if !line.is_empty() {
+                    writeln!(output, "{}", line)?;
+                }
+                return writeln!(output, "error: {}", error);
+            }
+        };
+        if offset % 16 == 0 {
+            if !line.is_empty() {
+                writeln!(output, "{}", line)?;
+            }
+            line = format!("{:04X} ", current);
+        }
+        line.push_str(&format!(" {:02X}", byte));
+    }
+    if !line.is_empty() {
+        writeln!(output, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Prints the display, `#` being a lit pixel and `.` an unlit one
+///
+/// # Parameters
+///
+/// - `chip8`: The debugged machine
+/// - `output`: The console output
+///
+/// # Returns
+///
+/// An `io::Error` if the console cannot be written
+fn print_screen<W: Write>(chip8: &Chip8, output: &mut W) -> io::Result<()> {
+    let width = chip8.get_display_width();
+    let buffer = chip8.get_display_buffer();
+    for row in buffer.chunks(width).take(chip8.get_display_height()) {
+        let line: String = row
+            .iter()
+            .map(|&color| if color != 0 { '#' } else { '.' })
+            .collect();
+        writeln!(output, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Describes a breakpoint
+///
+/// # Parameters
+///
+/// - `breakpoint`: The breakpoint
+///
+/// # Returns
+///
+/// The identifier, address and condition of the breakpoint
+fn describe_breakpoint(breakpoint: &Breakpoint) -> String {
+    match breakpoint.condition {
+        Some(condition) => format!(
+            "breakpoint {} at {:04X} if {}",
+            breakpoint.id, breakpoint.address, condition
+        ),
+        None => format!("breakpoint {} at {:04X}", breakpoint.id, breakpoint.address),
+    }
+}
+
+/// Parses a breakpoint condition
+///
+/// # Parameters
+///
+/// - `operand`: The register: V0 to VF, or I
+/// - `comparison`: The comparison: ==, !=, <, <=, > or >=
+/// - `value`: The value compared to the register
+///
+/// # Returns
+///
+/// The condition, or an error message if it is invalid
+fn parse_condition(operand: &str, comparison: &str, value: &str) -> Result<Condition, String> {
+    let upper = operand.to_ascii_uppercase();
+    let operand = match upper.strip_prefix('V') {
+        _ if upper == "I" => Operand::I,
+        Some(x) if x.len() == 1 => Operand::Register(
+            u8::from_str_radix(x, 16).map_err(|_| format!("invalid register '{}'", operand))?,
+        ),
+        _ => return Err(format!("invalid register '{}'", operand)),
+    };
+    let comparison = match comparison {
+        "==" => Comparison::Equal,
+        "!=" => Comparison::NotEqual,
+        "<" => Comparison::Less,
+        "<=" => Comparison::LessOrEqual,
+        ">" => Comparison::Greater,
+        ">=" => Comparison::GreaterOrEqual,
+        _ => return Err(format!("invalid comparison '{}'", comparison)),
+    };
+    let value = parse_number(value, 10)
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| format!("invalid value '{}'", value))?;
+    Ok(Condition {
+        operand,
+        comparison,
+        value,
+    })
+}
+
+/// Parses an hexadecimal address, with an optional `#` or `0x` prefix
+///
+/// # Parameters
+///
+/// - `text`: The address
+///
+/// # Returns
+///
+/// The address, or an error message if it is invalid
+fn parse_address(text: &str) -> Result<u16, String> {
+    parse_number(text, 16)
+        .and_then(|address| u16::try_from(address).ok())
+        .ok_or_else(|| format!("invalid address '{}'", text))
+}
+
+/// Parses a keypad key, from 0 to F
+///
+/// # Parameters
+///
+/// - `text`: The key
+///
+/// # Returns
+///
+/// The key code, or an error message if it is invalid
+fn parse_key(text: &str) -> Result<u8, String> {
+    Some(text)
+        .filter(|text| text.chars().all(|c| c.is_ascii_hexdigit()))
+        .and_then(|text| u8::from_str_radix(text, 16).ok())
+        .filter(|&key| key <= 0xF)
+        .ok_or_else(|| format!("invalid key '{}' (expected 0 to F)", text))
+}
+
+/// Parses a decimal count, or an hexadecimal one with a `#` or `0x` prefix
+///
+/// # Parameters
+///
+/// - `text`: The count
+///
+/// # Returns
+///
+/// The count, or an error message if it is invalid
+fn parse_count(text: &str) -> Result<u32, String> {
+    parse_number(text, 10).ok_or_else(|| format!("invalid count '{}'", text))
+}
+
+/// Parses a number, `#` and `0x` prefixes meaning hexadecimal
+///
+/// # Parameters
+///
+/// - `text`: The number
+/// - `radix`: The radix of the number without prefix
+///
+/// # Returns
+///
+/// The number, or None if it is invalid
+fn parse_number(text: &str, radix: u32) -> Option<u32> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = match lower.strip_prefix('#').or_else(|| lower.strip_prefix("0x")) {
+        Some(digits) => (digits, 16),
+        None => (lower.as_str(), radix),
+    };
+    // `from_str_radix` accepts a leading sign
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u32::from_str_radix(digits, radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    /// Program calling a recursive subroutine, then counting in V3 forever
+    const PROGRAM: &str = "\
+            CALL count      ; 0200
+        loop:
+            ADD V3, 1       ; 0202
+            JP loop         ; 0204
+        count:
+            ADD V4, 1       ; 0206
+            SE V4, 3        ; 0208
+            CALL count      ; 020A
+            RET             ; 020C
+        ";
+
+    /// Runs the debugger console over `PROGRAM` with the given commands
+    fn debug(commands: &str) -> (Chip8, String) {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&asm::assemble(PROGRAM, None).unwrap())
+            .unwrap();
+        let mut output = Vec::new();
+        Debugger::new(600)
+            .run(&mut chip8, commands.as_bytes(), &mut output)
+            .unwrap();
+        (chip8, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn next_steps_over_a_recursive_call() {
+        let (chip8, output) = debug("next\n");
+        assert_eq!(chip8.get_pc(), 0x202);
+        assert!(chip8.get_stack().is_empty());
+        assert_eq!(chip8.get_register(4), 3);
+        assert!(!output.contains("error"));
+    }
+
+    #[test]
+    fn finish_stops_when_the_stack_depth_drops() {
+        let (chip8, _) = debug("step 9\n");
+        assert_eq!((chip8.get_pc(), chip8.get_stack().len()), (0x20C, 3));
+
+        let (chip8, _) = debug("step 9\nfinish\n");
+        assert_eq!((chip8.get_pc(), chip8.get_stack().len()), (0x20C, 2));
+
+        let (chip8, output) = debug("step 9\nfinish\nfinish\nfinish\nfinish\n");
+        assert_eq!((chip8.get_pc(), chip8.get_stack().len()), (0x202, 0));
+        assert!(output.contains("error: not in a subroutine"));
+    }
+
+    #[test]
+    fn breakpoint_does_not_trigger_again_on_resume() {
+        let (chip8, output) = debug("break 202\ncontinue\ncontinue\n");
+        assert_eq!(chip8.get_pc(), 0x202);
+        // The second continue has run the loop once
+        assert_eq!(chip8.get_register(3), 1);
+        assert_eq!(output.matches("breakpoint 1\n").count(), 2);
+    }
+
+    #[test]
+    fn conditional_breakpoint_fires_when_the_condition_holds() {
+        let (chip8, output) = debug("break 202 if V3 == 5\ncontinue\n");
+        assert!(output.contains("breakpoint 1 at 0202 if V3 == #5\n"));
+        assert_eq!(chip8.get_pc(), 0x202);
+        assert_eq!(chip8.get_register(3), 5);
+    }
+
+    #[test]
+    fn invalid_arguments_are_reported() {
+        let (_, output) = debug("x\nx zz\nx +200\nlist 200 10 3\nlist 200 -1\nkey +5\n");
+        let errors: Vec<&str> = output
+            .lines()
+            .filter_map(|line| line.split("error: ").nth(1))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "invalid arguments for 'x' (see 'help')",
+                "invalid address 'zz'",
+                "invalid address '+200'",
+                "invalid arguments for 'list' (see 'help')",
+                "invalid count '-1'",
+                "invalid key '+5' (expected 0 to F)",
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_are_digits_with_an_optional_prefix() {
+        assert_eq!(parse_number("200", 16), Some(0x200));
+        assert_eq!(parse_number("#1F", 10), Some(0x1F));
+        assert_eq!(parse_number("0x1f", 10), Some(0x1F));
+        assert_eq!(parse_number("15", 10), Some(15));
+        assert_eq!(parse_number("+200", 16), None);
+        assert_eq!(parse_number("#+1F", 10), None);
+        assert_eq!(parse_number("1F", 10), None);
+        assert_eq!(parse_number("", 10), None);
+    }
+}
@@ -0,0 +1,475 @@
+use crate::cpu::PROGRAM_START;
+use crate::instruction::{self, Instruction};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Column of the comments holding the address and raw bytes in the source disassembly
+const COMMENT_COLUMN: usize = 28;
+
+/// Number of bytes per `DB` directive for the data which is not a sprite
+const DATA_BYTES_PER_LINE: usize = 8;
+
+/// Role of a ROM byte found by the flow analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Byte never reached by the execution flow
+    Data,
+    /// Byte drawn by a `DRW` instruction
+    Sprite,
+    /// First byte of an instruction
+    Code,
+    /// Other byte of an instruction
+    Operand,
+}
+
+/// Disassembles a ROM linearly, every 2 bytes being read as an instruction.
+///
+/// Each line holds the address, the raw bytes and the mnemonic of an instruction:
+///
+/// ```text
+/// 0200  6A02       LD VA, #02
+/// 0202  F000 0300  LD I, LONG #0300
+/// 0206  0123       DW #0123
+/// ```
+///
+/// Opcodes that do not match any instruction are listed as `DW` words, and a trailing odd byte
+/// as a `DB` byte.
+///
+/// # Parameters
+///
+/// - `rom`: The bytes of the ROM, loaded at `PROGRAM_START`
+///
+/// # Returns
+///
+/// The listing, one line per instruction
+pub fn listing(rom: &[u8]) -> String {
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = PROGRAM_START as usize + offset;
+        if offset + 1 == rom.len() {
+            let _ = writeln!(
+                listing,
+                "{:04X}  {:02X}         DB #{:02X}",
+                address, rom[offset], rom[offset]
+            );
+            break;
+        }
+
+        let opcode = read_word(rom, offset);
+        let (size, line) = match instruction::decode(opcode) {
+            Some(Instruction::LdILong) if offset + 4 <= rom.len() => {
+                let nnnn = read_word(rom, offset + 2);
+                let line = format!(
+                    "{:04X} {:04X}  {} #{:04X}",
+                    opcode,
+                    nnnn,
+                    Instruction::LdILong,
+                    nnnn
+                );
+                (4, line)
+            }
+            Some(instruction) if instruction.size() == 2 => {
+                (2, format!("{:04X}       {}", opcode, instruction))
+            }
+            _ => (2, format!("{:04X}       DW #{:04X}", opcode, opcode)),
+        };
+        let _ = writeln!(listing, "{:04X}  {}", address, line);
+        offset += size;
+    }
+    listing
+}
+
+/// Disassembles a ROM by following the execution flow, producing source that can be assembled
+/// back into the same ROM.
+///
+/// The flow starts at `PROGRAM_START` and follows jumps, calls, skips and returns. Bytes that are
+/// never executed are data: the ones drawn with `DRW` after an `LD I` are listed one row per line
+/// with a picture of the row, the others as `DB` directives. Jump, call and `LD I` targets get
+/// generated labels (`code_0208`, `sprite_02EA`, `data_0300`), and every line is followed by a
+/// comment holding its address and raw bytes:
+///
+/// ```text
+/// code_0200:
+///     LD VA, #02              ; 0200  6A02
+///     LD I, sprite_02EA       ; 0208  A2EA
+///     JP code_021A            ; 021E  121A
+///
+/// sprite_02EA:
+///     DB #80                  ; 02EA  #.......
+/// ```
+///
+/// Opcodes with bits the CPU ignores are kept as `DW` words so that the bytes are preserved.
+/// The targets of `JP V0` cannot be known, only its base address is followed.
+///
+/// # Parameters
+///
+/// - `rom`: The bytes of the ROM, loaded at `PROGRAM_START`
+///
+/// # Returns
+///
+/// The source, one line per instruction, sprite row or data directive
+pub fn source(rom: &[u8]) -> String {
+    let (kinds, targets) = analyze(rom);
+
+    // Name the targets which start a line, the other ones staying numbers
+    let mut labels = BTreeMap::new();
+    for &target in &targets {
+        let prefix = match offset_of(rom, target).map(|offset| kinds[offset]) {
+            Some(Kind::Code) => "code",
+            Some(Kind::Sprite) => "sprite",
+            Some(Kind::Data) => "data",
+            Some(Kind::Operand) | None => continue,
+        };
+        labels.insert(target, format!("{}_{:04X}", prefix, target));
+    }
+
+    let mut source = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = address_of(offset);
+        if let Some(label) = labels.get(&address) {
+            if !source.is_empty() {
+                source.push('\n');
+            }
+            let _ = writeln!(source, "{}:", label);
+        }
+
+        let size = match kinds[offset] {
+            Kind::Code => {
+                let opcode = read_word(rom, offset);
+                let (size, line, raw) = match instruction::decode(opcode) {
+                    Some(Instruction::LdILong) => {
+                        let nnnn = read_word(rom, offset + 2);
+                        let line =
+                            format!("{} {}", Instruction::LdILong, operand(&labels, nnnn, 4));
+                        (4, line, format!("{:04X} {:04X}", opcode, nnnn))
+                    }
+                    Some(instruction) if instruction.encode() == opcode => {
+                        let line = with_labels(&labels, instruction);
+                        (2, line, format!("{:04X}", opcode))
+                    }
+                    _ => (2, format!("DW #{:04X}", opcode), format!("{:04X}", opcode)),
+                };
+                write_line(&mut source, &line, address, &raw);
+                size
+            }
+            Kind::Sprite => {
+                let byte = rom[offset];
+                let row: String = (0..8)
+                    .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                    .collect();
+                write_line(&mut source, &format!("DB #{:02X}", byte), address, &row);
+                1
+            }
+            Kind::Data | Kind::Operand => {
+                let mut size = 1;
+                while size < DATA_BYTES_PER_LINE
+                    && offset + size < rom.len()
+                    && kinds[offset + size] == Kind::Data
+                    && !labels.contains_key(&address_of(offset + size))
+                {
+                    size += 1;
+                }
+                let bytes = &rom[offset..offset + size];
+                let line = bytes
+                    .iter()
+                    .map(|byte| format!("#{:02X}", byte))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let raw: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                write_line(&mut source, &format!("DB {}", line), address, &raw);
+                size
+            }
+        };
+        offset += size;
+    }
+    source
+}
+
+/// Follows the execution flow of a ROM from `PROGRAM_START`
+///
+/// # Parameters
+///
+/// - `rom`: The bytes of the ROM
+///
+/// # Returns
+///
+/// The role of each byte of the ROM, and the addresses targeted by jumps, calls and `LD I`
+fn analyze(rom: &[u8]) -> (Vec<Kind>, BTreeSet<u16>) {
+    let mut kinds = vec![Kind::Data; rom.len()];
+    let mut targets = BTreeSet::new();
+    let mut sprites = Vec::new();
+
+    // Addresses still to follow, with the value of I when it is known
+    let mut pending = vec![(PROGRAM_START, None)];
+    while let Some((mut address, mut i)) = pending.pop() {
+        while let Some(offset) = offset_of(rom, address) {
+            let instruction = match decode_at(rom, offset) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let size = instruction.size() as usize;
+            if kinds[offset..offset + size]
+                .iter()
+                .any(|&kind| kind == Kind::Code || kind == Kind::Operand)
+            {
+                break;
+            }
+            kinds[offset] = Kind::Code;
+            for kind in &mut kinds[offset + 1..offset + size] {
+                *kind = Kind::Operand;
+            }
+
+            let next = address.wrapping_add(size as u16);
+            match instruction {
+                Instruction::Ret | Instruction::Exit => break,
+                Instruction::Jp(nnn) => {
+                    targets.insert(nnn);
+                    pending.push((nnn, i));
+                    break;
+                }
+                Instruction::JpV0(nnn) => {
+                    targets.insert(nnn);
+                    pending.push((nnn, None));
+                    break;
+                }
+                Instruction::Call(nnn) => {
+                    targets.insert(nnn);
+                    pending.push((nnn, i));
+                    // The subroutine may change I
+                    i = None;
+                }
+                Instruction::SeByte(..)
+                | Instruction::SneByte(..)
+                | Instruction::SeReg(..)
+                | Instruction::SneReg(..)
+                | Instruction::Skp(..)
+                | Instruction::Sknp(..) => {
+                    let skipped = offset_of(rom, next)
+                        .and_then(|offset| decode_at(rom, offset))
+                        .map_or(2, |instruction| instruction.size());
+                    pending.push((next.wrapping_add(skipped), i));
+                }
+                Instruction::LdI(nnn) => {
+                    targets.insert(nnn);
+                    i = Some(nnn);
+                }
+                Instruction::LdILong => {
+                    let nnnn = read_word(rom, offset + 2);
+                    targets.insert(nnnn);
+                    i = Some(nnnn);
+                }
+                Instruction::Drw(_, _, n) => {
+                    if let Some(i) = i {
+                        // DRW Vx, Vy, 0 draws a 16x16 sprite in SUPER-CHIP
+                        sprites.push((i, if n == 0 { 32 } else { n as u16 }));
+                    }
+                }
+                Instruction::AddI(_)
+                | Instruction::LdF(_)
+                | Instruction::LdHf(_)
+                | Instruction::Store(_)
+                | Instruction::Load(_) => i = None,
+                _ => {}
+            }
+            address = next;
+        }
+    }
+
+    for (start, len) in sprites {
+        for address in (0..len).map(|row| start.wrapping_add(row)) {
+            if let Some(offset) = offset_of(rom, address) {
+                if kinds[offset] == Kind::Data {
+                    kinds[offset] = Kind::Sprite;
+                }
+            }
+        }
+    }
+    (kinds, targets)
+}
+
+/// Decodes the instruction at an offset of a ROM
+///
+/// # Parameters
+///
+/// - `rom`: The bytes of the ROM
+/// - `offset`: The offset of the instruction in the ROM
+///
+/// # Returns
+///
+/// The instruction, or None if the opcode is unknown or the instruction ends after the ROM
+fn decode_at(rom: &[u8], offset: usize) -> Option<Instruction> {
+    if offset + 2 > rom.len() {
+        return None;
+    }
+    let instruction = instruction::decode(read_word(rom, offset))?;
+    if offset + instruction.size() as usize > rom.len() {
+        return None;
+    }
+    Some(instruction)
+}
+
+/// Formats an instruction, replacing its address with a label if there is one
+///
+/// # Parameters
+///
+/// - `labels`: The label of each labelled address
+/// - `instruction`: The instruction
+///
+/// # Returns
+///
+/// The mnemonic of the instruction
+fn with_labels(labels: &BTreeMap<u16, String>, instruction: Instruction) -> String {
+    match instruction {
+        Instruction::Jp(nnn) => format!("JP {}", operand(labels, nnn, 3)),
+        Instruction::Call(nnn) => format!("CALL {}", operand(labels, nnn, 3)),
+        Instruction::LdI(nnn) => format!("LD I, {}", operand(labels, nnn, 3)),
+        Instruction::JpV0(nnn) => format!("JP V0, {}", operand(labels, nnn, 3)),
+        _ => instruction.to_string(),
+    }
+}
+
+/// Formats an address operand
+///
+/// # Parameters
+///
+/// - `labels`: The label of each labelled address
+/// - `address`: The address
+/// - `digits`: The number of hexadecimal digits used when the address has no label
+///
+/// # Returns
+///
+/// The label of the address, or the address as a number
+fn operand(labels: &BTreeMap<u16, String>, address: u16, digits: usize) -> String {
+    match labels.get(&address) {
+        Some(label) => label.clone(),
+        None => format!("#{:0digits$X}", address, digits = digits),
+    }
+}
+
+/// Writes an indented source line followed by a comment holding its address and raw bytes
+///
+/// # Parameters
+///
+/// - `source`: The source being written
+/// - `line`: The instruction or directive
+/// - `address`: The address of the line
+/// - `raw`: The raw bytes of the line, or any other note
+fn write_line(source: &mut String, line: &str, address: u16, raw: &str) {
+    let _ = writeln!(
+        source,
+        "    {:width$}; {:04X}  {}",
+        line,
+        address,
+        raw,
+        width = COMMENT_COLUMN - 4
+    );
+}
+
+/// Gets the offset of an address in a ROM
+///
+/// # Parameters
+///
+/// - `rom`: The bytes of the ROM
+/// - `address`: The address
+///
+/// # Returns
+///
+/// The offset of the address in the ROM, or None if the address is outside the ROM
+fn offset_of(rom: &[u8], address: u16) -> Option<usize> {
+    let offset = (address as usize).checked_sub(PROGRAM_START as usize)?;
+    if offset < rom.len() {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+/// Gets the address of a ROM offset
+///
+/// # Parameters
+///
+/// - `offset`: The offset in the ROM
+///
+/// # Returns
+///
+/// The address of the offset once the ROM is loaded
+fn address_of(offset: usize) -> u16 {
+    (PROGRAM_START as usize + offset) as u16
+}
+
+/// Reads a big endian word in a ROM
+///
+/// # Parameters
+///
+/// - `rom`: The bytes of the ROM
+/// - `offset`: The offset of the word in the ROM
+///
+/// # Returns
+///
+/// The word
+fn read_word(rom: &[u8], offset: usize) -> u16 {
+    (rom[offset] as u16) << 8 | rom[offset + 1] as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn listing_reads_every_word_as_an_instruction() {
+        let rom = [0x6A, 0x02, 0xF0, 0x00, 0x03, 0x00, 0x01, 0x23, 0xFF];
+
+        assert_eq!(
+            listing(&rom),
+            "0200  6A02       LD VA, #02\n\
+             0202  F000 0300  LD I, LONG #0300\n\
+             0206  0123       DW #0123\n\
+             0208  FF         DB #FF\n"
+        );
+    }
+
+    #[test]
+    fn listing_of_a_truncated_long_load_is_a_word() {
+        assert_eq!(listing(&[0xF0, 0x00]), "0200  F000       DW #F000\n");
+    }
+
+    #[test]
+    fn source_of_every_bundled_rom_assembles_back_into_the_rom() {
+        let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        let mut count = 0;
+        for entry in fs::read_dir(roms).unwrap() {
+            let path = entry.unwrap().path();
+            let rom = fs::read(&path).unwrap();
+            let source = source(&rom);
+
+            match asm::assemble(&source, None) {
+                Ok(assembled) => assert!(assembled == rom, "{} differs", path.display()),
+                Err(error) => panic!("{}: {}", path.display(), error),
+            }
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn source_labels_the_targets_and_pictures_the_sprites() {
+        // LD I, #208; DRW V0, V0, 1; JP #202; 2 unused bytes, then a sprite row
+        let rom = [0xA2, 0x08, 0xD0, 0x01, 0x12, 0x02, 0x00, 0x00, 0x81];
+        let source = source(&rom);
+
+        assert!(source.contains("LD I, sprite_0208"), "{}", source);
+        assert!(source.contains("code_0202:"), "{}", source);
+        assert!(source.contains("JP code_0202"), "{}", source);
+        assert!(
+            source.contains("DB #81                  ; 0208  #......#"),
+            "{}",
+            source
+        );
+        assert_eq!(asm::assemble(&source, None).unwrap(), rom);
+    }
+}
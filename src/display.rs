@@ -1,28 +1,113 @@
-extern crate minifb;
+use crate::state::{StateError, StateReader, StateWriter};
 
+/// Width of the display in low resolution mode
 pub const WIDTH: usize = 64;
+/// Height of the display in low resolution mode
 pub const HEIGHT: usize = 32;
+/// Width of the display in high resolution mode (SUPER-CHIP)
+pub const HIRES_WIDTH: usize = 128;
+/// Height of the display in high resolution mode (SUPER-CHIP)
+pub const HIRES_HEIGHT: usize = 64;
+/// Number of bitplanes (XO-CHIP)
+pub const PLANES: usize = 2;
+/// Colors of the pixels, indexed by their value: bit 0 is set when the pixel is on in the first
+/// plane, bit 1 when it is on in the second plane (XO-CHIP)
+pub const PALETTE: [u32; 1 << PLANES] = [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555];
 
 /// Chip8 Display struct
 pub struct Display {
-    /// The buffer that contains pixels information (on or off)
-    buffer: [u32; WIDTH * HEIGHT],
-    /// The buffer that contains pixels colors (0xFFFFFF or 0x000000)
-    display_buffer: [u32; WIDTH * HEIGHT],
+    /// Is the display in high resolution mode (128x64 instead of 64x32)?
+    hires: bool,
+    /// The bitplanes affected by drawing, clearing and scrolling (XO-CHIP), as a bit mask
+    selected_planes: u8,
+    /// The buffer that contains pixels information (one bit per plane)
+    buffer: [u32; HIRES_WIDTH * HIRES_HEIGHT],
+    /// The buffer that contains pixels colors (from `PALETTE`)
+    display_buffer: [u32; HIRES_WIDTH * HIRES_HEIGHT],
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
 }
 
 impl Display {
-    /// Creates and returns a new `Display` struct.
+    /// Creates and returns a new `Display` struct, in low resolution mode.
     ///
     /// # Returns
     ///
     /// A new `Display` struct.
     pub fn new() -> Display {
-        let display = Display {
-            buffer: [0; WIDTH * HEIGHT],
-            display_buffer: [0; WIDTH * HEIGHT],
-        };
-        display
+        Display {
+            hires: false,
+            selected_planes: 0x1,
+            buffer: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            display_buffer: [0; HIRES_WIDTH * HIRES_HEIGHT],
+        }
+    }
+
+    /// Gets the display width in the current resolution mode
+    ///
+    /// # Returns
+    ///
+    /// The display width, in pixels
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            WIDTH
+        }
+    }
+
+    /// Gets the display height in the current resolution mode
+    ///
+    /// # Returns
+    ///
+    /// The display height, in pixels
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            HEIGHT
+        }
+    }
+
+    /// Is the display in high resolution mode?
+    ///
+    /// # Returns
+    ///
+    /// `true` if the display is 128x64, `false` if it is 64x32
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switches between low and high resolution modes, which clears the display
+    ///
+    /// # Parameters
+    ///
+    /// - `hires`: `true` for the 128x64 mode, `false` for the 64x32 mode
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.clear_planes(0x3);
+    }
+
+    /// Gets the bitplanes affected by drawing, clearing and scrolling (XO-CHIP)
+    ///
+    /// # Returns
+    ///
+    /// The selected planes, as a bit mask: 0x1 for the first plane, 0x2 for the second one
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    /// Selects the bitplanes affected by drawing, clearing and scrolling (XO-CHIP)
+    ///
+    /// # Parameters
+    ///
+    /// - `planes`: The planes to select, as a bit mask (0x0 to 0x3)
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & 0x3;
     }
 
     /// Gets pixel index in the pixel buffer given its (x, y) coordinates
@@ -36,30 +121,27 @@ impl Display {
     ///
     /// The index of the pixel in the pixel buffer
     fn get_pixel_index(&self, x: usize, y: usize) -> usize {
-        (y * WIDTH) + x
+        (y * self.width()) + x
     }
 
-    /// Sets the pixel (x, y) value
+    /// Flips the pixel (x, y) in the given plane
     ///
     /// # Parameters
     ///
     /// - `x`: The x coordinate of the pixel
     /// - `y`: The y coordinate of the pixel
-    /// - `value`: The value of the pixel (0x0 or 0x1)
+    /// - `plane`: The bit of the plane in the pixel value (0x1 or 0x2)
     ///
     /// # Returns
     ///
     /// `true` if the operation erased the pixel
-    fn set_pixel(&mut self, x: usize, y: usize, value: u8) -> bool {
+    fn flip_pixel(&mut self, x: usize, y: usize, plane: u8) -> bool {
         let index = self.get_pixel_index(x, y);
         let prev_value = self.buffer[index];
-        let pixel_value = prev_value ^ value as u32;
+        let pixel_value = prev_value ^ plane as u32;
 
-        let mut erased = false;
         // The pixel was set and has been erased
-        if prev_value == 0x1 && pixel_value == 0x0 {
-            erased = true;
-        }
+        let erased = prev_value & plane as u32 != 0;
 
         // Update pixel in the pixel buffer
         self.buffer[index] = pixel_value;
@@ -75,17 +157,9 @@ impl Display {
     /// # Parameters
     ///
     /// - `index`: The index of the pixel in `display_buffer`
-    /// - `pixel_value`: The value of the pixel (0x0 or 0x1)
+    /// - `pixel_value`: The value of the pixel (0x0 to 0x3)
     fn set_pixel_color(&mut self, index: usize, pixel_value: u32) {
-        let pixel_color = match pixel_value {
-            0x0 => 0x0,
-            0x1 => 0xFFFFFF,
-            _ => {
-                println!("{:#x}", pixel_value);
-                0xFF33CC
-            }
-        };
-        self.display_buffer[index] = pixel_color;
+        self.display_buffer[index] = PALETTE[pixel_value as usize];
     }
 
     /// Updates the frame buffer to display the given byte (which is part of a sprite)
@@ -95,43 +169,168 @@ impl Display {
     /// - `x`: The x coordinate of the sprite part
     /// - `y`: The y coordinate of the sprite part
     /// - `value`: The value sprite part
+    /// - `clip`: Are the pixels outside of the display clipped, instead of wrapping around?
+    /// - `plane`: The plane to draw in (0x1 or 0x2)
     ///
     /// # Returns
     ///
     /// `true` if the operation erased any pixel
-    pub fn draw_byte(&mut self, x: usize, y: usize, value: u8) -> bool {
+    pub fn draw_byte(&mut self, x: usize, y: usize, value: u8, clip: bool, plane: u8) -> bool {
+        let width = self.width();
+        let height = self.height();
+        if clip && y >= height {
+            return false;
+        }
         let mut erased = false;
         let mut coord_x;
-        let coord_y = y % HEIGHT;
+        let coord_y = y % height;
 
         let mut offset = 7;
         // Set each pixel of the byte
         for i in 0..8 {
-            coord_x = (x + i) % WIDTH;
+            if clip && x + i >= width {
+                break;
+            }
+            coord_x = (x + i) % width;
             let pixel_value = (value >> offset) & 0x1;
 
             // Check if any pixel has been erased
-            if self.set_pixel(coord_x, coord_y, pixel_value) {
+            if pixel_value == 0x1 && self.flip_pixel(coord_x, coord_y, plane) {
                 erased = true;
             }
             offset -= 1;
         }
         erased
     }
+
     /// Gets the display buffer
     ///
     /// # Returns
     ///
-    /// The display buffer
-    pub fn get_display_buffer(&self) -> [u32; WIDTH * HEIGHT] {
-        self.display_buffer
+    /// The display buffer, `width() * height()` pixels long
+    pub fn get_display_buffer(&self) -> &[u32] {
+        &self.display_buffer[..self.width() * self.height()]
     }
 
-    /// Clears the display
+    /// Writes the resolution mode, the selected planes and the pixels in a save state
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The save state writer
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.hires);
+        writer.write_u8(self.selected_planes);
+        for pixel_value in self.buffer.iter() {
+            writer.write_u8(*pixel_value as u8);
+        }
+    }
+
+    /// Restores the resolution mode, the selected planes and the pixels from a save state
+    /// written by `save_state`
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The save state reader
+    ///
+    /// # Returns
+    ///
+    /// A `StateError` if the save state is invalid, in which case the display is partially
+    /// restored
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.hires = reader.read_bool("resolution mode")?;
+        self.selected_planes = reader.read_u8()?;
+        if self.selected_planes > 0x3 {
+            return Err(StateError::InvalidValue("plane selection"));
+        }
+        let pixels = reader.read_bytes(self.buffer.len())?;
+        for (index, pixel_value) in pixels.iter().enumerate() {
+            if *pixel_value as usize >= PALETTE.len() {
+                return Err(StateError::InvalidValue("pixel value"));
+            }
+            self.buffer[index] = *pixel_value as u32;
+            self.set_pixel_color(index, *pixel_value as u32);
+        }
+        Ok(())
+    }
+
+    /// Clears the selected planes of the display
     pub fn clear(&mut self) {
-        for i in 0..(WIDTH * HEIGHT) {
-            self.buffer[i] = 0;
-            self.display_buffer[i] = 0;
+        self.clear_planes(self.selected_planes);
+    }
+
+    /// Clears the given planes of the display
+    ///
+    /// # Parameters
+    ///
+    /// - `planes`: The planes to clear, as a bit mask
+    fn clear_planes(&mut self, planes: u8) {
+        for i in 0..(HIRES_WIDTH * HIRES_HEIGHT) {
+            self.buffer[i] &= !(planes as u32);
+            self.set_pixel_color(i, self.buffer[i]);
+        }
+    }
+
+    /// Scrolls the display content up, the bottom rows are cleared (XO-CHIP)
+    ///
+    /// # Parameters
+    ///
+    /// - `n`: The number of rows to scroll
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Scrolls the display content down, the top rows are cleared
+    ///
+    /// # Parameters
+    ///
+    /// - `n`: The number of rows to scroll
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Scrolls the display content right, the leftmost columns are cleared
+    ///
+    /// # Parameters
+    ///
+    /// - `n`: The number of columns to scroll
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Scrolls the display content left, the rightmost columns are cleared
+    ///
+    /// # Parameters
+    ///
+    /// - `n`: The number of columns to scroll
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    /// Moves every pixel of the selected planes by (dx, dy), pixels moved from outside of the
+    /// display are cleared
+    ///
+    /// # Parameters
+    ///
+    /// - `dx`: The horizontal offset, positive to the right
+    /// - `dy`: The vertical offset, positive to the bottom
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.width() as isize;
+        let height = self.height() as isize;
+        let planes = self.selected_planes as u32;
+        let previous = self.buffer;
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                    previous[(src_y * width + src_x) as usize] & planes
+                } else {
+                    0
+                };
+                let index = (y * width + x) as usize;
+                let pixel_value = (self.buffer[index] & !planes) | moved;
+                self.buffer[index] = pixel_value;
+                self.set_pixel_color(index, pixel_value);
+            }
         }
     }
 }
@@ -0,0 +1,1030 @@
+use crate::chip8::Chip8;
+use crate::cpu::CpuError;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Number of registers: V0 to VF, I, PC, SP, DT and ST
+const REGISTER_COUNT: usize = 21;
+/// Number of the I register
+const REGISTER_I: usize = 16;
+/// Number of the PC register
+const REGISTER_PC: usize = 17;
+/// Number of the SP register
+const REGISTER_SP: usize = 18;
+/// Number of the DT register
+const REGISTER_DT: usize = 19;
+/// Number of the ST register
+const REGISTER_ST: usize = 20;
+
+/// Maximum size of a packet, advertised to the client
+const PACKET_SIZE: usize = 0x1000;
+
+/// Time waited for the next bytes of the client before giving the hand back to the frontend
+const READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Signal reported when the client interrupts the execution
+const SIGINT: u8 = 2;
+/// Signal reported when an unknown instruction is executed
+const SIGILL: u8 = 4;
+/// Signal reported on breakpoints and single steps
+const SIGTRAP: u8 = 5;
+/// Signal reported on invalid memory accesses and stack errors
+const SIGSEGV: u8 = 11;
+
+/// Target description, naming the registers for the clients
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.cpu">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Event received from the client
+enum Event {
+    /// A packet, without its framing and checksum
+    Packet(String),
+    /// A request to stop the execution (Ctrl-C)
+    Interrupt,
+}
+
+/// Connection with a debugger client
+struct Client {
+    /// The socket
+    stream: TcpStream,
+    /// Bytes received and not handled yet
+    input: Vec<u8>,
+    /// Are the packets acknowledged? Clients can disable it with `QStartNoAckMode`
+    ack: bool,
+}
+
+/// Server of the GDB remote serial protocol, letting a standard debugger client control the
+/// machine over a local TCP socket.
+///
+/// The registers are V0 to VF, I, PC, SP, DT and ST, numbered from 0 to 20, I and PC being 16
+/// bits little endian values. The memory of the machine is the target memory. Breakpoints,
+/// single steps, continue and interrupt (Ctrl-C) are supported, SP being read-only.
+///
+/// The server never blocks: the frontend calls `poll` regularly to accept a client and answer
+/// its requests, and runs its frames with `run_frame`, which stops on breakpoints. The machine
+/// is stopped when a client attaches, and resumes when it detaches.
+pub struct GdbServer {
+    /// The socket waiting for a client
+    listener: TcpListener,
+    /// The connected client, if any
+    client: Option<Client>,
+    /// Addresses of the breakpoints
+    breakpoints: BTreeSet<u16>,
+    /// Is the execution stopped by the client?
+    stopped: bool,
+    /// Is the execution resuming, the breakpoint at PC being ignored for the first instruction?
+    resuming: bool,
+}
+
+impl GdbServer {
+    /// Creates a new `GdbServer` listening on the given address
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The address to listen on, e.g. `("127.0.0.1", 9000)`
+    ///
+    /// # Returns
+    ///
+    /// A new `GdbServer` struct, or an `io::Error` if the address cannot be listened on
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbServer {
+            listener,
+            client: None,
+            breakpoints: BTreeSet::new(),
+            stopped: false,
+            resuming: false,
+        })
+    }
+
+    /// Gets the address the server listens on
+    ///
+    /// # Returns
+    ///
+    /// The address, or an `io::Error` if it cannot be read
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Is a client connected?
+    ///
+    /// # Returns
+    ///
+    /// `true` if a client is connected
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Is the execution stopped by the client?
+    ///
+    /// # Returns
+    ///
+    /// `true` if the frontend must not run frames
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Accepts a client if none is connected, then answers its pending requests. A client which
+    /// sends invalid data or closes the connection is detached.
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The machine controlled by the client
+    pub fn poll(&mut self, chip8: &mut Chip8) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if self.attach(stream).is_err() {
+                        self.detach();
+                        return;
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+        if self.serve(chip8).is_err() {
+            self.detach();
+        }
+    }
+
+    /// Runs one frame: `cycles_per_frame` instructions followed by one timers tick, like
+    /// `Chip8::run_frame`. With a client, the frame stops at breakpoints and instruction errors,
+    /// which are reported to the client, and nothing is run while the client stops the
+    /// execution.
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The machine
+    /// - `cycles_per_frame`: The number of instructions to run during the frame
+    ///
+    /// # Returns
+    ///
+    /// A `CpuError` if an instruction could not be executed without a client to report it to
+    pub fn run_frame(&mut self, chip8: &mut Chip8, cycles_per_frame: u32) -> Result<(), CpuError> {
+        if self.client.is_none() {
+            return chip8.run_frame(cycles_per_frame);
+        }
+        if self.stopped {
+            return Ok(());
+        }
+
+        for _ in 0..cycles_per_frame {
+            if !self.resuming && self.breakpoints.contains(&chip8.get_pc()) {
+                self.stop(SIGTRAP);
+                return Ok(());
+            }
+            self.resuming = false;
+            if let Err(error) = chip8.step_instruction() {
+                self.stop(error_signal(error));
+                return Ok(());
+            }
+            if chip8.has_exited() {
+                self.stop_exited();
+                return Ok(());
+            }
+        }
+        chip8.tick_timers();
+        Ok(())
+    }
+
+    /// Sets up a new client, stopping the execution
+    ///
+    /// # Parameters
+    ///
+    /// - `stream`: The socket of the client
+    ///
+    /// # Returns
+    ///
+    /// An `io::Error` if the socket cannot be configured
+    fn attach(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        self.client = Some(Client {
+            stream,
+            input: Vec::new(),
+            ack: true,
+        });
+        self.stopped = true;
+        Ok(())
+    }
+
+    /// Forgets the client and its breakpoints, resuming the execution
+    fn detach(&mut self) {
+        self.client = None;
+        self.breakpoints.clear();
+        self.stopped = false;
+        self.resuming = false;
+    }
+
+    /// Stops the execution and reports it to the client
+    ///
+    /// # Parameters
+    ///
+    /// - `signal`: The signal reported to the client
+    fn stop(&mut self, signal: u8) {
+        self.stopped = true;
+        if self.send_packet(&format!("S{:02x}", signal)).is_err() {
+            self.detach();
+        }
+    }
+
+    /// Reports the exit of the program to the client
+    fn stop_exited(&mut self) {
+        self.stopped = true;
+        if self.send_packet("W00").is_err() {
+            self.detach();
+        }
+    }
+
+    /// Answers the requests received from the client, until it waits for more than
+    /// `READ_TIMEOUT`
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The machine controlled by the client
+    ///
+    /// # Returns
+    ///
+    /// An `io::Error` if the client must be detached
+    fn serve(&mut self, chip8: &mut Chip8) -> io::Result<()> {
+        loop {
+            while let Some(event) = self.next_event()? {
+                match event {
+                    Event::Interrupt => {
+                        if !self.stopped {
+                            self.stop(SIGINT);
+                        }
+                    }
+                    Event::Packet(packet) => self.handle_packet(chip8, &packet)?,
+                }
+                if self.client.is_none() {
+                    return Ok(());
+                }
+            }
+
+            let client = match &mut self.client {
+                Some(client) => client,
+                None => return Ok(()),
+            };
+            let mut buffer = [0; 4096];
+            match client.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Ok(size) => client.input.extend_from_slice(&buffer[..size]),
+                Err(error)
+                    if error.kind() == io::ErrorKind::WouldBlock
+                        || error.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(())
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Extracts the next event from the bytes received, acknowledging the packets
+    ///
+    /// # Returns
+    ///
+    /// The event, None if more bytes are needed, or an `io::Error` if the acknowledgement
+    /// cannot be sent
+    fn next_event(&mut self) -> io::Result<Option<Event>> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return Ok(None),
+        };
+        loop {
+            match client.input.first() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    client.input.remove(0);
+                    return Ok(Some(Event::Interrupt));
+                }
+                Some(b'$') => break,
+                // Acknowledgements and noise between packets
+                Some(_) => {
+                    client.input.remove(0);
+                }
+            }
+        }
+
+        let end = match client.input.iter().position(|&byte| byte == b'#') {
+            Some(end) if end + 3 <= client.input.len() => end,
+            _ => return Ok(None),
+        };
+        let frame: Vec<u8> = client.input.drain(..end + 3).collect();
+        let data = &frame[1..end];
+        let checksum = std::str::from_utf8(&frame[end + 1..])
+            .ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+        if checksum != Some(checksum_of(data)) {
+            if client.ack {
+                client.stream.write_all(b"-")?;
+            }
+            return Ok(None);
+        }
+        if client.ack {
+            client.stream.write_all(b"+")?;
+        }
+        Ok(Some(Event::Packet(
+            String::from_utf8_lossy(&unescape(data)).into_owned(),
+        )))
+    }
+
+    /// Answers a packet
+    ///
+    /// # Parameters
+    ///
+    /// - `chip8`: The machine controlled by the client
+    /// - `packet`: The packet
+    ///
+    /// # Returns
+    ///
+    /// An `io::Error` if the answer cannot be sent
+    fn handle_packet(&mut self, chip8: &mut Chip8, packet: &str) -> io::Result<()> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REGISTER_COUNT)
+                .map(|number| encode_hex(&read_register(chip8, number)))
+                .collect(),
+            "G" => write_registers(chip8, args),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(number) if number < REGISTER_COUNT => encode_hex(&read_register(chip8, number)),
+                _ => String::from("E01"),
+            },
+            "P" => match args.split_once('=').and_then(|(number, value)| {
+                Some((usize::from_str_radix(number, 16).ok()?, decode_hex(value)?))
+            }) {
+                Some((number, value)) if write_register(chip8, number, &value) => {
+                    String::from("OK")
+                }
+                _ => String::from("E01"),
+            },
+            "m" => read_memory(chip8, args),
+            "M" => write_memory(chip8, args),
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(address) => chip8.set_pc(address),
+                        Err(_) => return self.send_packet("E01"),
+                    }
+                }
+                if command == "c" {
+                    // The stop is reported later, by `run_frame` or an interrupt
+                    self.stopped = false;
+                    self.resuming = true;
+                    return Ok(());
+                }
+                match chip8.step_instruction() {
+                    Ok(()) if chip8.has_exited() => String::from("W00"),
+                    Ok(()) => format!("S{:02x}", SIGTRAP),
+                    Err(error) => format!("S{:02x}", error_signal(error)),
+                }
+            }
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let address = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+                match (kind, address) {
+                    // Software and hardware breakpoints are the same for the emulator
+                    (Some("0" | "1"), Some(address)) => {
+                        if command == "Z" {
+                            self.breakpoints.insert(address);
+                        } else {
+                            self.breakpoints.remove(&address);
+                        }
+                        String::from("OK")
+                    }
+                    (Some("0" | "1"), None) => String::from("E01"),
+                    _ => String::new(),
+                }
+            }
+            "D" => {
+                self.send_packet("OK")?;
+                self.detach();
+                return Ok(());
+            }
+            "k" => {
+                self.detach();
+                return Ok(());
+            }
+            "H" | "T" => String::from("OK"),
+            "q" | "Q" | "v" => self.handle_query(packet),
+            _ => String::new(),
+        };
+        self.send_packet(&reply)
+    }
+
+    /// Answers a general query or setting packet
+    ///
+    /// # Parameters
+    ///
+    /// - `packet`: The packet
+    ///
+    /// # Returns
+    ///
+    /// The answer, empty for unsupported queries
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            );
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match range.split_once(',').and_then(|(offset, length)| {
+                Some((
+                    usize::from_str_radix(offset, 16).ok()?,
+                    usize::from_str_radix(length, 16).ok()?,
+                ))
+            }) {
+                Some(range) => range,
+                None => return String::from("E01"),
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = offset.saturating_add(length).min(xml.len());
+            let marker = if end < xml.len() { 'm' } else { 'l' };
+            return format!("{}{}", marker, String::from_utf8_lossy(&xml[start..end]));
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                if let Some(client) = &mut self.client {
+                    client.ack = false;
+                }
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    /// Sends a packet to the client
+    ///
+    /// # Parameters
+    ///
+    /// - `data`: The content of the packet
+    ///
+    /// # Returns
+    ///
+    /// An `io::Error` if the packet cannot be sent
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let escaped = escape(data.as_bytes());
+        let mut frame = Vec::with_capacity(escaped.len() + 4);
+        frame.push(b'$');
+        frame.extend_from_slice(&escaped);
+        frame.extend_from_slice(format!("#{:02x}", checksum_of(&escaped)).as_bytes());
+        client.stream.write_all(&frame)
+    }
+}
+
+/// Gets the signal reported for an instruction error
+///
+/// # Parameters
+///
+/// - `error`: The instruction error
+///
+/// # Returns
+///
+/// SIGILL for unknown instructions, SIGSEGV for memory and stack errors
+fn error_signal(error: CpuError) -> u8 {
+    match error {
+        CpuError::UnknownOpcode { .. } => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+/// Reads a register in the byte order of the protocol
+///
+/// # Parameters
+///
+/// - `chip8`: The machine
+/// - `number`: The register number, below `REGISTER_COUNT`
+///
+/// # Returns
+///
+/// The bytes of the register
+fn read_register(chip8: &Chip8, number: usize) -> Vec<u8> {
+    match number {
+        REGISTER_I => chip8.get_i().to_le_bytes().to_vec(),
+        REGISTER_PC => chip8.get_pc().to_le_bytes().to_vec(),
+        REGISTER_SP => vec![chip8.get_stack().len() as u8],
+        REGISTER_DT => vec![chip8.get_dt()],
+        REGISTER_ST => vec![chip8.get_st()],
+        x => vec![chip8.get_register(x as u8)],
+    }
+}
+
+/// Writes a register from the byte order of the protocol
+///
+/// # Parameters
+///
+/// - `chip8`: The machine
+/// - `number`: The register number
+/// - `value`: The bytes of the register
+///
+/// # Returns
+///
+/// `false` if the register does not exist, is read-only, or the value has the wrong size
+fn write_register(chip8: &mut Chip8, number: usize, value: &[u8]) -> bool {
+    match (number, value) {
+        (REGISTER_I, [low, high]) => chip8.set_i(u16::from_le_bytes([*low, *high])),
+        (REGISTER_PC, [low, high]) => chip8.set_pc(u16::from_le_bytes([*low, *high])),
+        (REGISTER_DT, [value]) => chip8.set_dt(*value),
+        (REGISTER_ST, [value]) => chip8.set_st(*value),
+        (x, [value]) if x < REGISTER_I => chip8.set_register(x as u8, *value),
+        _ => return false,
+    }
+    true
+}
+
+/// Writes all the registers from a `G` packet, SP being left unchanged
+///
+/// # Parameters
+///
+/// - `chip8`: The machine
+/// - `hex`: The hexadecimal bytes of the registers
+///
+/// # Returns
+///
+/// The answer to the packet
+fn write_registers(chip8: &mut Chip8, hex: &str) -> String {
+    let bytes = match decode_hex(hex) {
+        Some(bytes) => bytes,
+        None => return String::from("E01"),
+    };
+    let sizes: Vec<usize> = (0..REGISTER_COUNT)
+        .map(|number| read_register(chip8, number).len())
+        .collect();
+    if bytes.len() != sizes.iter().sum::<usize>() {
+        return String::from("E01");
+    }
+    let mut offset = 0;
+    for (number, size) in sizes.into_iter().enumerate() {
+        if number != REGISTER_SP {
+            write_register(chip8, number, &bytes[offset..offset + size]);
+        }
+        offset += size;
+    }
+    String::from("OK")
+}
+
+/// Reads memory for an `m addr,length` packet
+///
+/// # Parameters
+///
+/// - `chip8`: The machine
+/// - `args`: The address and length, in hexadecimal
+///
+/// # Returns
+///
+/// The hexadecimal bytes, fewer than requested at the end of the memory, or an error
+fn read_memory(chip8: &Chip8, args: &str) -> String {
+    let (address, length) = match parse_address_length(args) {
+        Some(range) => range,
+        None => return String::from("E01"),
+    };
+    let bytes: Vec<u8> = (0..length)
+        .map_while(|offset| {
+            let address = u16::try_from(address.checked_add(offset)?).ok()?;
+            chip8.read_memory(address).ok()
+        })
+        .collect();
+    if bytes.is_empty() && length > 0 {
+        return String::from("E01");
+    }
+    encode_hex(&bytes)
+}
+
+/// Writes memory for an `M addr,length:bytes` packet. Nothing is written unless the whole
+/// range is in the memory.
+///
+/// # Parameters
+///
+/// - `chip8`: The machine
+/// - `args`: The address and length, in hexadecimal, followed by the hexadecimal bytes
+///
+/// # Returns
+///
+/// The answer to the packet
+fn write_memory(chip8: &mut Chip8, args: &str) -> String {
+    let (range, hex) = match args.split_once(':') {
+        Some(split) => split,
+        None => return String::from("E01"),
+    };
+    let (address, bytes) = match (parse_address_length(range), decode_hex(hex)) {
+        (Some((address, length)), Some(bytes)) if bytes.len() == length => (address, bytes),
+        _ => return String::from("E01"),
+    };
+    let addresses: Option<Vec<u16>> = (0..bytes.len())
+        .map(|offset| {
+            let address = u16::try_from(address.checked_add(offset)?).ok()?;
+            chip8.read_memory(address).ok().map(|_| address)
+        })
+        .collect();
+    let addresses = match addresses {
+        Some(addresses) => addresses,
+        None => return String::from("E01"),
+    };
+    for (address, byte) in addresses.into_iter().zip(bytes) {
+        chip8
+            .write_memory(address, byte)
+            .expect("the addresses are in the memory");
+    }
+    String::from("OK")
+}
+
+/// Parses the `addr,length` arguments of the memory packets
+///
+/// # Parameters
+///
+/// - `args`: The address and length, in hexadecimal
+///
+/// # Returns
+///
+/// The address and length, or None if they are invalid
+fn parse_address_length(args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+/// Computes the checksum of a packet: the sum of its bytes, modulo 256
+///
+/// # Parameters
+///
+/// - `data`: The content of the packet, as sent
+///
+/// # Returns
+///
+/// The checksum
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Escapes the bytes of a packet which have a meaning in the protocol: `#`, `$`, `}` and `*`
+///
+/// # Parameters
+///
+/// - `data`: The content of the packet
+///
+/// # Returns
+///
+/// The escaped content, each special byte being sent as `}` followed by the byte xor 0x20
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+            escaped.push(b'}');
+            escaped.push(byte ^ 0x20);
+        } else {
+            escaped.push(byte);
+        }
+    }
+    escaped
+}
+
+/// Reverts `escape`
+///
+/// # Parameters
+///
+/// - `data`: The content of the packet, as received
+///
+/// # Returns
+///
+/// The unescaped content
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => {
+                if let Some(&next) = bytes.next() {
+                    unescaped.push(next ^ 0x20);
+                }
+            }
+            _ => unescaped.push(byte),
+        }
+    }
+    unescaped
+}
+
+/// Encodes bytes in hexadecimal
+///
+/// # Parameters
+///
+/// - `bytes`: The bytes
+///
+/// # Returns
+///
+/// Two lower case hexadecimal digits per byte
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes hexadecimal bytes
+///
+/// # Parameters
+///
+/// - `hex`: Two hexadecimal digits per byte
+///
+/// # Returns
+///
+/// The bytes, or None if the text is not hexadecimal bytes
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LD V0, #01; ADD V0, #01; JP #202
+    const PROGRAM: [u8; 6] = [0x60, 0x01, 0x70, 0x01, 0x12, 0x02];
+
+    /// A server with a connected client
+    struct Session {
+        server: GdbServer,
+        client: TcpStream,
+        chip8: Chip8,
+    }
+
+    impl Session {
+        /// Starts a server, connects a client to it and waits until it is attached
+        fn connect() -> Session {
+            let mut server = GdbServer::bind(("127.0.0.1", 0)).unwrap();
+            let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_millis(10)))
+                .unwrap();
+            let mut chip8 = Chip8::new();
+            chip8.load_rom(&PROGRAM).unwrap();
+            for _ in 0..1000 {
+                server.poll(&mut chip8);
+                if server.is_connected() {
+                    return Session {
+                        server,
+                        client,
+                        chip8,
+                    };
+                }
+            }
+            panic!("the client is not attached");
+        }
+
+        /// Sends raw bytes, then answers them
+        fn send(&mut self, bytes: &[u8]) {
+            self.client.write_all(bytes).unwrap();
+            self.server.poll(&mut self.chip8);
+        }
+
+        /// Receives the next packet, skipping the acknowledgements
+        fn receive(&mut self) -> String {
+            let mut received = Vec::new();
+            for _ in 0..500 {
+                self.server.poll(&mut self.chip8);
+                let mut buffer = [0; 4096];
+                match self.client.read(&mut buffer) {
+                    Ok(size) => received.extend_from_slice(&buffer[..size]),
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(error) if error.kind() == io::ErrorKind::TimedOut => {}
+                    Err(error) => panic!("{}", error),
+                }
+                let start = received.iter().position(|&byte| byte == b'$');
+                let end = received.iter().position(|&byte| byte == b'#');
+                if let (Some(start), Some(end)) = (start, end) {
+                    if end + 3 <= received.len() {
+                        let data = &received[start + 1..end];
+                        let checksum = std::str::from_utf8(&received[end + 1..end + 3]).unwrap();
+                        assert_eq!(checksum, format!("{:02x}", checksum_of(data)));
+                        return String::from_utf8(unescape(data)).unwrap();
+                    }
+                }
+            }
+            panic!(
+                "no packet received: {:?}",
+                String::from_utf8_lossy(&received)
+            );
+        }
+
+        /// Sends a packet and receives the answer
+        fn request(&mut self, packet: &str) -> String {
+            let escaped = escape(packet.as_bytes());
+            let mut frame = vec![b'$'];
+            frame.extend_from_slice(&escaped);
+            frame.extend_from_slice(format!("#{:02x}", checksum_of(&escaped)).as_bytes());
+            self.send(&frame);
+            self.receive()
+        }
+    }
+
+    #[test]
+    fn checksum_is_the_sum_of_the_bytes_modulo_256() {
+        assert_eq!(checksum_of(b""), 0x00);
+        assert_eq!(checksum_of(b"OK"), 0x9A);
+        assert_eq!(checksum_of(&[0xFF, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn packet_with_a_bad_checksum_is_rejected() {
+        let mut session = Session::connect();
+
+        session.send(b"$?#00");
+        let mut answer = [0; 1];
+        session.client.read_exact(&mut answer).unwrap();
+        assert_eq!(&answer, b"-");
+
+        assert_eq!(session.request("?"), "S05");
+    }
+
+    #[test]
+    fn hex_is_decoded_by_pairs_of_digits() {
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xFF, 0x7A]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("é0"), None);
+        assert_eq!(encode_hex(&[0x00, 0xFF, 0x7A]), "00ff7a");
+    }
+
+    #[test]
+    fn special_bytes_are_escaped() {
+        let data = b"a#b$c}d*e";
+
+        assert_eq!(escape(data), b"a}\x03b}\x04c}\x5dd}\x0ae");
+        assert_eq!(unescape(&escape(data)), data);
+    }
+
+    #[test]
+    fn attached_client_stops_the_execution() {
+        let mut session = Session::connect();
+
+        assert!(session.server.is_stopped());
+        session.server.run_frame(&mut session.chip8, 10).unwrap();
+        assert_eq!(session.chip8.get_pc(), 0x200);
+    }
+
+    #[test]
+    fn g_reads_every_register() {
+        let mut session = Session::connect();
+        session.chip8.set_register(0x3, 0x2A);
+        session.chip8.set_i(0x1234);
+        session.chip8.set_dt(7);
+
+        let registers = session.request("g");
+        assert_eq!(registers.len(), 2 * (16 + 2 + 2 + 3));
+        assert_eq!(&registers[6..8], "2a");
+        // I, PC, SP, DT and ST
+        assert_eq!(&registers[32..], "34120002000700");
+    }
+
+    #[test]
+    fn big_g_writes_every_register_but_sp() {
+        let mut session = Session::connect();
+        // V0 to VF, then I, PC, SP, DT and ST
+        let registers = "G000102030405060708090a0b0c0d0e0f34120403050607";
+
+        assert_eq!(session.request(registers), "OK");
+        assert_eq!(session.chip8.get_register(0xF), 0x0F);
+        assert_eq!(session.chip8.get_i(), 0x1234);
+        assert_eq!(session.chip8.get_pc(), 0x0304);
+        assert!(session.chip8.get_stack().is_empty());
+        assert_eq!(session.chip8.get_dt(), 6);
+        assert_eq!(session.chip8.get_st(), 7);
+
+        assert_eq!(session.request("G0001"), "E01");
+        assert_eq!(session.chip8.get_register(0), 0x00);
+    }
+
+    #[test]
+    fn m_reads_memory() {
+        let mut session = Session::connect();
+        session.chip8.write_memory(0xFFF, 0xAB).unwrap();
+
+        assert_eq!(session.request("m200,6"), "600170011202");
+        assert_eq!(session.request("mfff,4"), "ab");
+        assert_eq!(session.request("m1000,1"), "E01");
+        assert_eq!(session.request("m200"), "E01");
+    }
+
+    #[test]
+    fn big_m_writes_memory() {
+        let mut session = Session::connect();
+
+        assert_eq!(session.request("M300,3:01237d"), "OK");
+        assert_eq!(session.request("m300,3"), "01237d");
+        assert_eq!(session.request("M300,2:01"), "E01");
+        assert_eq!(session.request("M300,1:zz"), "E01");
+    }
+
+    #[test]
+    fn big_m_out_of_the_memory_writes_nothing() {
+        let mut session = Session::connect();
+
+        assert_eq!(session.request("Mffe,3:010203"), "E01");
+        assert_eq!(session.request("mffe,2"), "0000");
+        assert_eq!(session.request("Mffffffffffffffff,2:0102"), "E01");
+    }
+
+    #[test]
+    fn z_sets_breakpoints_which_stop_the_execution() {
+        let mut session = Session::connect();
+
+        assert_eq!(session.request("Z0,204,2"), "OK");
+        session.send(b"$c#63");
+        assert!(!session.server.is_stopped());
+        session.server.run_frame(&mut session.chip8, 10).unwrap();
+        assert!(session.server.is_stopped());
+        assert_eq!(session.chip8.get_pc(), 0x204);
+        assert_eq!(session.receive(), "S05");
+
+        // Continuing runs the instruction at the breakpoint, then stops there again
+        session.send(b"$c#63");
+        session.server.run_frame(&mut session.chip8, 10).unwrap();
+        assert_eq!(session.chip8.get_pc(), 0x204);
+        assert_eq!(session.receive(), "S05");
+        assert_eq!(session.chip8.get_register(0), 3);
+    }
+
+    #[test]
+    fn small_z_removes_breakpoints() {
+        let mut session = Session::connect();
+
+        assert_eq!(session.request("Z1,204,2"), "OK");
+        assert_eq!(session.request("z1,204,2"), "OK");
+        session.send(b"$c#63");
+        session.server.run_frame(&mut session.chip8, 10).unwrap();
+        assert!(!session.server.is_stopped());
+        assert_eq!(session.chip8.get_register(0), 6);
+
+        assert_eq!(session.request("Z0,zz,2"), "E01");
+        assert_eq!(session.request("Z2,204,2"), "");
+    }
+
+    #[test]
+    fn s_steps_one_instruction() {
+        let mut session = Session::connect();
+
+        assert_eq!(session.request("s"), "S05");
+        assert_eq!(session.chip8.get_pc(), 0x202);
+        assert!(session.server.is_stopped());
+    }
+
+    #[test]
+    fn detach_resumes_the_execution() {
+        let mut session = Session::connect();
+        assert_eq!(session.request("Z0,202,2"), "OK");
+
+        assert_eq!(session.request("D"), "OK");
+        assert!(!session.server.is_connected());
+        session.server.run_frame(&mut session.chip8, 10).unwrap();
+        assert_eq!(session.chip8.get_register(0), 6);
+    }
+}
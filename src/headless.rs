@@ -0,0 +1,235 @@
+use crate::chip8::RunError;
+use crate::cpu::CpuError;
+use crate::keyboard::KEY_COUNT;
+use crate::movie::Divergence;
+use crate::png::Palette;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Error returned when parsing an invalid key script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidKeyScript(pub String);
+
+impl fmt::Display for InvalidKeyScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid key script '{}' (expected <FRAME>:<KEYS>,... with increasing frames and \
+             hexadecimal keys)",
+            self.0
+        )
+    }
+}
+
+impl error::Error for InvalidKeyScript {}
+
+/// Keys pressed during a headless run: each step holds a set of keys down from a frame until the
+/// next step
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    /// The first frame of each step and the keys held down during the step, in frame order
+    steps: Vec<(u32, [bool; KEY_COUNT])>,
+}
+
+impl KeyScript {
+    /// Gets the keys held down during a frame
+    ///
+    /// # Parameters
+    ///
+    /// - `frame`: The frame number, starting at 0
+    ///
+    /// # Returns
+    ///
+    /// The state of each key, `true` when the key is down
+    pub fn keys_at(&self, frame: u32) -> [bool; KEY_COUNT] {
+        self.steps
+            .iter()
+            .take_while(|(start, _)| *start <= frame)
+            .last()
+            .map_or([false; KEY_COUNT], |(_, keys)| *keys)
+    }
+}
+
+impl FromStr for KeyScript {
+    type Err = InvalidKeyScript;
+
+    /// Parses comma separated `<FRAME>:<KEYS>` steps, frames being decimal and increasing and
+    /// keys being hexadecimal digits, e.g. `0:5,10:,60:46` holds 5 during the frames 0 to 9,
+    /// nothing during the frames 10 to 59, then 4 and 6.
+    fn from_str(spec: &str) -> Result<KeyScript, InvalidKeyScript> {
+        let invalid = || InvalidKeyScript(spec.to_string());
+        let mut script = KeyScript::default();
+        for step in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+        {
+            let (frame, keys) = step.split_once(':').ok_or_else(invalid)?;
+            let frame: u32 = frame.trim().parse().map_err(|_| invalid())?;
+            if script.steps.last().is_some_and(|(last, _)| *last >= frame) {
+                return Err(invalid());
+            }
+            let mut state = [false; KEY_COUNT];
+            for key in keys.trim().chars() {
+                let key = key.to_digit(16).ok_or_else(invalid)?;
+                state[key as usize] = true;
+            }
+            script.steps.push((frame, state));
+        }
+        Ok(script)
+    }
+}
+
+/// Settings of the headless frontend started by `Chip8::run_headless`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadlessConfig {
+    /// Number of instructions executed per second
+    pub cpu_hz: u32,
+    /// Number of frames to run
+    pub frames: u32,
+    /// Path of the screenshot of the last frame, or path template of the screenshots taken
+    /// every `every` frames (see `numbered_path`)
+    pub screenshot: Option<PathBuf>,
+    /// Take a screenshot every given number of frames instead of after the last frame
+    pub every: Option<u32>,
+    /// Size of a Chip8 pixel in the screenshots, in image pixels
+    pub scale: usize,
+    /// Colors of the screenshots
+    pub palette: Palette,
+    /// Keys pressed during the run
+    pub keys: KeyScript,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> HeadlessConfig {
+        HeadlessConfig {
+            cpu_hz: 500,
+            frames: 600,
+            screenshot: None,
+            every: None,
+            scale: 10,
+            palette: Palette::default(),
+            keys: KeyScript::default(),
+        }
+    }
+}
+
+/// Error that stopped the headless frontend
+#[derive(Debug)]
+pub enum HeadlessError {
+    /// The emulation failed
+    Run(RunError),
+    /// A screenshot could not be written
+    Screenshot {
+        /// Path of the screenshot
+        path: PathBuf,
+        /// The write error
+        error: io::Error,
+    },
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::Run(error) => error.fmt(f),
+            HeadlessError::Screenshot { path, error } => {
+                write!(f, "cannot write screenshot '{}': {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl error::Error for HeadlessError {}
+
+impl From<RunError> for HeadlessError {
+    fn from(error: RunError) -> HeadlessError {
+        HeadlessError::Run(error)
+    }
+}
+
+impl From<CpuError> for HeadlessError {
+    fn from(error: CpuError) -> HeadlessError {
+        HeadlessError::Run(RunError::Cpu(error))
+    }
+}
+
+impl From<Divergence> for HeadlessError {
+    fn from(divergence: Divergence) -> HeadlessError {
+        HeadlessError::Run(RunError::Divergence(divergence))
+    }
+}
+
+/// Gets the path of a screenshot taken every few frames: the frame number is appended to the
+/// file stem, e.g. `out.png` becomes `out-000060.png` for the frame 60
+///
+/// # Parameters
+///
+/// - `path`: The path template
+/// - `frame`: The number of frames run before the screenshot
+///
+/// # Returns
+///
+/// The path of the screenshot
+pub fn numbered_path(path: &Path, frame: u32) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{:06}", frame));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a keypad state with the given keys down
+    fn keys(pressed: &[usize]) -> [bool; KEY_COUNT] {
+        let mut keys = [false; KEY_COUNT];
+        for key in pressed {
+            keys[*key] = true;
+        }
+        keys
+    }
+
+    #[test]
+    fn key_script_holds_keys_until_the_next_step() {
+        let script: KeyScript = "5:a, 10:, 60:46".parse().unwrap();
+        assert_eq!(script.keys_at(0), keys(&[]));
+        assert_eq!(script.keys_at(5), keys(&[0xA]));
+        assert_eq!(script.keys_at(9), keys(&[0xA]));
+        // An empty step releases every key
+        assert_eq!(script.keys_at(10), keys(&[]));
+        assert_eq!(script.keys_at(59), keys(&[]));
+        assert_eq!(script.keys_at(60), keys(&[4, 6]));
+        assert_eq!(script.keys_at(1000), keys(&[4, 6]));
+
+        assert_eq!("".parse(), Ok(KeyScript::default()));
+    }
+
+    #[test]
+    fn invalid_key_script_is_rejected() {
+        for spec in ["10:5,10:6", "10:5,5:6", "5", "x:5", "5:g", "-1:5"] {
+            assert_eq!(
+                spec.parse::<KeyScript>(),
+                Err(InvalidKeyScript(spec.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn screenshot_paths_are_numbered() {
+        assert_eq!(
+            numbered_path(Path::new("out.png"), 60),
+            Path::new("out-000060.png")
+        );
+        assert_eq!(
+            numbered_path(Path::new("shots/frame"), 7),
+            Path::new("shots/frame-000007")
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+///
+/// The operands are named like in the opcodes: `x` and `y` are register numbers, `kk` a byte,
+/// `nnn` an address and `n` a nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00E0 - CLS
+    Cls,
+    /// 00EE - RET
+    Ret,
+    /// 00Cn - SCD n (SUPER-CHIP)
+    Scd(u8),
+    /// 00Dn - SCU n (XO-CHIP)
+    Scu(u8),
+    /// 00FB - SCR (SUPER-CHIP)
+    Scr,
+    /// 00FC - SCL (SUPER-CHIP)
+    Scl,
+    /// 00FD - EXIT (SUPER-CHIP)
+    Exit,
+    /// 00FE - LOW (SUPER-CHIP)
+    Low,
+    /// 00FF - HIGH (SUPER-CHIP)
+    High,
+    /// 1nnn - JP nnn
+    Jp(u16),
+    /// 2nnn - CALL nnn
+    Call(u16),
+    /// 3xkk - SE Vx, kk
+    SeByte(u8, u8),
+    /// 4xkk - SNE Vx, kk
+    SneByte(u8, u8),
+    /// 5xy0 - SE Vx, Vy
+    SeReg(u8, u8),
+    /// 5xy2 - LD [I], Vx-Vy (XO-CHIP)
+    StoreRange(u8, u8),
+    /// 5xy3 - LD Vx-Vy, [I] (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 6xkk - LD Vx, kk
+    LdByte(u8, u8),
+    /// 7xkk - ADD Vx, kk
+    AddByte(u8, u8),
+    /// 8xy0 - LD Vx, Vy
+    LdReg(u8, u8),
+    /// 8xy1 - OR Vx, Vy
+    Or(u8, u8),
+    /// 8xy2 - AND Vx, Vy
+    And(u8, u8),
+    /// 8xy3 - XOR Vx, Vy
+    Xor(u8, u8),
+    /// 8xy4 - ADD Vx, Vy
+    AddReg(u8, u8),
+    /// 8xy5 - SUB Vx, Vy
+    Sub(u8, u8),
+    /// 8xy6 - SHR Vx, Vy
+    Shr(u8, u8),
+    /// 8xy7 - SUBN Vx, Vy
+    Subn(u8, u8),
+    /// 8xyE - SHL Vx, Vy
+    Shl(u8, u8),
+    /// 9xy0 - SNE Vx, Vy
+    SneReg(u8, u8),
+    /// Annn - LD I, nnn
+    LdI(u16),
+    /// Bnnn - JP V0, nnn
+    JpV0(u16),
+    /// Cxkk - RND Vx, kk
+    Rnd(u8, u8),
+    /// Dxyn - DRW Vx, Vy, n
+    Drw(u8, u8, u8),
+    /// Ex9E - SKP Vx
+    Skp(u8),
+    /// ExA1 - SKNP Vx
+    Sknp(u8),
+    /// F000 nnnn - LD I, LONG nnnn (XO-CHIP), the address being the word after the opcode
+    LdILong,
+    /// Fn01 - PLANE n (XO-CHIP)
+    Plane(u8),
+    /// F002 - AUDIO (XO-CHIP)
+    Audio,
+    /// Fx07 - LD Vx, DT
+    LdVxDt(u8),
+    /// Fx0A - LD Vx, K
+    LdVxK(u8),
+    /// Fx15 - LD DT, Vx
+    LdDtVx(u8),
+    /// Fx18 - LD ST, Vx
+    LdStVx(u8),
+    /// Fx1E - ADD I, Vx
+    AddI(u8),
+    /// Fx29 - LD F, Vx
+    LdF(u8),
+    /// Fx30 - LD HF, Vx (SUPER-CHIP)
+    LdHf(u8),
+    /// Fx33 - LD B, Vx
+    LdB(u8),
+    /// Fx3A - PITCH Vx (XO-CHIP)
+    Pitch(u8),
+    /// Fx55 - LD [I], Vx
+    Store(u8),
+    /// Fx65 - LD Vx, [I]
+    Load(u8),
+    /// Fx75 - LD R, Vx (SUPER-CHIP)
+    StoreFlags(u8),
+    /// Fx85 - LD Vx, R (SUPER-CHIP)
+    LoadFlags(u8),
+}
+
+impl Instruction {
+    /// Gets the size of the instruction in memory
+    ///
+    /// # Returns
+    ///
+    /// The size of the instruction, in bytes: 4 for `LD I, LONG nnnn`, else 2
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+
+    /// Encodes the instruction, `decode(instruction.encode())` giving back the instruction
+    ///
+    /// # Returns
+    ///
+    /// The opcode, the bits ignored by the CPU being set to 0. For `LD I, LONG nnnn` it is only
+    /// the first word (F000), the address being the following word.
+    pub fn encode(&self) -> u16 {
+        let xy = |prefix: u16, x: u8, y: u8, n: u16| {
+            prefix << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n
+        };
+        let xkk = |prefix: u16, x: u8, kk: u8| prefix << 12 | (x as u16 & 0xF) << 8 | kk as u16;
+
+        match *self {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Scd(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::Scu(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::Scr => 0x00FB,
+            Instruction::Scl => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(nnn) => 0x1000 | (nnn & 0xFFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0xFFF),
+            Instruction::SeByte(x, kk) => xkk(0x3, x, kk),
+            Instruction::SneByte(x, kk) => xkk(0x4, x, kk),
+            Instruction::SeReg(x, y) => xy(0x5, x, y, 0x0),
+            Instruction::StoreRange(x, y) => xy(0x5, x, y, 0x2),
+            Instruction::LoadRange(x, y) => xy(0x5, x, y, 0x3),
+            Instruction::LdByte(x, kk) => xkk(0x6, x, kk),
+            Instruction::AddByte(x, kk) => xkk(0x7, x, kk),
+            Instruction::LdReg(x, y) => xy(0x8, x, y, 0x0),
+            Instruction::Or(x, y) => xy(0x8, x, y, 0x1),
+            Instruction::And(x, y) => xy(0x8, x, y, 0x2),
+            Instruction::Xor(x, y) => xy(0x8, x, y, 0x3),
+            Instruction::AddReg(x, y) => xy(0x8, x, y, 0x4),
+            Instruction::Sub(x, y) => xy(0x8, x, y, 0x5),
+            Instruction::Shr(x, y) => xy(0x8, x, y, 0x6),
+            Instruction::Subn(x, y) => xy(0x8, x, y, 0x7),
+            Instruction::Shl(x, y) => xy(0x8, x, y, 0xE),
+            Instruction::SneReg(x, y) => xy(0x9, x, y, 0x0),
+            Instruction::LdI(nnn) => 0xA000 | (nnn & 0xFFF),
+            Instruction::JpV0(nnn) => 0xB000 | (nnn & 0xFFF),
+            Instruction::Rnd(x, kk) => xkk(0xC, x, kk),
+            Instruction::Drw(x, y, n) => xy(0xD, x, y, n as u16 & 0xF),
+            Instruction::Skp(x) => xkk(0xE, x, 0x9E),
+            Instruction::Sknp(x) => xkk(0xE, x, 0xA1),
+            Instruction::LdILong => 0xF000,
+            Instruction::Plane(n) => xkk(0xF, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt(x) => xkk(0xF, x, 0x07),
+            Instruction::LdVxK(x) => xkk(0xF, x, 0x0A),
+            Instruction::LdDtVx(x) => xkk(0xF, x, 0x15),
+            Instruction::LdStVx(x) => xkk(0xF, x, 0x18),
+            Instruction::AddI(x) => xkk(0xF, x, 0x1E),
+            Instruction::LdF(x) => xkk(0xF, x, 0x29),
+            Instruction::LdHf(x) => xkk(0xF, x, 0x30),
+            Instruction::LdB(x) => xkk(0xF, x, 0x33),
+            Instruction::Pitch(x) => xkk(0xF, x, 0x3A),
+            Instruction::Store(x) => xkk(0xF, x, 0x55),
+            Instruction::Load(x) => xkk(0xF, x, 0x65),
+            Instruction::StoreFlags(x) => xkk(0xF, x, 0x75),
+            Instruction::LoadFlags(x) => xkk(0xF, x, 0x85),
+        }
+    }
+
+    /// Gets the address operand of the instruction, which the disassembler replaces with a label
+    ///
+    /// # Returns
+    ///
+    /// The address of `JP`, `CALL`, `LD I` and `JP V0`, else None
+    pub fn get_address(&self) -> Option<u16> {
+        match *self {
+            Instruction::Jp(nnn)
+            | Instruction::Call(nnn)
+            | Instruction::LdI(nnn)
+            | Instruction::JpV0(nnn) => Some(nnn),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes an opcode
+///
+/// The SUPER-CHIP and XO-CHIP instructions are decoded whatever the quirk profile: none of their
+/// opcodes is a CHIP-8 instruction, so they never change how a CHIP-8 program runs, and the
+/// disassembler, the assembler and the debugger work on any ROM without knowing its profile.
+///
+/// # Parameters
+///
+/// - `opcode`: The opcode, as stored in memory (big endian)
+///
+/// # Returns
+///
+/// The instruction, or None if the opcode does not match any known instruction
+pub fn decode(opcode: u16) -> Option<Instruction> {
+    let nnn: u16 = opcode & 0x0FFF;
+    let n: u8 = (opcode & 0x000F) as u8;
+    let x: u8 = ((opcode & 0x0F00) >> 8) as u8;
+    let y: u8 = ((opcode & 0x00F0) >> 4) as u8;
+    let kk: u8 = (opcode & 0x00FF) as u8;
+
+    let instruction = match (opcode & 0xF000) >> 12 {
+        0x0 => match kk {
+            0xE0 => Instruction::Cls,
+            0xEE => Instruction::Ret,
+            0xFB => Instruction::Scr,
+            0xFC => Instruction::Scl,
+            0xFD => Instruction::Exit,
+            0xFE => Instruction::Low,
+            0xFF => Instruction::High,
+            _ if y == 0xC => Instruction::Scd(n),
+            _ if y == 0xD => Instruction::Scu(n),
+            _ => return None,
+        },
+
+        0x1 => Instruction::Jp(nnn),
+        0x2 => Instruction::Call(nnn),
+
+        0x3 => Instruction::SeByte(x, kk),
+        0x4 => Instruction::SneByte(x, kk),
+        0x5 => match n {
+            0x0 => Instruction::SeReg(x, y),
+            0x2 => Instruction::StoreRange(x, y),
+            0x3 => Instruction::LoadRange(x, y),
+            _ => return None,
+        },
+        0x6 => Instruction::LdByte(x, kk),
+        0x7 => Instruction::AddByte(x, kk),
+
+        0x8 => match n {
+            0x0 => Instruction::LdReg(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::Xor(x, y),
+            0x4 => Instruction::AddReg(x, y),
+            0x5 => Instruction::Sub(x, y),
+            0x6 => Instruction::Shr(x, y),
+            0x7 => Instruction::Subn(x, y),
+            0xE => Instruction::Shl(x, y),
+            _ => return None,
+        },
+
+        0x9 => Instruction::SneReg(x, y),
+        0xA => Instruction::LdI(nnn),
+        0xB => Instruction::JpV0(nnn),
+        0xC => Instruction::Rnd(x, kk),
+        0xD => Instruction::Drw(x, y, n),
+
+        0xE => match kk {
+            0x9E => Instruction::Skp(x),
+            0xA1 => Instruction::Sknp(x),
+            _ => return None,
+        },
+
+        0xF => match kk {
+            0x00 if x == 0 => Instruction::LdILong,
+            0x01 => Instruction::Plane(x),
+            0x02 if x == 0 => Instruction::Audio,
+            0x07 => Instruction::LdVxDt(x),
+            0x0A => Instruction::LdVxK(x),
+            0x15 => Instruction::LdDtVx(x),
+            0x18 => Instruction::LdStVx(x),
+            0x1E => Instruction::AddI(x),
+            0x29 => Instruction::LdF(x),
+            0x30 => Instruction::LdHf(x),
+            0x33 => Instruction::LdB(x),
+            0x3A => Instruction::Pitch(x),
+            0x55 => Instruction::Store(x),
+            0x65 => Instruction::Load(x),
+            0x75 => Instruction::StoreFlags(x),
+            0x85 => Instruction::LoadFlags(x),
+            _ => return None,
+        },
+
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Scd(n) => write!(f, "SCD {}", n),
+            Instruction::Scu(n) => write!(f, "SCU {}", n),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(nnn) => write!(f, "JP #{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL #{:03X}", nnn),
+            Instruction::SeByte(x, kk) => write!(f, "SE V{:X}, #{:02X}", x, kk),
+            Instruction::SneByte(x, kk) => write!(f, "SNE V{:X}, #{:02X}", x, kk),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::StoreRange(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::LdByte(x, kk) => write!(f, "LD V{:X}, #{:02X}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, #{:02X}", x, kk),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => write!(f, "LD I, #{:03X}", nnn),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, #{:03X}", nnn),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, #{:02X}", x, kk),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trips() {
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = decode(opcode) {
+                assert_eq!(decode(instruction.encode()), Some(instruction));
+            }
+        }
+    }
+
+    #[test]
+    fn encode_only_clears_the_ignored_bits() {
+        for opcode in 0..=0xFFFF_u16 {
+            let ignored_bits = match opcode >> 12 {
+                // x of 00E0, 00Cn, ... and n of 9xy0
+                0x0 => 0x0F00,
+                0x9 => 0x000F,
+                _ => 0,
+            };
+            if let Some(instruction) = decode(opcode) {
+                assert_eq!(
+                    instruction.encode(),
+                    opcode & !ignored_bits,
+                    "{:04X}",
+                    opcode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_are_not_decoded() {
+        for &opcode in &[0x0000, 0x0123, 0x5121, 0x800F, 0xE000, 0xF100, 0xF0FF] {
+            assert_eq!(decode(opcode), None, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn instructions_are_displayed_as_mnemonics() {
+        let cases = [
+            (0x00C4, "SCD 4"),
+            (0x1208, "JP #208"),
+            (0x5AB2, "LD [I], VA-VB"),
+            (0x6A02, "LD VA, #02"),
+            (0x8AB6, "SHR VA, VB"),
+            (0xB300, "JP V0, #300"),
+            (0xD01F, "DRW V0, V1, 15"),
+            (0xF000, "LD I, LONG"),
+            (0xF201, "PLANE 2"),
+            (0xF30A, "LD V3, K"),
+            (0xF465, "LD V4, [I]"),
+        ];
+        for &(opcode, mnemonic) in &cases {
+            assert_eq!(decode(opcode).unwrap().to_string(), mnemonic);
+        }
+    }
+
+    #[test]
+    fn only_long_load_takes_four_bytes() {
+        assert_eq!(Instruction::LdILong.size(), 4);
+        assert_eq!(Instruction::LdI(0x300).size(), 2);
+    }
+}
@@ -1,36 +1,64 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Number of keys on the Chip8 keyboard
+pub const KEY_COUNT: usize = 16;
 
 /// Chip8 keyboard struct
 pub struct Keyboard {
-    /// The keyboard key currently pressed
-    pressed_key: Option<u8>,
+    /// The state of each key, `true` when the key is down
+    keys: [bool; KEY_COUNT],
+    /// The keys pressed since their last press was taken, one bit per key
+    pressed_edges: u16,
+    /// The keys released since their last release was taken, one bit per key
+    released_edges: u16,
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard::new()
+    }
 }
 
 impl Keyboard {
-    /// Creates and returns a new `Keyboard` struct.
+    /// Creates and returns a new `Keyboard` struct, with every key up.
     ///
     /// # Returns
     ///
     /// A new `Keyboard` struct.
     pub fn new() -> Keyboard {
-        Keyboard { pressed_key: None }
+        Keyboard {
+            keys: [false; KEY_COUNT],
+            pressed_edges: 0,
+            released_edges: 0,
+        }
     }
 
-    /// Sets keyboard pressed key
+    /// Sets the state of a key, recording a press or release edge if it changes
     ///
     /// # Parameters
     ///
-    /// - `key`: The value of the pressed key (an u8 or None)
-    pub fn set_pressed_key(&mut self, key: Option<u8>) {
-        self.pressed_key = key;
+    /// - `key`: The value of the key (0x0 to 0xF)
+    /// - `pressed`: `true` if the key is down, `false` if it is up
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let index = (key & 0xF) as usize;
+        if self.keys[index] == pressed {
+            return;
+        }
+        self.keys[index] = pressed;
+        if pressed {
+            self.pressed_edges |= 1 << index;
+        } else {
+            self.released_edges |= 1 << index;
+        }
     }
 
-    /// Gets keyboard pressed key
+    /// Gets the state of every key
     ///
     /// # Returns
     ///
-    /// The value of the pressed key (an u8 or None)
-    pub fn get_pressed_key(&self) -> Option<u8> {
-        self.pressed_key
+    /// The state of each key, `true` when the key is down
+    pub fn get_keys(&self) -> [bool; KEY_COUNT] {
+        self.keys
     }
 
     /// Is the given key currently pressed?
@@ -43,10 +71,85 @@ impl Keyboard {
     ///
     /// `true` if the key is pressed, else `false`
     pub fn is_key_pressed(&self, key_code: u8) -> bool {
-        if let Some(key) = self.pressed_key {
-            key == key_code
-        } else {
-            false
+        self.keys[(key_code & 0xF) as usize]
+    }
+
+    /// Takes the press edge of the lowest key pressed since its last press was taken
+    ///
+    /// # Returns
+    ///
+    /// The value of the pressed key, or None if no key has been pressed
+    pub fn take_pressed_key(&mut self) -> Option<u8> {
+        take_lowest_edge(&mut self.pressed_edges)
+    }
+
+    /// Takes the release edge of the lowest key released since its last release was taken
+    ///
+    /// # Returns
+    ///
+    /// The value of the released key, or None if no key has been released
+    pub fn take_released_key(&mut self) -> Option<u8> {
+        take_lowest_edge(&mut self.released_edges)
+    }
+
+    /// Forgets every press and release edge not taken yet
+    pub fn clear_edges(&mut self) {
+        self.pressed_edges = 0;
+        self.released_edges = 0;
+    }
+
+    /// Writes the state of every key and the edges not taken yet in a save state
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The save state writer
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let mut keys = 0;
+        for (key, pressed) in self.keys.iter().enumerate() {
+            if *pressed {
+                keys |= 1 << key;
+            }
         }
+        writer.write_u16(keys);
+        writer.write_u16(self.pressed_edges);
+        writer.write_u16(self.released_edges);
+    }
+
+    /// Restores the state of every key and the edges not taken yet from a save state written by
+    /// `save_state`
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The save state reader
+    ///
+    /// # Returns
+    ///
+    /// A `StateError` if the save state is truncated
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let keys = reader.read_u16()?;
+        for (key, pressed) in self.keys.iter_mut().enumerate() {
+            *pressed = keys & (1 << key) != 0;
+        }
+        self.pressed_edges = reader.read_u16()?;
+        self.released_edges = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+/// Clears the lowest bit set in the given edges
+///
+/// # Parameters
+///
+/// - `edges`: The edges, one bit per key
+///
+/// # Returns
+///
+/// The value of the key whose edge was cleared, or None if there was no edge
+fn take_lowest_edge(edges: &mut u16) -> Option<u8> {
+    if *edges == 0 {
+        return None;
     }
+    let key = edges.trailing_zeros() as u8;
+    *edges &= !(1 << key);
+    Some(key)
 }
@@ -0,0 +1,520 @@
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Host keys of the QWERTY preset, in Chip8 keyboard layout order
+const QWERTY: [[&str; 4]; 4] = [
+    ["1", "2", "3", "4"],
+    ["Q", "W", "E", "R"],
+    ["A", "S", "D", "F"],
+    ["Z", "X", "C", "V"],
+];
+
+/// Host keys of the AZERTY preset, in Chip8 keyboard layout order
+const AZERTY: [[&str; 4]; 4] = [
+    ["A", "Z", "E", "R"],
+    ["Q", "S", "D", "F"],
+    ["U", "I", "O", "P"],
+    ["J", "K", "L", "M"],
+];
+
+/// Chip8 keyboard layout: the value of each key, row by row
+const CHIP8_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Names of the host keys other than letters, digits, F1 to F15 and NumPad0 to NumPad9, as
+/// normalized by `normalize_key_name`
+const NAMED_KEYS: [&str; 45] = [
+    "DOWN",
+    "LEFT",
+    "RIGHT",
+    "UP",
+    "APOSTROPHE",
+    "BACKQUOTE",
+    "BACKSLASH",
+    "COMMA",
+    "EQUAL",
+    "LEFTBRACKET",
+    "MINUS",
+    "PERIOD",
+    "RIGHTBRACKET",
+    "SEMICOLON",
+    "SLASH",
+    "BACKSPACE",
+    "DELETE",
+    "END",
+    "ENTER",
+    "ESCAPE",
+    "HOME",
+    "INSERT",
+    "MENU",
+    "PAGEDOWN",
+    "PAGEUP",
+    "PAUSE",
+    "SPACE",
+    "TAB",
+    "NUMLOCK",
+    "CAPSLOCK",
+    "SCROLLLOCK",
+    "LEFTSHIFT",
+    "RIGHTSHIFT",
+    "LEFTCTRL",
+    "RIGHTCTRL",
+    "NUMPADDOT",
+    "NUMPADSLASH",
+    "NUMPADASTERISK",
+    "NUMPADMINUS",
+    "NUMPADPLUS",
+    "NUMPADENTER",
+    "LEFTALT",
+    "RIGHTALT",
+    "LEFTSUPER",
+    "RIGHTSUPER",
+];
+
+/// Names of the key mapping presets, as accepted by `Keymap::from_str`
+pub const PRESET_NAMES: [&str; 2] = ["qwerty", "azerty"];
+
+/// Mapping from host keyboard keys to Chip8 keys.
+///
+/// Host keys are identified by name, case insensitively: letters (`Q`), digits (`1`), and the
+/// other key names of the window library (`Space`, `Left`, `NumPad5`, ...).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    /// Chip8 key value of each bound host key, indexed by normalized host key name
+    bindings: HashMap<String, u8>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::qwerty()
+    }
+}
+
+impl Keymap {
+    /// Creates and returns a key mapping with the QWERTY preset: 1234 / QWER / ASDF / ZXCV
+    ///
+    /// # Returns
+    ///
+    /// The QWERTY `Keymap`
+    pub fn qwerty() -> Keymap {
+        Keymap::from_layout(&QWERTY)
+    }
+
+    /// Creates and returns a key mapping with the AZERTY preset: AZER / QSDF / UIOP / JKLM
+    ///
+    /// # Returns
+    ///
+    /// The AZERTY `Keymap`
+    pub fn azerty() -> Keymap {
+        Keymap::from_layout(&AZERTY)
+    }
+
+    /// Creates and returns a key mapping binding host keys to the Chip8 keys at the same place
+    ///
+    /// # Parameters
+    ///
+    /// - `layout`: The host keys, in Chip8 keyboard layout order
+    ///
+    /// # Returns
+    ///
+    /// A new `Keymap`
+    fn from_layout(layout: &[[&str; 4]; 4]) -> Keymap {
+        let mut keymap = Keymap {
+            bindings: HashMap::new(),
+        };
+        for (row, host_keys) in layout.iter().enumerate() {
+            for (column, host_key) in host_keys.iter().enumerate() {
+                keymap.bind(host_key, CHIP8_LAYOUT[row][column]);
+            }
+        }
+        keymap
+    }
+
+    /// Binds a host key to a Chip8 key, replacing its previous binding
+    ///
+    /// # Parameters
+    ///
+    /// - `host_key`: The name of the host key
+    /// - `key_code`: The value of the Chip8 key (0x0 to 0xF)
+    pub fn bind(&mut self, host_key: &str, key_code: u8) {
+        self.bindings
+            .insert(normalize_key_name(host_key), key_code & 0xF);
+    }
+
+    /// Gets Chip8 hexa key code associated to a host key
+    ///
+    /// # Parameters
+    ///
+    /// - `host_key`: The name of the host key
+    ///
+    /// # Returns
+    ///
+    /// The code of the associated Chip8 keyboard key, if any
+    pub fn get_key_code(&self, host_key: &str) -> Option<u8> {
+        self.bindings.get(&normalize_key_name(host_key)).copied()
+    }
+}
+
+/// Error returned when parsing an unknown key mapping preset name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPreset(pub String);
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown key mapping preset '{}' (expected one of: {})",
+            self.0,
+            PRESET_NAMES.join(", ")
+        )
+    }
+}
+
+impl error::Error for UnknownPreset {}
+
+impl FromStr for Keymap {
+    type Err = UnknownPreset;
+
+    fn from_str(name: &str) -> Result<Keymap, UnknownPreset> {
+        match name.to_ascii_lowercase().as_str() {
+            "qwerty" => Ok(Keymap::qwerty()),
+            "azerty" => Ok(Keymap::azerty()),
+            _ => Err(UnknownPreset(name.to_string())),
+        }
+    }
+}
+
+/// Error returned when a key mapping file is invalid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapError {
+    /// The line of the error, starting at 1
+    pub line: usize,
+    /// The description of the error
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for KeymapError {}
+
+/// Key mappings read from a key mapping file: a global mapping plus per-ROM overrides.
+///
+/// The file is made of `<name> = <value>` lines, `#` starting a comment. `layout = <preset>`
+/// replaces every binding with a preset, and `<host key> = <Chip8 key>` binds a host key to a
+/// Chip8 key, given as an hexadecimal digit. A host key is bound at most once per section. A
+/// `[<ROM file name>]` line starts the overrides of a ROM, which apply on top of the global
+/// mapping:
+///
+/// ```text
+/// layout = qwerty
+/// Space = 5
+///
+/// [INVADERS]
+/// Left = 4
+/// Right = 6
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct KeymapFile {
+    /// Lines of the global section
+    global: Vec<Binding>,
+    /// Lines of the ROM sections, indexed by ROM file name
+    roms: HashMap<String, Vec<Binding>>,
+}
+
+/// A line of a key mapping file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Binding {
+    /// Replace every binding with a preset
+    Layout(Keymap),
+    /// Bind a host key to a Chip8 key
+    Key(String, u8),
+}
+
+impl KeymapFile {
+    /// Parses the content of a key mapping file
+    ///
+    /// # Parameters
+    ///
+    /// - `source`: The content of the file
+    ///
+    /// # Returns
+    ///
+    /// The parsed `KeymapFile`, or a `KeymapError` if the content is invalid
+    pub fn parse(source: &str) -> Result<KeymapFile, KeymapError> {
+        let mut file = KeymapFile::default();
+        let mut section: Option<String> = None;
+        // Normalized host keys bound in each section, to reject duplicate bindings
+        let mut bound: HashSet<(Option<String>, String)> = HashSet::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| KeymapError {
+                line: index + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') || line.len() < 3 {
+                    return Err(error(format!("invalid section header '{}'", line)));
+                }
+                let rom = line[1..line.len() - 1].trim().to_string();
+                file.roms.entry(rom.clone()).or_default();
+                section = Some(rom);
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => {
+                    return Err(error(format!(
+                        "expected '<name> = <value>', got '{}'",
+                        line
+                    )))
+                }
+            };
+            if name.is_empty() {
+                return Err(error(String::from("missing key name")));
+            }
+
+            let binding = if name.eq_ignore_ascii_case("layout") {
+                Binding::Layout(
+                    value
+                        .parse()
+                        .map_err(|e: UnknownPreset| error(e.to_string()))?,
+                )
+            } else {
+                let host_key = normalize_key_name(name);
+                if !is_known_key_name(&host_key) {
+                    return Err(error(format!("unknown host key '{}'", name)));
+                }
+                if !bound.insert((section.clone(), host_key)) {
+                    return Err(error(format!(
+                        "host key '{}' is bound twice in the same section",
+                        name
+                    )));
+                }
+                match u8::from_str_radix(value.trim_start_matches("0x"), 16) {
+                    Ok(key_code) if key_code <= 0xF => Binding::Key(name.to_string(), key_code),
+                    _ => {
+                        return Err(error(format!(
+                            "invalid Chip8 key '{}', expected 0 to F",
+                            value
+                        )))
+                    }
+                }
+            };
+
+            match &section {
+                Some(rom) => file.roms.entry(rom.clone()).or_default().push(binding),
+                None => file.global.push(binding),
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Builds the key mapping of a ROM: the global mapping with the ROM overrides applied
+    ///
+    /// # Parameters
+    ///
+    /// - `rom_name`: The file name of the ROM, without its directory
+    ///
+    /// # Returns
+    ///
+    /// The `Keymap` of the ROM, based on the QWERTY preset
+    pub fn keymap_for(&self, rom_name: &str) -> Keymap {
+        let mut keymap = Keymap::default();
+        let rom_bindings = self.roms.get(rom_name).map(Vec::as_slice).unwrap_or(&[]);
+        for binding in self.global.iter().chain(rom_bindings) {
+            match binding {
+                Binding::Layout(layout) => keymap = layout.clone(),
+                Binding::Key(host_key, key_code) => keymap.bind(host_key, *key_code),
+            }
+        }
+        keymap
+    }
+}
+
+/// Checks whether a normalized name is the name of a host key of the window library
+///
+/// # Parameters
+///
+/// - `name`: The normalized name of the host key
+///
+/// # Returns
+///
+/// `true` if the host key exists
+fn is_known_key_name(name: &str) -> bool {
+    let numbered = |prefix: &str, mut numbers: Range<u8>| {
+        numbers.any(|number| name == format!("{}{}", prefix, number))
+    };
+    (name.len() == 1 && name.as_bytes()[0].is_ascii_uppercase())
+        || numbered("KEY", 0..10)
+        || numbered("NUMPAD", 0..10)
+        || numbered("F", 1..16)
+        || NAMED_KEYS.contains(&name)
+}
+
+/// Normalizes a host key name: uppercase, and digits named like the window library does
+///
+/// # Parameters
+///
+/// - `name`: The name of the host key
+///
+/// # Returns
+///
+/// The normalized name
+fn normalize_key_name(name: &str) -> String {
+    let name = name.trim().to_ascii_uppercase();
+    if name.len() == 1 && name.as_bytes()[0].is_ascii_digit() {
+        format!("KEY{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gets the Chip8 keys bound to a 4x4 grid of one character host keys, row by row
+    fn grid_key_codes(keymap: &Keymap, grid: &str) -> Vec<Option<u8>> {
+        grid.chars()
+            .map(|key| keymap.get_key_code(&key.to_string()))
+            .collect()
+    }
+
+    /// Gets the Chip8 keys in layout order, row by row
+    fn chip8_layout() -> Vec<Option<u8>> {
+        CHIP8_LAYOUT
+            .iter()
+            .flatten()
+            .map(|&code| Some(code))
+            .collect()
+    }
+
+    /// Parses a key mapping file that must be invalid
+    fn parse_error(source: &str) -> (usize, String) {
+        let error = KeymapFile::parse(source).unwrap_err();
+        (error.line, error.message)
+    }
+
+    #[test]
+    fn qwerty_maps_the_grid_to_the_chip8_layout() {
+        let keymap = Keymap::qwerty();
+        assert_eq!(grid_key_codes(&keymap, "1234QWERASDFZXCV"), chip8_layout());
+        assert_eq!(keymap.get_key_code("q"), Some(0x4));
+        assert_eq!(keymap.get_key_code("Key1"), Some(0x1));
+        assert_eq!(keymap.get_key_code("Space"), None);
+    }
+
+    #[test]
+    fn azerty_maps_the_azer_grid_to_the_chip8_layout() {
+        let keymap: Keymap = "AZERTY".parse().unwrap();
+        assert_eq!(grid_key_codes(&keymap, "AZERQSDFUIOPJKLM"), chip8_layout());
+        assert_eq!(keymap.get_key_code("1"), None);
+    }
+
+    #[test]
+    fn unknown_preset_is_rejected() {
+        assert_eq!(
+            "dvorak".parse::<Keymap>(),
+            Err(UnknownPreset(String::from("dvorak")))
+        );
+    }
+
+    #[test]
+    fn rom_section_overrides_only_the_keys_it_lists() {
+        let file = KeymapFile::parse(
+            "layout = azerty # comment\nSpace = 5\n\n[INVADERS]\nLeft = 4\nA = 0x6\n",
+        )
+        .unwrap();
+
+        let keymap = file.keymap_for("INVADERS");
+        assert_eq!(keymap.get_key_code("Left"), Some(0x4));
+        assert_eq!(keymap.get_key_code("A"), Some(0x6));
+        assert_eq!(keymap.get_key_code("Z"), Some(0x2));
+        assert_eq!(keymap.get_key_code("Space"), Some(0x5));
+
+        let keymap = file.keymap_for("PONG");
+        assert_eq!(keymap.get_key_code("Left"), None);
+        assert_eq!(keymap.get_key_code("A"), Some(0x1));
+    }
+
+    #[test]
+    fn empty_file_gives_the_qwerty_preset() {
+        let file = KeymapFile::parse("# nothing\n\n").unwrap();
+        assert_eq!(file.keymap_for("PONG"), Keymap::qwerty());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_their_line() {
+        assert!(KeymapFile::parse("NumPad5 = 5\nF15 = 1\nKey0 = 0\nLeftShift = 2\n").is_ok());
+        assert_eq!(
+            parse_error("F16 = 1\n"),
+            (1, String::from("unknown host key 'F16'"))
+        );
+        assert_eq!(
+            parse_error("Q = 5\nFoo = 1\n"),
+            (2, String::from("unknown host key 'Foo'"))
+        );
+        assert_eq!(
+            parse_error("\nQ = G\n"),
+            (2, String::from("invalid Chip8 key 'G', expected 0 to F"))
+        );
+        assert_eq!(
+            parse_error("layout = dvorak\n"),
+            (1, UnknownPreset(String::from("dvorak")).to_string())
+        );
+    }
+
+    #[test]
+    fn duplicate_bindings_are_reported_with_their_line() {
+        assert_eq!(
+            parse_error("Q = 5\nW = 6\nq = 7\n"),
+            (
+                3,
+                String::from("host key 'q' is bound twice in the same section")
+            )
+        );
+        assert_eq!(
+            parse_error("Q = 5\n[PONG]\nQ = 6\nQ = 7\n"),
+            (
+                4,
+                String::from("host key 'Q' is bound twice in the same section")
+            )
+        );
+    }
+
+    #[test]
+    fn bad_section_headers_are_reported_with_their_line() {
+        assert_eq!(
+            parse_error("Q = 5\n[INVADERS\n"),
+            (2, String::from("invalid section header '[INVADERS'"))
+        );
+        assert_eq!(
+            parse_error("[]\n"),
+            (1, String::from("invalid section header '[]'"))
+        );
+        assert_eq!(
+            parse_error("[PONG]\nLeft 4\n"),
+            (2, String::from("expected '<name> = <value>', got 'Left 4'"))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! A CHIP-8 emulator library.
+//!
+//! The [`Chip8`] struct is the entry point: it builds a complete virtual machine, loads ROMs,
+//! executes instructions and gives access to the display buffer, the keyboard and the timers.
+//! [`Chip8::run`] is a ready-made frontend that runs the machine in a window.
+//!
+//! More information about CHIP-8 here: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+
+/// CHIP-8 assembler
+pub mod asm;
+/// Sound output
+pub mod audio;
+/// CHIP-8 Communication Bus
+pub mod bus;
+/// CHIP-8 Virtual Machine
+pub mod chip8;
+/// CHIP-8 CPU
+pub mod cpu;
+/// Interactive debugger
+pub mod debugger;
+/// ROM disassembly
+pub mod disasm;
+/// CHIP-8 Display
+pub mod display;
+/// GDB remote serial protocol server
+pub mod gdb;
+/// Windowless frontend
+pub mod headless;
+/// CHIP-8 instruction decoding
+pub mod instruction;
+/// CHIP-8 Keyboard
+pub mod keyboard;
+/// Mapping from the computer keyboard to the CHIP-8 keyboard
+pub mod keymap;
+/// CHIP-8 Memory
+pub mod memory;
+/// Input recording and deterministic replay
+pub mod movie;
+/// PNG screenshots of the display
+pub mod png;
+/// Behaviour of the ambiguous CHIP-8 instructions
+pub mod quirks;
+/// Rewind history of the last frames
+pub mod rewind;
+/// Seedable random number generator
+pub mod rng;
+/// 60Hz frame pacing
+pub mod scheduler;
+/// Save states of the whole machine
+pub mod state;
+/// Execution trace logging
+pub mod trace;
+/// Memory watchpoints
+pub mod watch;
+
+pub use crate::asm::AsmError;
+pub use crate::audio::{AudioSink, WavSink};
+pub use crate::bus::RomTooLarge;
+pub use crate::chip8::{Chip8, RunConfig, RunError};
+pub use crate::cpu::CpuError;
+pub use crate::debugger::Debugger;
+pub use crate::gdb::GdbServer;
+pub use crate::headless::{HeadlessConfig, HeadlessError, KeyScript};
+pub use crate::instruction::Instruction;
+pub use crate::keymap::{Keymap, KeymapFile};
+pub use crate::memory::AddressError;
+pub use crate::movie::{Divergence, InputSource, Movie, MovieError};
+pub use crate::png::Palette;
+pub use crate::quirks::Quirks;
+pub use crate::rng::Rng;
+pub use crate::state::StateError;
+pub use crate::trace::{TraceFilter, Tracer};
+pub use crate::watch::{WatchHit, Watchpoint};
@@ -1,30 +1,339 @@
-mod bus;
-mod chip8;
-mod cpu;
-mod display;
-mod keyboard;
-mod memory;
-
-use chip8::Chip8;
-use std::fs::File;
-use std::io;
-use std::io::prelude::*;
-
-fn main() -> io::Result<()> {
-    // Create Chip8
-    let mut chip8: Chip8 = Chip8::new();
+mod cli;
+
+use chip8::movie::{InputSource, LiveInput, MoviePlayer, MovieRecorder};
+#[cfg(feature = "window")]
+use chip8::GdbServer;
+use chip8::{asm, disasm};
+use chip8::{
+    Chip8, Debugger, HeadlessConfig, Keymap, KeymapFile, Movie, Quirks, Rng, RunConfig, Tracer,
+    WavSink,
+};
+use cli::{Command, Options};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn main() {
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        Command::Run(options) => run(&options),
+        Command::Disasm { rom, linear } => disasm(&rom, linear),
+        Command::Asm { source, output } => asm(&source, &output),
+        Command::Debug {
+            rom,
+            quirks,
+            seed,
+            cpu_hz,
+        } => debug(&rom, quirks, seed, cpu_hz),
+    };
+
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+/// Runs a ROM in the emulator window
+///
+/// # Parameters
+///
+/// - `options`: The command line options
+///
+/// # Returns
+///
+/// The error that stopped the emulator, if any
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    // Read ROM file
+    let buffer = fs::read(&options.rom)
+        .map_err(|error| format!("cannot read ROM '{}': {}", options.rom.display(), error))?;
+
+    // Read the movie to replay, whose settings override the command line ones
+    let mut config = options.config.clone();
+    let mut quirks = options.quirks;
+    let mut seed = options.seed;
+    let replay = match &options.replay {
+        Some(path) => {
+            let movie = read_movie(path)?;
+            if !movie.matches_rom(&buffer) {
+                return Err(
+                    format!("movie '{}' was recorded with another ROM", path.display()).into(),
+                );
+            }
+            quirks = movie.quirks;
+            seed = Some(movie.seed);
+            config.cpu_hz = movie.cpu_hz;
+            Some(movie)
+        }
+        None => None,
+    };
 
-    // Open and read ROM file
-    let mut file = File::open("roms/INVADERS")?;
-    let mut buffer = Vec::<u8>::new();
+    // A recorded movie needs a known seed to be replayed
+    if options.record.is_some() && seed.is_none() {
+        seed = Some(Rng::from_entropy().next_u64());
+    }
 
-    file.read_to_end(&mut buffer)?;
+    // Create Chip8
+    let mut chip8: Chip8 = Chip8::with_quirks(quirks);
+    if let Some(seed) = seed {
+        chip8.set_rng(Rng::from_seed(seed));
+    }
 
     // Load ROM in Chip8 memory
-    chip8.load_rom(&buffer);
+    chip8.load_rom(&buffer)?;
+    chip8.set_watchpoints(options.watchpoints.clone());
+
+    // Record the sound
+    if let Some(wav) = &options.wav {
+        let sink = WavSink::create(wav)
+            .map_err(|error| format!("cannot create WAV file '{}': {}", wav.display(), error))?;
+        chip8.set_audio_sink(Box::new(sink));
+    }
+
+    // Trace the executed instructions
+    if let Some(path) = &options.trace {
+        let tracer = if path.as_os_str() == "-" {
+            Tracer::new(Box::new(BufWriter::new(io::stdout())), options.trace_filter)
+        } else {
+            Tracer::create(path, options.trace_filter).map_err(|error| {
+                format!("cannot create trace file '{}': {}", path.display(), error)
+            })?
+        };
+        chip8.set_tracer(Some(tracer));
+    }
+
+    // Listen for a debugger client
+    if let Some(port) = options.gdb_port {
+        #[cfg(feature = "window")]
+        {
+            let gdb = GdbServer::bind(("127.0.0.1", port))
+                .map_err(|error| format!("cannot listen on port {}: {}", port, error))?;
+            println!("listening for a GDB client on 127.0.0.1:{}", port);
+            chip8.set_gdb_server(Some(gdb));
+        }
+        #[cfg(not(feature = "window"))]
+        return Err(format!(
+            "cannot listen on port {}: this build has no window frontend",
+            port
+        )
+        .into());
+    }
 
     // Start the emulator
-    chip8.run();
+    config.keymap = load_keymap(options.keymap.as_deref(), &options.rom)?;
+    config.state_path = Some(options.rom.clone());
+    if let Some(movie) = replay {
+        let frame_count = movie.frame_count();
+        let mut player = MoviePlayer::new(movie);
+        start(&mut chip8, options, &config, &mut player)?;
+        println!(
+            "replayed {} of {} frames, the display matched the recording",
+            player.get_frame(),
+            frame_count
+        );
+    } else if let (Some(path), Some(seed)) = (&options.record, seed) {
+        let movie = Movie::new(seed, config.cpu_hz, quirks, &buffer);
+        let mut recorder = MovieRecorder::new(movie);
+        let result = start(&mut chip8, options, &config, &mut recorder);
+        fs::write(path, recorder.get_movie().to_bytes())
+            .map_err(|error| format!("cannot write movie file '{}': {}", path.display(), error))?;
+        result?;
+    } else {
+        start(&mut chip8, options, &config, &mut LiveInput)?;
+    }
+    chip8
+        .finish_audio()
+        .map_err(|error| format!("cannot write WAV file: {}", error))?;
+    chip8
+        .finish_trace()
+        .map_err(|error| format!("cannot write trace: {}", error))?;
+
+    Ok(())
+}
+
+/// Starts the frontend selected on the command line: headless, or the window
+///
+/// # Parameters
+///
+/// - `chip8`: The machine, with its ROM loaded
+/// - `options`: The command line options
+/// - `config`: The window frontend settings
+/// - `input`: The source of the keypad state of each frame
+///
+/// # Returns
+///
+/// The error that stopped the emulator, if any
+fn start(
+    chip8: &mut Chip8,
+    options: &Options,
+    config: &RunConfig,
+    input: &mut dyn InputSource,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(headless) = &options.headless {
+        // A replayed movie overrides the CPU frequency of the command line
+        let headless = HeadlessConfig {
+            cpu_hz: config.cpu_hz,
+            ..headless.clone()
+        };
+        chip8.run_headless(&headless, input)?;
+        return Ok(());
+    }
 
+    #[cfg(feature = "window")]
+    {
+        chip8.run_with_input(config, input)?;
+        Ok(())
+    }
+    #[cfg(not(feature = "window"))]
+    {
+        Err("this build has no window frontend, use '--headless'".into())
+    }
+}
+
+/// Prints the disassembly of a ROM
+///
+/// # Parameters
+///
+/// - `rom`: The path of the ROM
+/// - `linear`: Whether every word is listed as an instruction, instead of following the
+///   execution flow
+///
+/// # Returns
+///
+/// An error if the ROM cannot be read
+fn disasm(rom: &Path, linear: bool) -> Result<(), Box<dyn Error>> {
+    let buffer =
+        fs::read(rom).map_err(|error| format!("cannot read ROM '{}': {}", rom.display(), error))?;
+    if linear {
+        print!("{}", disasm::listing(&buffer));
+    } else {
+        print!("{}", disasm::source(&buffer));
+    }
+    Ok(())
+}
+
+/// Runs a ROM in the console debugger, reading commands from the standard input
+///
+/// # Parameters
+///
+/// - `rom`: The path of the ROM
+/// - `quirks`: The behaviour of the ambiguous instructions
+/// - `seed`: The seed of the random number generator, if any
+/// - `cpu_hz`: The number of instructions executed per second, which paces the timers
+///
+/// # Returns
+///
+/// An error if the ROM cannot be loaded or the console cannot be used
+fn debug(rom: &Path, quirks: Quirks, seed: Option<u64>, cpu_hz: u32) -> Result<(), Box<dyn Error>> {
+    let buffer =
+        fs::read(rom).map_err(|error| format!("cannot read ROM '{}': {}", rom.display(), error))?;
+    let mut chip8 = Chip8::with_quirks(quirks);
+    if let Some(seed) = seed {
+        chip8.set_rng(Rng::from_seed(seed));
+    }
+    chip8.load_rom(&buffer)?;
+
+    let stdin = io::stdin();
+    Debugger::new(cpu_hz).run(&mut chip8, stdin.lock(), io::stdout())?;
+    Ok(())
+}
+
+/// Assembles a source file into a ROM
+///
+/// # Parameters
+///
+/// - `source`: The path of the source
+/// - `output`: The path of the ROM to write
+///
+/// # Returns
+///
+/// An error if the source cannot be read or assembled, or the ROM cannot be written
+fn asm(source: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let text = fs::read_to_string(source)
+        .map_err(|error| format!("cannot read source '{}': {}", source.display(), error))?;
+    let rom = asm::assemble(&text, Some(source))?;
+    fs::write(output, &rom)
+        .map_err(|error| format!("cannot write ROM '{}': {}", output.display(), error))?;
+    println!("assembled {} bytes to '{}'", rom.len(), output.display());
     Ok(())
 }
+
+/// Reads a movie file
+///
+/// # Parameters
+///
+/// - `path`: The path of the movie file
+///
+/// # Returns
+///
+/// The movie, or an error if the file cannot be read or is invalid
+fn read_movie(path: &Path) -> Result<Movie, Box<dyn Error>> {
+    let data = fs::read(path)
+        .map_err(|error| format!("cannot read movie file '{}': {}", path.display(), error))?;
+    let movie = Movie::from_bytes(&data)
+        .map_err(|error| format!("invalid movie file '{}': {}", path.display(), error))?;
+    Ok(movie)
+}
+
+/// Loads the key mapping of a ROM
+///
+/// # Parameters
+///
+/// - `keymap`: The key mapping preset name or key mapping file path given on the command line,
+///   if any. Without it, the default key mapping file is used if it exists.
+/// - `rom`: The path of the ROM, whose overrides are applied
+///
+/// # Returns
+///
+/// The key mapping, or an error if the key mapping file is invalid
+fn load_keymap(keymap: Option<&str>, rom: &Path) -> Result<Keymap, Box<dyn Error>> {
+    let path = match keymap {
+        Some(keymap) => match keymap.parse() {
+            Ok(preset) => return Ok(preset),
+            Err(_) => PathBuf::from(keymap),
+        },
+        None => match default_keymap_path() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(Keymap::default()),
+        },
+    };
+
+    let source = fs::read_to_string(&path).map_err(|error| {
+        format!(
+            "cannot read key mapping file '{}': {}",
+            path.display(),
+            error
+        )
+    })?;
+    let file = KeymapFile::parse(&source)
+        .map_err(|error| format!("invalid key mapping file '{}': {}", path.display(), error))?;
+    let rom_name = rom.file_name().unwrap_or_default().to_string_lossy();
+    Ok(file.keymap_for(&rom_name))
+}
+
+/// Gets the path of the default key mapping file: `$XDG_CONFIG_HOME/chip8/keymap.cfg`, or
+/// `$HOME/.config/chip8/keymap.cfg`
+///
+/// # Returns
+///
+/// The path of the default key mapping file, if the configuration directory is known
+fn default_keymap_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("chip8").join("keymap.cfg"))
+}
@@ -1,11 +1,42 @@
+use crate::state::{StateError, StateReader, StateWriter};
+use std::error;
 use std::fmt;
 
+/// Error returned when accessing an address outside of the memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressError {
+    /// The address that could not be accessed
+    pub address: u16,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "address {:#06X} is outside of the memory", self.address)
+    }
+}
+
+impl error::Error for AddressError {}
+
 /// Memory of the Chip8 Virtual Machine struct.
 pub struct Memory {
-    mem: [u8; 4096],
+    mem: Vec<u8>,
 }
 
-/// Hexa sprites to load in memory at address 0x0
+/// Size of the CHIP-8 memory, in bytes
+pub const MEMORY_SIZE: usize = 0x1000;
+/// Size of the XO-CHIP extended memory, in bytes
+pub const EXTENDED_MEMORY_SIZE: usize = 0x10000;
+
+/// Address of the hexa sprites in memory
+pub const SPRITES_ADDRESS: u16 = 0x0;
+/// Size of a hexa sprite, in bytes
+pub const SPRITE_SIZE: u16 = 5;
+/// Address of the big hexa sprites (SUPER-CHIP) in memory
+pub const BIG_SPRITES_ADDRESS: u16 = 0x50;
+/// Size of a big hexa sprite, in bytes
+pub const BIG_SPRITE_SIZE: u16 = 10;
+
+/// Hexa sprites to load in memory at address `SPRITES_ADDRESS`
 const SPRITES: [[u8; 5]; 16] = [
     [0xF0, 0x90, 0x90, 0x90, 0xF0],
     [0x20, 0x60, 0x20, 0x20, 0x70],
@@ -25,6 +56,32 @@ const SPRITES: [[u8; 5]; 16] = [
     [0xF0, 0x80, 0xF0, 0x80, 0x80],
 ];
 
+/// Big 8x10 hexa sprites (SUPER-CHIP) to load in memory at address `BIG_SPRITES_ADDRESS`
+const BIG_SPRITES: [[u8; 10]; 16] = [
+    [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C],
+    [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C],
+    [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF],
+    [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C],
+    [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C],
+    [0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C],
+    [0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60],
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C],
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C],
+    [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+    [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+    [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+];
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
 impl Memory {
     /// Creates and returns a new `Memory` struct.
     ///
@@ -32,26 +89,94 @@ impl Memory {
     ///
     /// A new `Memory` struct.
     pub fn new() -> Memory {
-        let mut memory = Memory { mem: [0; 4096] };
-        let mut i = 0;
+        Memory::with_size(MEMORY_SIZE)
+    }
+
+    /// Creates and returns a new `Memory` struct of the given size.
+    ///
+    /// # Parameters
+    ///
+    /// - `size`: The memory size, in bytes, from 512 bytes up to `EXTENDED_MEMORY_SIZE`
+    ///
+    /// # Returns
+    ///
+    /// A new `Memory` struct.
+    pub fn with_size(size: usize) -> Memory {
+        let size = size.clamp(0x200, EXTENDED_MEMORY_SIZE);
+        let mut memory = Memory { mem: vec![0; size] };
+        let mut i = SPRITES_ADDRESS as usize;
         for sprite in &SPRITES {
             for byte in sprite {
                 memory.mem[i] = *byte;
                 i += 1;
             }
         }
+        let mut i = BIG_SPRITES_ADDRESS as usize;
+        for sprite in &BIG_SPRITES {
+            for byte in sprite {
+                memory.mem[i] = *byte;
+                i += 1;
+            }
+        }
 
         memory
     }
 
+    /// Gets the memory size
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes in memory
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    /// Writes the memory size and content in a save state
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The save state writer
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.mem.len() as u32);
+        writer.write_bytes(&self.mem);
+    }
+
+    /// Restores the memory size and content from a save state written by `save_state`
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The save state reader
+    ///
+    /// # Returns
+    ///
+    /// A `StateError` if the save state is invalid, in which case the memory is unchanged
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let size = reader.read_u32()? as usize;
+        if !(0x200..=EXTENDED_MEMORY_SIZE).contains(&size) {
+            return Err(StateError::InvalidValue("memory size"));
+        }
+        self.mem = reader.read_bytes(size)?.to_vec();
+        Ok(())
+    }
+
     /// Writes the given byte in memory at the giver address
     ///
     /// # Parameters
     ///
     /// - `address`: The memory address where to write the byte
     /// - `value` : The byte to write
-    pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.mem[address as usize] = value;
+    ///
+    /// # Returns
+    ///
+    /// An `AddressError` if `address` is outside of the memory
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), AddressError> {
+        match self.mem.get_mut(address as usize) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(AddressError { address }),
+        }
     }
 
     /// Reads the bytes at the given address
@@ -62,17 +187,21 @@ impl Memory {
     ///
     /// # Returns
     ///
-    /// The memory value at address `address`
-    pub fn read_byte(&self, address: u16) -> u8 {
-        self.mem[address as usize]
+    /// The memory value at address `address`, or an `AddressError` if `address` is outside of
+    /// the memory
+    pub fn read_byte(&self, address: u16) -> Result<u8, AddressError> {
+        match self.mem.get(address as usize) {
+            Some(byte) => Ok(*byte),
+            None => Err(AddressError { address }),
+        }
     }
 }
 
 impl fmt::Debug for Memory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for i in 0..self.mem.len() {
-            write!(f, "{:#x}", self.mem[i]);
+        for byte in self.mem.iter() {
+            write!(f, "{:#x}", byte)?;
         }
-        std::result::Result::Ok(())
+        Ok(())
     }
 }
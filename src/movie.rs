@@ -0,0 +1,583 @@
+use crate::keyboard::KEY_COUNT;
+use crate::quirks::Quirks;
+use crate::state::{StateReader, StateWriter};
+use std::error;
+use std::fmt;
+
+/// Magic number starting every movie file
+pub const MOVIE_MAGIC: [u8; 4] = *b"C8MV";
+/// Version of the movie file format, incremented on every format change
+pub const MOVIE_VERSION: u16 = 1;
+
+/// Error returned when a movie file cannot be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data does not start with `MOVIE_MAGIC`: it is not a movie file
+    InvalidMagic,
+    /// The movie has been written by an incompatible version of the emulator
+    UnsupportedVersion(u16),
+    /// The movie file is truncated or contains an invalid value
+    Corrupted,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::InvalidMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported movie version {} (expected {})",
+                version, MOVIE_VERSION
+            ),
+            MovieError::Corrupted => write!(f, "movie file is corrupted"),
+        }
+    }
+}
+
+impl error::Error for MovieError {}
+
+/// Error returned when a replayed session diverges from its recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// The first frame whose display differs, starting at 0
+    pub frame: u32,
+    /// The display hash recorded for the frame
+    pub expected: u64,
+    /// The display hash of the replayed frame
+    pub actual: u64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replay diverged at frame {}: display hash is {:016x}, recorded {:016x}",
+            self.frame, self.actual, self.expected
+        )
+    }
+}
+
+impl error::Error for Divergence {}
+
+/// A recorded session: the settings needed to reproduce it, every keypad state change with the
+/// frame at which it happened, and the hash of the display after each frame.
+///
+/// The file format starts with the `MOVIE_MAGIC` magic number and the `MOVIE_VERSION` format
+/// version, all values being little endian.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// Seed of the random number generator
+    pub seed: u64,
+    /// Number of instructions executed per second
+    pub cpu_hz: u32,
+    /// Behaviour of the ambiguous instructions
+    pub quirks: Quirks,
+    /// Hash of the ROM, to detect replays against another ROM
+    pub rom_hash: u64,
+    /// Keypad state changes: frame number, and key mask (one bit per key)
+    events: Vec<(u32, u16)>,
+    /// Hash of the display after each frame
+    display_hashes: Vec<u64>,
+}
+
+impl Movie {
+    /// Creates and returns a new `Movie` struct, without any frame.
+    ///
+    /// # Parameters
+    ///
+    /// - `seed`: The seed of the random number generator
+    /// - `cpu_hz`: The number of instructions executed per second
+    /// - `quirks`: The behaviour of the ambiguous instructions
+    /// - `rom`: The bytes of the ROM
+    ///
+    /// # Returns
+    ///
+    /// A new `Movie` struct.
+    pub fn new(seed: u64, cpu_hz: u32, quirks: Quirks, rom: &[u8]) -> Movie {
+        Movie {
+            seed,
+            cpu_hz,
+            quirks,
+            rom_hash: hash_bytes(rom),
+            events: Vec::new(),
+            display_hashes: Vec::new(),
+        }
+    }
+
+    /// Gets the number of recorded frames
+    ///
+    /// # Returns
+    ///
+    /// The number of frames
+    pub fn frame_count(&self) -> u32 {
+        self.display_hashes.len() as u32
+    }
+
+    /// Was the movie recorded with the given ROM?
+    ///
+    /// # Parameters
+    ///
+    /// - `rom`: The bytes of the ROM
+    ///
+    /// # Returns
+    ///
+    /// `true` if the ROM matches the recorded one
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == hash_bytes(rom)
+    }
+
+    /// Encodes the movie
+    ///
+    /// # Returns
+    ///
+    /// The content of the movie file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&MOVIE_MAGIC);
+        writer.write_u16(MOVIE_VERSION);
+        writer.write_u64(self.seed);
+        writer.write_u32(self.cpu_hz);
+        self.quirks.save_state(&mut writer);
+        writer.write_u64(self.rom_hash);
+        writer.write_u32(self.events.len() as u32);
+        for (frame, keys) in &self.events {
+            writer.write_u32(*frame);
+            writer.write_u16(*keys);
+        }
+        writer.write_u32(self.display_hashes.len() as u32);
+        for hash in &self.display_hashes {
+            writer.write_u64(*hash);
+        }
+        writer.into_bytes()
+    }
+
+    /// Decodes a movie encoded by `to_bytes`
+    ///
+    /// # Parameters
+    ///
+    /// - `data`: The content of the movie file
+    ///
+    /// # Returns
+    ///
+    /// The movie, or a `MovieError` if the data is not a valid movie
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let mut reader = StateReader::new(data);
+        if reader.read_bytes(MOVIE_MAGIC.len()) != Ok(&MOVIE_MAGIC[..]) {
+            return Err(MovieError::InvalidMagic);
+        }
+        let version = reader.read_u16().map_err(|_| MovieError::Corrupted)?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        Movie::read(&mut reader).ok_or(MovieError::Corrupted)
+    }
+
+    /// Reads the movie following the file header
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The movie file reader
+    ///
+    /// # Returns
+    ///
+    /// The movie, or None if the data is truncated or invalid
+    fn read(reader: &mut StateReader) -> Option<Movie> {
+        let mut movie = Movie {
+            seed: reader.read_u64().ok()?,
+            cpu_hz: reader.read_u32().ok()?,
+            quirks: Quirks::load_state(reader).ok()?,
+            rom_hash: reader.read_u64().ok()?,
+            events: Vec::new(),
+            display_hashes: Vec::new(),
+        };
+        for _ in 0..reader.read_u32().ok()? {
+            let event = (reader.read_u32().ok()?, reader.read_u16().ok()?);
+            movie.events.push(event);
+        }
+        for _ in 0..reader.read_u32().ok()? {
+            movie.display_hashes.push(reader.read_u64().ok()?);
+        }
+        reader.finish().ok()?;
+        Some(movie)
+    }
+}
+
+/// Source of the keypad state of each frame
+pub trait InputSource {
+    /// Gets the keypad state of the next frame
+    ///
+    /// # Parameters
+    ///
+    /// - `host_keys`: The keys currently pressed on the host keyboard
+    ///
+    /// # Returns
+    ///
+    /// The state of each key during the frame, `true` when the key is down
+    fn next_frame(&mut self, host_keys: [bool; KEY_COUNT]) -> [bool; KEY_COUNT];
+
+    /// Checks the display once the frame has run
+    ///
+    /// # Parameters
+    ///
+    /// - `display_hash`: The hash of the display after the frame
+    ///
+    /// # Returns
+    ///
+    /// A `Divergence` if the display differs from the expected one
+    fn end_frame(&mut self, display_hash: u64) -> Result<(), Divergence>;
+
+    /// Has the source run out of frames?
+    ///
+    /// # Returns
+    ///
+    /// `true` if the emulation should stop
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    /// Can the machine state be changed outside of the frames (save state loading, rewind)?
+    ///
+    /// # Returns
+    ///
+    /// `true` if the frames of the source do not need to follow each other
+    fn allows_state_changes(&self) -> bool {
+        true
+    }
+}
+
+/// Input source reading the host keyboard
+#[derive(Debug, Default)]
+pub struct LiveInput;
+
+impl InputSource for LiveInput {
+    fn next_frame(&mut self, host_keys: [bool; KEY_COUNT]) -> [bool; KEY_COUNT] {
+        host_keys
+    }
+
+    fn end_frame(&mut self, _display_hash: u64) -> Result<(), Divergence> {
+        Ok(())
+    }
+}
+
+/// Input source reading the host keyboard and recording the session in a movie
+pub struct MovieRecorder {
+    /// The movie being recorded
+    movie: Movie,
+    /// Key mask of the previous frame
+    keys: u16,
+}
+
+impl MovieRecorder {
+    /// Creates and returns a new `MovieRecorder` struct.
+    ///
+    /// # Parameters
+    ///
+    /// - `movie`: The movie to record frames into
+    ///
+    /// # Returns
+    ///
+    /// A new `MovieRecorder` struct.
+    pub fn new(movie: Movie) -> MovieRecorder {
+        MovieRecorder { movie, keys: 0 }
+    }
+
+    /// Gets the recorded movie
+    ///
+    /// # Returns
+    ///
+    /// The movie, with every frame run so far
+    pub fn get_movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+impl InputSource for MovieRecorder {
+    fn next_frame(&mut self, host_keys: [bool; KEY_COUNT]) -> [bool; KEY_COUNT] {
+        let keys = keys_to_mask(host_keys);
+        if keys != self.keys {
+            let frame = self.movie.frame_count();
+            self.movie.events.push((frame, keys));
+            self.keys = keys;
+        }
+        host_keys
+    }
+
+    fn end_frame(&mut self, display_hash: u64) -> Result<(), Divergence> {
+        self.movie.display_hashes.push(display_hash);
+        Ok(())
+    }
+
+    fn allows_state_changes(&self) -> bool {
+        false
+    }
+}
+
+/// Input source replaying a movie, which checks that the display matches the recording
+pub struct MoviePlayer {
+    /// The movie being replayed
+    movie: Movie,
+    /// Number of frames replayed so far
+    frame: u32,
+    /// Index of the next keypad state change
+    event: usize,
+    /// Key mask of the current frame
+    keys: u16,
+}
+
+impl MoviePlayer {
+    /// Creates and returns a new `MoviePlayer` struct, starting at the first frame.
+    ///
+    /// # Parameters
+    ///
+    /// - `movie`: The movie to replay
+    ///
+    /// # Returns
+    ///
+    /// A new `MoviePlayer` struct.
+    pub fn new(movie: Movie) -> MoviePlayer {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            event: 0,
+            keys: 0,
+        }
+    }
+
+    /// Gets the number of frames replayed so far
+    ///
+    /// # Returns
+    ///
+    /// The number of frames
+    pub fn get_frame(&self) -> u32 {
+        self.frame
+    }
+}
+
+impl InputSource for MoviePlayer {
+    fn next_frame(&mut self, _host_keys: [bool; KEY_COUNT]) -> [bool; KEY_COUNT] {
+        while let Some((frame, keys)) = self.movie.events.get(self.event) {
+            if *frame > self.frame {
+                break;
+            }
+            self.keys = *keys;
+            self.event += 1;
+        }
+        mask_to_keys(self.keys)
+    }
+
+    fn end_frame(&mut self, display_hash: u64) -> Result<(), Divergence> {
+        let frame = self.frame;
+        self.frame += 1;
+        match self.movie.display_hashes.get(frame as usize) {
+            Some(expected) if *expected != display_hash => Err(Divergence {
+                frame,
+                expected: *expected,
+                actual: display_hash,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frame_count()
+    }
+
+    fn allows_state_changes(&self) -> bool {
+        false
+    }
+}
+
+/// Hashes bytes with the 64 bits FNV-1a function
+///
+/// # Parameters
+///
+/// - `bytes`: The bytes to hash
+///
+/// # Returns
+///
+/// The hash of the bytes
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSET_BASIS, bytes)
+}
+
+/// Hashes a display buffer with the 64 bits FNV-1a function
+///
+/// # Parameters
+///
+/// - `width`: The display width, in pixels
+/// - `height`: The display height, in pixels
+/// - `buffer`: The display buffer, one `0xRRGGBB` color per pixel
+///
+/// # Returns
+///
+/// The hash of the display
+pub fn hash_display(width: usize, height: usize, buffer: &[u32]) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, &(width as u32).to_le_bytes());
+    hash = fnv1a(hash, &(height as u32).to_le_bytes());
+    for pixel in buffer {
+        hash = fnv1a(hash, &pixel.to_le_bytes());
+    }
+    hash
+}
+
+/// Initial value of the FNV-1a hash
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+
+/// Feeds bytes to the 64 bits FNV-1a hash function
+///
+/// # Parameters
+///
+/// - `hash`: The hash of the previous bytes
+/// - `bytes`: The bytes to hash
+///
+/// # Returns
+///
+/// The updated hash
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01B3);
+    }
+    hash
+}
+
+/// Converts a keypad state into a key mask
+///
+/// # Parameters
+///
+/// - `keys`: The state of each key, `true` when the key is down
+///
+/// # Returns
+///
+/// The key mask, one bit per key
+fn keys_to_mask(keys: [bool; KEY_COUNT]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, pressed)| **pressed)
+        .fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+/// Converts a key mask into a keypad state
+///
+/// # Parameters
+///
+/// - `mask`: The key mask, one bit per key
+///
+/// # Returns
+///
+/// The state of each key, `true` when the key is down
+fn mask_to_keys(mask: u16) -> [bool; KEY_COUNT] {
+    let mut keys = [false; KEY_COUNT];
+    for (key, pressed) in keys.iter_mut().enumerate() {
+        *pressed = mask & (1 << key) != 0;
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a keypad state with the given keys down
+    fn keys(pressed: &[usize]) -> [bool; KEY_COUNT] {
+        let mut keys = [false; KEY_COUNT];
+        for key in pressed {
+            keys[*key] = true;
+        }
+        keys
+    }
+
+    /// Records a movie whose frames have the given keys down, and the frame number as hash
+    fn record(frames: &[&[usize]]) -> Movie {
+        let movie = Movie::new(0xC8, 500, Quirks::superchip(), b"ROM");
+        let mut recorder = MovieRecorder::new(movie);
+        for (frame, pressed) in frames.iter().enumerate() {
+            assert_eq!(recorder.next_frame(keys(pressed)), keys(pressed));
+            recorder.end_frame(frame as u64).unwrap();
+        }
+        recorder.get_movie().clone()
+    }
+
+    #[test]
+    fn movie_round_trips_through_bytes() {
+        let movie = record(&[&[], &[1], &[1, 2], &[]]);
+        let decoded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(decoded, movie);
+        assert_eq!(decoded.frame_count(), 4);
+        assert!(decoded.matches_rom(b"ROM"));
+        assert!(!decoded.matches_rom(b"OTHER ROM"));
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut bytes = record(&[&[]]).to_bytes();
+        bytes[0] = b'X';
+        assert_eq!(Movie::from_bytes(&bytes), Err(MovieError::InvalidMagic));
+        assert_eq!(Movie::from_bytes(b"C8"), Err(MovieError::InvalidMagic));
+    }
+
+    #[test]
+    fn wrong_version_is_rejected() {
+        let mut bytes = record(&[&[]]).to_bytes();
+        let version = (MOVIE_VERSION + 1).to_le_bytes();
+        bytes[MOVIE_MAGIC.len()..MOVIE_MAGIC.len() + 2].copy_from_slice(&version);
+        assert_eq!(
+            Movie::from_bytes(&bytes),
+            Err(MovieError::UnsupportedVersion(MOVIE_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn truncated_or_extended_movie_is_rejected() {
+        let mut bytes = record(&[&[3], &[]]).to_bytes();
+        for len in [
+            MOVIE_MAGIC.len() + 1,
+            MOVIE_MAGIC.len() + 2,
+            bytes.len() - 1,
+        ] {
+            assert_eq!(Movie::from_bytes(&bytes[..len]), Err(MovieError::Corrupted));
+        }
+        bytes.push(0);
+        assert_eq!(Movie::from_bytes(&bytes), Err(MovieError::Corrupted));
+    }
+
+    #[test]
+    fn recorder_saves_key_changes_only() {
+        let movie = record(&[&[], &[1], &[1], &[1, 0xF], &[], &[]]);
+        assert_eq!(movie.events, vec![(1, 0x0002), (3, 0x8002), (4, 0)]);
+        assert_eq!(movie.display_hashes, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn player_returns_the_recorded_keys() {
+        let frames: [&[usize]; 6] = [&[5], &[5], &[], &[2, 8], &[8], &[8]];
+        let mut player = MoviePlayer::new(record(&frames));
+        for (frame, pressed) in frames.iter().enumerate() {
+            assert!(!player.is_finished());
+            // The host keys are ignored
+            assert_eq!(player.next_frame(keys(&[0xA])), keys(pressed));
+            assert_eq!(player.end_frame(frame as u64), Ok(()));
+        }
+        assert_eq!(player.get_frame(), 6);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn player_reports_the_first_divergence() {
+        let mut player = MoviePlayer::new(record(&[&[], &[], &[], &[]]));
+        for hash in 0..2 {
+            player.next_frame(keys(&[]));
+            assert_eq!(player.end_frame(hash), Ok(()));
+        }
+        player.next_frame(keys(&[]));
+        assert_eq!(
+            player.end_frame(7),
+            Err(Divergence {
+                frame: 2,
+                expected: 2,
+                actual: 7
+            })
+        );
+        assert_eq!(player.get_frame(), 3);
+    }
+}
@@ -0,0 +1,423 @@
+use crate::display;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// PNG file signature
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// PNG color type of the indexed color images
+const COLOR_TYPE_INDEXED: u8 = 3;
+
+/// PNG filter type predicting each byte from the byte above it, which turns the repeated rows of
+/// a scaled display into runs of zeros
+const FILTER_UP: u8 = 2;
+
+/// Longest match of a deflate stream
+const MAX_MATCH: usize = 258;
+
+/// Base lengths of the deflate length codes 257 to 285
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Number of extra bits of the deflate length codes 257 to 285
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Error returned when parsing an invalid palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPalette(pub String);
+
+impl fmt::Display for InvalidPalette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid palette '{}' (expected 2 to 4 comma separated RRGGBB colors)",
+            self.0
+        )
+    }
+}
+
+impl error::Error for InvalidPalette {}
+
+/// Colors of the pixels in the PNG images, indexed by the value of the pixel planes: off, plane
+/// 1, plane 2 (XO-CHIP) and both planes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [u32; 1 << display::PLANES]);
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette(display::PALETTE)
+    }
+}
+
+impl FromStr for Palette {
+    type Err = InvalidPalette;
+
+    /// Parses 2 to 4 comma separated hexadecimal `RRGGBB` colors, with an optional `#` prefix,
+    /// e.g. `000000,FFFFFF`. Missing colors keep their default value.
+    fn from_str(spec: &str) -> Result<Palette, InvalidPalette> {
+        let mut palette = Palette::default();
+        let colors: Vec<&str> = spec.split(',').map(str::trim).collect();
+        if colors.len() < 2 || colors.len() > palette.0.len() {
+            return Err(InvalidPalette(spec.to_string()));
+        }
+        for (entry, color) in palette.0.iter_mut().zip(colors) {
+            let digits = color.strip_prefix('#').unwrap_or(color);
+            match u32::from_str_radix(digits, 16) {
+                Ok(value) if digits.len() == 6 => *entry = value,
+                _ => return Err(InvalidPalette(spec.to_string())),
+            }
+        }
+        Ok(palette)
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, color) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{:06X}", color)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a display buffer as a PNG image
+///
+/// # Parameters
+///
+/// - `width`: The display width, in pixels
+/// - `height`: The display height, in pixels
+/// - `buffer`: The display buffer, `width * height` colors of `display::PALETTE`
+/// - `scale`: The size of a display pixel in the image, in image pixels
+/// - `palette`: The colors of the image
+///
+/// # Returns
+///
+/// The bytes of the PNG file
+pub fn encode(
+    width: usize,
+    height: usize,
+    buffer: &[u32],
+    scale: usize,
+    palette: &Palette,
+) -> Vec<u8> {
+    let image_width = width * scale;
+    let image_height = height * scale;
+
+    // Scale the display, each pixel becoming the palette index of its planes
+    let mut pixels = Vec::with_capacity(image_width * image_height);
+    for row in buffer.chunks(width).take(height) {
+        let indexes: Vec<u8> = row
+            .iter()
+            .flat_map(|color| {
+                let index = display::PALETTE
+                    .iter()
+                    .position(|entry| entry == color)
+                    .unwrap_or(0) as u8;
+                std::iter::repeat(index).take(scale)
+            })
+            .collect();
+        for _ in 0..scale {
+            pixels.extend_from_slice(&indexes);
+        }
+    }
+
+    // Filter the rows
+    let mut data = Vec::with_capacity((image_width + 1) * image_height);
+    for y in 0..image_height {
+        let row = &pixels[y * image_width..(y + 1) * image_width];
+        data.push(FILTER_UP);
+        if y == 0 {
+            data.extend_from_slice(row);
+        } else {
+            let above = &pixels[(y - 1) * image_width..y * image_width];
+            data.extend(
+                row.iter()
+                    .zip(above)
+                    .map(|(pixel, up)| pixel.wrapping_sub(*up)),
+            );
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(image_width as u32).to_be_bytes());
+    header.extend_from_slice(&(image_height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, COLOR_TYPE_INDEXED, 0, 0, 0]);
+
+    let colors: Vec<u8> = palette
+        .0
+        .iter()
+        .flat_map(|color| color.to_be_bytes()[1..].to_vec())
+        .collect();
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", &colors);
+    write_chunk(&mut png, b"IDAT", &zlib_compress(&data));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Appends a chunk to a PNG file
+///
+/// # Parameters
+///
+/// - `png`: The bytes of the PNG file
+/// - `kind`: The chunk type
+/// - `data`: The chunk data
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Computes the CRC-32 of the PNG chunks
+///
+/// # Parameters
+///
+/// - `bytes`: The chunk type and data
+///
+/// # Returns
+///
+/// The CRC-32 (ISO 3309 polynomial)
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Compresses data in the zlib format, with a single deflate block using the fixed Huffman
+/// codes. Only runs of identical bytes are matched, which is enough for the large flat areas of
+/// the display.
+///
+/// # Parameters
+///
+/// - `data`: The data to compress
+///
+/// # Returns
+///
+/// The zlib stream
+fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // Header: 32K window, deflate, no dictionary, fastest compression
+    writer.bytes.extend_from_slice(&[0x78, 0x01]);
+    // Last block, fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut index = 0;
+    while index < data.len() {
+        writer.write_literal(data[index] as u16);
+        index += 1;
+        let run = data[index..]
+            .iter()
+            .take(MAX_MATCH)
+            .take_while(|&&byte| byte == data[index - 1])
+            .count();
+        if run >= 3 {
+            writer.write_match(run);
+            index += run;
+        }
+    }
+    // End of block
+    writer.write_literal(256);
+    writer.flush();
+
+    writer.bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    writer.bytes
+}
+
+/// Computes the Adler-32 checksum of the zlib streams
+///
+/// # Parameters
+///
+/// - `data`: The uncompressed data
+///
+/// # Returns
+///
+/// The checksum
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Writer of a deflate bit stream, filling the bytes from their least significant bit
+#[derive(Debug, Default)]
+struct BitWriter {
+    /// Complete bytes
+    bytes: Vec<u8>,
+    /// Pending bits, not forming a complete byte yet
+    bits: u32,
+    /// Number of pending bits
+    bit_count: u32,
+}
+
+impl BitWriter {
+    /// Writes a value, least significant bit first
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The value
+    /// - `count`: The number of bits of the value
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, most significant bit first
+    ///
+    /// # Parameters
+    ///
+    /// - `code`: The code
+    /// - `length`: The number of bits of the code
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.write_bits(reversed, length);
+    }
+
+    /// Writes a literal/length symbol with the fixed Huffman codes
+    ///
+    /// # Parameters
+    ///
+    /// - `symbol`: The symbol, from 0 to 287
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    /// Writes a match repeating the previous byte
+    ///
+    /// # Parameters
+    ///
+    /// - `length`: The length of the match, from 3 to `MAX_MATCH`
+    fn write_match(&mut self, length: usize) {
+        let code = LENGTH_BASES
+            .iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap_or(0);
+        self.write_literal(257 + code as u16);
+        let extra_bits = LENGTH_EXTRA_BITS[code] as u32;
+        if extra_bits > 0 {
+            self.write_bits((length - LENGTH_BASES[code] as usize) as u32, extra_bits);
+        }
+        // Distance 1: code 0 with 5 bits, no extra bits
+        self.write_code(0, 5);
+    }
+
+    /// Writes the pending bits, padded with zeros to a complete byte
+    fn flush(&mut self) {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bits as u8);
+            self.bits = 0;
+            self.bit_count = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a PNG file into its chunks: type and data, checking their CRC
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let body = &rest[4..8 + length];
+            let crc = &rest[8 + length..12 + length];
+            assert_eq!(crc, crc32(body).to_be_bytes());
+            chunks.push(([body[0], body[1], body[2], body[3]], body[4..].to_vec()));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn header_has_the_scaled_dimensions() {
+        let buffer = [
+            display::PALETTE[0],
+            display::PALETTE[1],
+            display::PALETTE[3],
+        ];
+        let png = encode(3, 1, &buffer, 4, &Palette::default());
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"PLTE", b"IDAT", b"IEND"]);
+
+        let (_, header) = &chunks[0];
+        assert_eq!(header[..4], 12u32.to_be_bytes());
+        assert_eq!(header[4..8], 4u32.to_be_bytes());
+        // 8 bits indexed colors, deflate, no interlacing
+        assert_eq!(header[8..], [8, COLOR_TYPE_INDEXED, 0, 0, 0]);
+    }
+
+    #[test]
+    fn palette_chunk_holds_the_colors() {
+        let palette: Palette = "#102030,405060".parse().unwrap();
+        let png = encode(1, 1, &[display::PALETTE[0]], 1, &palette);
+        let (_, colors) = &chunks(&png)[1];
+        assert_eq!(
+            *colors,
+            [0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0xAA, 0xAA, 0xAA, 0x55, 0x55, 0x55]
+        );
+    }
+
+    #[test]
+    fn palette_is_parsed() {
+        assert_eq!(
+            "000000,FFFFFF,FF0000,00FF00".parse(),
+            Ok(Palette([0x000000, 0xFFFFFF, 0xFF0000, 0x00FF00]))
+        );
+        assert_eq!(
+            Palette::default().to_string(),
+            "000000,FFFFFF,AAAAAA,555555"
+        );
+        for spec in ["000000", "000000,FFFFF", "0,1,2,3,4", "000000,GGGGGG"] {
+            assert_eq!(
+                spec.parse::<Palette>(),
+                Err(InvalidPalette(spec.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
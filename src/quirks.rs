@@ -0,0 +1,223 @@
+use crate::memory::{EXTENDED_MEMORY_SIZE, MEMORY_SIZE};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// How Fx55 and Fx65 update the memory address register I
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// I is left unchanged
+    Unchanged,
+    /// I is incremented by x
+    ByX,
+    /// I is incremented by x + 1, pointing right after the last accessed byte
+    ByXPlusOne,
+}
+
+/// Behaviour of the instructions whose semantics differ between CHIP-8 implementations.
+///
+/// The default quirks shift Vx in place, leave I unchanged on Fx55 / Fx65, jump to nnn + V0, leave
+/// VF untouched on logic operations, wrap sprites around the screen edges, set VF to 0 or 1 after
+/// Dxyn, scroll 4 pixels right or left in both resolution modes, complete Fx0A on key press and
+/// provide a 4 KiB memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6 / 8xyE store the shifted value of Vy in Vx, instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    /// How Fx55 / Fx65 update I
+    pub index_increment: IndexIncrement,
+    /// Bxnn jumps to xnn + Vx, instead of Bnnn jumping to nnn + V0
+    pub jump_uses_vx: bool,
+    /// 8xy1 / 8xy2 / 8xy3 reset VF to 0
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the screen edges, instead of wrapping around
+    pub clip_sprites: bool,
+    /// Dxyn in the 128x64 mode sets VF to the number of sprite rows that collided or were clipped
+    /// off the bottom of the display, instead of 1 on collision
+    pub count_collision_rows: bool,
+    /// 00FB / 00FC scroll 2 pixels in the 64x32 mode, the width of 4 pixels of the 128x64 mode,
+    /// instead of 4
+    pub halve_lores_scroll: bool,
+    /// Fx0A completes when the pressed key is released, instead of when it is pressed
+    pub key_wait_on_release: bool,
+    /// Size of the memory, in bytes: 4 KiB, or 64 KiB for XO-CHIP
+    pub memory_size: usize,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            count_collision_rows: false,
+            halve_lores_scroll: false,
+            key_wait_on_release: false,
+            memory_size: MEMORY_SIZE,
+        }
+    }
+}
+
+/// Names of the quirk presets, as accepted by `Quirks::from_str`
+pub const PRESET_NAMES: [&str; 4] = ["cosmac-vip", "chip-48", "superchip", "xo-chip"];
+
+impl Quirks {
+    /// Quirks of the original COSMAC VIP interpreter
+    ///
+    /// # Returns
+    ///
+    /// The COSMAC VIP `Quirks`
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            count_collision_rows: false,
+            halve_lores_scroll: false,
+            key_wait_on_release: true,
+            memory_size: MEMORY_SIZE,
+        }
+    }
+
+    /// Quirks of the CHIP-48 interpreter for HP-48 calculators
+    ///
+    /// # Returns
+    ///
+    /// The CHIP-48 `Quirks`
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            count_collision_rows: false,
+            halve_lores_scroll: false,
+            key_wait_on_release: false,
+            memory_size: MEMORY_SIZE,
+        }
+    }
+
+    /// Quirks of the SUPER-CHIP 1.1 interpreter
+    ///
+    /// # Returns
+    ///
+    /// The SUPER-CHIP 1.1 `Quirks`
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            count_collision_rows: true,
+            halve_lores_scroll: true,
+            key_wait_on_release: false,
+            memory_size: MEMORY_SIZE,
+        }
+    }
+
+    /// Quirks of XO-CHIP, as implemented by Octo
+    ///
+    /// # Returns
+    ///
+    /// The XO-CHIP `Quirks`
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            count_collision_rows: false,
+            halve_lores_scroll: false,
+            key_wait_on_release: false,
+            memory_size: EXTENDED_MEMORY_SIZE,
+        }
+    }
+
+    /// Writes the quirks in a save state
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The save state writer
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.shift_uses_vy);
+        writer.write_u8(match self.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => 1,
+            IndexIncrement::ByXPlusOne => 2,
+        });
+        writer.write_bool(self.jump_uses_vx);
+        writer.write_bool(self.logic_resets_vf);
+        writer.write_bool(self.clip_sprites);
+        writer.write_bool(self.count_collision_rows);
+        writer.write_bool(self.halve_lores_scroll);
+        writer.write_bool(self.key_wait_on_release);
+        writer.write_u32(self.memory_size as u32);
+    }
+
+    /// Reads quirks written by `save_state`
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The save state reader
+    ///
+    /// # Returns
+    ///
+    /// The quirks, or a `StateError` if the save state is invalid
+    pub fn load_state(reader: &mut StateReader) -> Result<Quirks, StateError> {
+        Ok(Quirks {
+            shift_uses_vy: reader.read_bool("shift quirk")?,
+            index_increment: match reader.read_u8()? {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::ByX,
+                2 => IndexIncrement::ByXPlusOne,
+                _ => return Err(StateError::InvalidValue("index increment quirk")),
+            },
+            jump_uses_vx: reader.read_bool("jump quirk")?,
+            logic_resets_vf: reader.read_bool("logic quirk")?,
+            clip_sprites: reader.read_bool("clip quirk")?,
+            count_collision_rows: reader.read_bool("collision quirk")?,
+            halve_lores_scroll: reader.read_bool("scroll quirk")?,
+            key_wait_on_release: reader.read_bool("key wait quirk")?,
+            memory_size: reader.read_u32()? as usize,
+        })
+    }
+}
+
+/// Error returned when parsing an unknown quirk preset name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPreset(pub String);
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown quirks preset '{}' (expected one of: {})",
+            self.0,
+            PRESET_NAMES.join(", ")
+        )
+    }
+}
+
+impl error::Error for UnknownPreset {}
+
+impl FromStr for Quirks {
+    type Err = UnknownPreset;
+
+    fn from_str(name: &str) -> Result<Quirks, UnknownPreset> {
+        match name.to_ascii_lowercase().as_str() {
+            "cosmac-vip" | "vip" | "chip-8" => Ok(Quirks::cosmac_vip()),
+            "chip-48" | "chip48" => Ok(Quirks::chip48()),
+            "superchip" | "super-chip" | "schip" => Ok(Quirks::superchip()),
+            "xo-chip" | "xochip" => Ok(Quirks::xo_chip()),
+            _ => Err(UnknownPreset(name.to_string())),
+        }
+    }
+}
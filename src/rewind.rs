@@ -0,0 +1,352 @@
+use std::collections::VecDeque;
+
+/// Default memory budget of the rewind history, in bytes
+pub const DEFAULT_REWIND_BUDGET: usize = 16 * 1024 * 1024;
+
+/// Rewind history: a bounded ring buffer of the save states of the last frames.
+///
+/// Only the latest state is stored in full. Each older state is stored as a delta against the
+/// state that followed it: the XOR of the two states, whose runs of zeros (the bytes that did
+/// not change) are run-length encoded. When the latest state and the deltas exceed the memory
+/// budget, the oldest deltas are dropped.
+pub struct Rewind {
+    /// Maximum size of the latest state and the deltas, in bytes
+    budget: usize,
+    /// The latest state
+    current: Vec<u8>,
+    /// Deltas to the previous states, from the oldest to the most recent
+    deltas: VecDeque<Vec<u8>>,
+    /// Size of the deltas, in bytes
+    size: usize,
+}
+
+impl Rewind {
+    /// Creates and returns a new `Rewind` struct, with an empty history.
+    ///
+    /// # Parameters
+    ///
+    /// - `budget`: The maximum memory used by the history, in bytes (0 disables the history)
+    ///
+    /// # Returns
+    ///
+    /// A new `Rewind` struct.
+    pub fn new(budget: usize) -> Rewind {
+        Rewind {
+            budget,
+            current: Vec::new(),
+            deltas: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    /// Gets the number of states the history can step back
+    ///
+    /// # Returns
+    ///
+    /// The number of previous states in the history
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Is the history unable to step back?
+    ///
+    /// # Returns
+    ///
+    /// `true` if there is no previous state in the history
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Forgets every state
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.deltas.clear();
+        self.size = 0;
+    }
+
+    /// Records the state of a new frame, which becomes the latest state
+    ///
+    /// # Parameters
+    ///
+    /// - `state`: The save state of the frame
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.budget == 0 {
+            return;
+        }
+        if !self.current.is_empty() {
+            let delta = encode_delta(&state, &self.current);
+            self.size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.current = state;
+
+        while self.size + self.current.len() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Steps back one frame: drops the latest state
+    ///
+    /// # Returns
+    ///
+    /// The previous state, which becomes the latest one, or None if the history cannot step back
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        self.size -= delta.len();
+        self.current = decode_delta(&self.current, &delta);
+        Some(&self.current)
+    }
+}
+
+/// Encodes the delta turning a state into another one.
+///
+/// When the states have the same length, the delta is a sequence of (number of unchanged bytes,
+/// number of changed bytes, changed bytes XOR) chunks, the numbers being LEB128 encoded.
+/// Otherwise, it is a 0xFF marker followed by the whole target state.
+///
+/// # Parameters
+///
+/// - `from`: The state the delta applies to
+/// - `to`: The state the delta produces
+///
+/// # Returns
+///
+/// The encoded delta
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    if from.len() != to.len() {
+        delta.push(0xFF);
+        delta.extend_from_slice(to);
+        return delta;
+    }
+
+    delta.push(0x00);
+    let mut i = 0;
+    while i < to.len() {
+        let unchanged = from[i..]
+            .iter()
+            .zip(&to[i..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        i += unchanged;
+        let changed = from[i..]
+            .iter()
+            .zip(&to[i..])
+            .take_while(|(a, b)| a != b)
+            .count();
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend(
+            from[i..i + changed]
+                .iter()
+                .zip(&to[i..])
+                .map(|(a, b)| a ^ b),
+        );
+        i += changed;
+    }
+    delta
+}
+
+/// Applies a delta encoded by `encode_delta` to a state
+///
+/// # Parameters
+///
+/// - `from`: The state the delta applies to
+/// - `delta`: The encoded delta
+///
+/// # Returns
+///
+/// The state the delta produces
+fn decode_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta.first() == Some(&0xFF) {
+        return delta[1..].to_vec();
+    }
+
+    let mut state = from.to_vec();
+    let mut i = 0;
+    let mut position = 1;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position);
+        let changed = read_varint(delta, &mut position);
+        for byte in &mut state[i..i + changed] {
+            *byte ^= delta[position];
+            position += 1;
+        }
+        i += changed;
+    }
+    state
+}
+
+/// Appends a LEB128 encoded number: 7 bits per byte, the high bit set on all but the last byte
+///
+/// # Parameters
+///
+/// - `buffer`: The buffer to append the number to
+/// - `value`: The number to encode
+fn write_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Reads a LEB128 encoded number written by `write_varint`
+///
+/// # Parameters
+///
+/// - `buffer`: The buffer to read the number from
+/// - `position`: The position of the number, moved past it
+///
+/// # Returns
+///
+/// The decoded number
+fn read_varint(buffer: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buffer[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that the delta between two states turns the first one into the second one
+    fn assert_round_trip(from: &[u8], to: &[u8]) {
+        let delta = encode_delta(from, to);
+        assert_eq!(decode_delta(from, &delta), to);
+    }
+
+    #[test]
+    fn delta_of_identical_states_is_empty() {
+        let state = vec![7; 300];
+
+        assert_eq!(encode_delta(&state, &state), [0x00, 0xAC, 0x02, 0x00]);
+        assert_round_trip(&state, &state);
+    }
+
+    #[test]
+    fn delta_round_trips_scattered_changes() {
+        let from: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut to = from.clone();
+        to[0] ^= 0x01;
+        to[1] ^= 0x80;
+        for byte in &mut to[200..400] {
+            *byte = !*byte;
+        }
+        to[999] = 0;
+
+        assert_round_trip(&from, &to);
+        assert_round_trip(&to, &from);
+    }
+
+    #[test]
+    fn delta_round_trips_length_changes() {
+        let from = vec![1, 2, 3];
+        let to = vec![1, 2, 3, 4];
+
+        let delta = encode_delta(&from, &to);
+        assert_eq!(delta, [0xFF, 1, 2, 3, 4]);
+        assert_round_trip(&from, &to);
+        assert_round_trip(&to, &from);
+        assert_round_trip(&from, &[]);
+    }
+
+    #[test]
+    fn varint_round_trips() {
+        let mut buffer = Vec::new();
+        let values = [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, usize::MAX];
+        for &value in &values {
+            write_varint(&mut buffer, value);
+        }
+
+        let mut position = 0;
+        for &value in &values {
+            assert_eq!(read_varint(&buffer, &mut position), value);
+        }
+        assert_eq!(position, buffer.len());
+    }
+
+    #[test]
+    fn pop_returns_the_states_from_the_most_recent() {
+        let mut rewind = Rewind::new(DEFAULT_REWIND_BUDGET);
+        for frame in 0..5_u8 {
+            rewind.push(vec![frame; 64]);
+        }
+        assert_eq!(rewind.len(), 4);
+
+        for frame in (0..4_u8).rev() {
+            assert_eq!(rewind.pop(), Some(&[frame; 64][..]));
+        }
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn push_after_pop_continues_from_the_popped_state() {
+        let mut rewind = Rewind::new(DEFAULT_REWIND_BUDGET);
+        rewind.push(vec![0; 8]);
+        rewind.push(vec![1; 8]);
+        rewind.pop();
+        rewind.push(vec![2; 8]);
+
+        assert_eq!(rewind.pop(), Some(&[0; 8][..]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn oldest_states_are_evicted_over_the_budget() {
+        // Each state differs completely from the previous one: the deltas take 3 + 95 bytes, so
+        // the latest state and 2 deltas fit in the budget
+        let mut rewind = Rewind::new(300);
+        for frame in 0..10_u8 {
+            rewind.push(vec![frame; 95]);
+        }
+
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.pop(), Some(&[8; 95][..]));
+        assert_eq!(rewind.pop(), Some(&[7; 95][..]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn budget_counts_the_latest_state() {
+        let mut rewind = Rewind::new(100);
+        rewind.push(vec![0; 60]);
+        rewind.push(vec![1; 60]);
+
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn zero_budget_disables_the_history() {
+        let mut rewind = Rewind::new(0);
+        rewind.push(vec![0; 8]);
+        rewind.push(vec![1; 8]);
+
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn clear_forgets_every_state() {
+        let mut rewind = Rewind::new(DEFAULT_REWIND_BUDGET);
+        rewind.push(vec![0; 8]);
+        rewind.push(vec![1; 8]);
+        rewind.clear();
+        rewind.push(vec![2; 8]);
+
+        assert!(rewind.is_empty());
+    }
+}
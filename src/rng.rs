@@ -0,0 +1,108 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Seedable pseudo random number generator (SplitMix64) used by the `RND` instruction.
+///
+/// The whole generator state is a single 64 bits value, which is part of the save states:
+/// two machines with the same seed and the same inputs produce the same random numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    /// The generator state, advanced by each number generated
+    state: u64,
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::from_entropy()
+    }
+}
+
+impl Rng {
+    /// Creates and returns a new `Rng` struct, whose numbers only depend on the seed.
+    ///
+    /// # Parameters
+    ///
+    /// - `seed`: The seed of the generator
+    ///
+    /// # Returns
+    ///
+    /// A new `Rng` struct.
+    pub fn from_seed(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Creates and returns a new `Rng` struct with an unpredictable seed.
+    ///
+    /// # Returns
+    ///
+    /// A new `Rng` struct.
+    pub fn from_entropy() -> Rng {
+        // The standard library seeds the hash map keys randomly
+        Rng::from_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// Gets the generator state
+    ///
+    /// # Returns
+    ///
+    /// The generator state, which `from_seed` turns back into the same generator
+    pub fn get_state(&self) -> u64 {
+        self.state
+    }
+
+    /// Generates a random 64 bits number
+    ///
+    /// # Returns
+    ///
+    /// The generated number
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Generates a random byte
+    ///
+    /// # Returns
+    ///
+    /// The generated byte, from 0 to 255
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_the_same_numbers() {
+        let mut a = Rng::from_seed(0x1234_5678);
+        let mut b = Rng::from_seed(0x1234_5678);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_eq!(a.get_state(), b.get_state());
+        assert_ne!(Rng::from_seed(1).next_u64(), Rng::from_seed(2).next_u64());
+    }
+
+    #[test]
+    fn numbers_follow_the_splitmix64_sequence() {
+        let mut rng = Rng::from_seed(0);
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+        assert_eq!(rng.next_u64(), 0x06C4_5D18_8009_454F);
+        // Bytes are the high bits of the next number
+        assert_eq!(Rng::from_seed(0).next_u8(), 0xE2);
+    }
+
+    #[test]
+    fn state_resumes_the_sequence() {
+        let mut rng = Rng::from_seed(42);
+        rng.next_u64();
+        let mut resumed = Rng::from_seed(rng.get_state());
+        assert_eq!(resumed.next_u64(), rng.next_u64());
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+/// Number of frames per second, the rate of the delay and sound timers
+pub const FRAME_RATE: u32 = 60;
+/// Maximum number of late frames run at once to catch up after a stall, the other ones are dropped
+pub const MAX_CATCH_UP_FRAMES: u32 = 5;
+
+/// Frame scheduler: paces the emulation at 60 frames per second, each frame running a fixed
+/// number of instructions followed by one timers tick.
+///
+/// The number of instructions of each frame only depends on the instruction rate and on the
+/// frame number, so the emulation is the same whatever the host load. When the host stalls, the
+/// late frames are run back to back, up to `MAX_CATCH_UP_FRAMES`; older late frames are dropped.
+pub struct Scheduler {
+    /// Number of instructions executed per second
+    cpu_hz: u32,
+    /// Number of frames run so far
+    frame: u64,
+    /// Duration of a frame
+    frame_time: Duration,
+    /// Deadline of the next frame
+    next_frame_time: Instant,
+}
+
+impl Scheduler {
+    /// Creates and returns a new `Scheduler` struct, whose first frame is due at `start`.
+    ///
+    /// # Parameters
+    ///
+    /// - `cpu_hz`: The number of instructions executed per second
+    /// - `start`: The time of the first frame
+    ///
+    /// # Returns
+    ///
+    /// A new `Scheduler` struct.
+    pub fn new(cpu_hz: u32, start: Instant) -> Scheduler {
+        Scheduler {
+            cpu_hz,
+            frame: 0,
+            frame_time: Duration::from_secs(1) / FRAME_RATE,
+            next_frame_time: start,
+        }
+    }
+
+    /// Counts the frames due at the given time and moves the deadline of the next frame past it
+    ///
+    /// # Parameters
+    ///
+    /// - `now`: The current time
+    ///
+    /// # Returns
+    ///
+    /// The number of frames to run now, at most `MAX_CATCH_UP_FRAMES`
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        let mut frames = 0;
+        while self.next_frame_time <= now {
+            frames += 1;
+            self.next_frame_time += self.frame_time;
+            if frames == MAX_CATCH_UP_FRAMES && self.next_frame_time <= now {
+                // Drop the frames the host could not keep up with
+                self.next_frame_time = now + self.frame_time;
+            }
+        }
+        frames
+    }
+
+    /// Gets the time left before the next frame is due
+    ///
+    /// # Parameters
+    ///
+    /// - `now`: The current time
+    ///
+    /// # Returns
+    ///
+    /// The time left before the next frame, zero if it is already due
+    pub fn time_until_next_frame(&self, now: Instant) -> Duration {
+        self.next_frame_time.saturating_duration_since(now)
+    }
+
+    /// Counts the instructions of the next frame and moves on to the following frame.
+    /// When the instruction rate is not a multiple of the frame rate, the remainder is spread
+    /// over the frames so that exactly `cpu_hz` instructions run every 60 frames.
+    ///
+    /// # Returns
+    ///
+    /// The number of instructions to run during the next frame
+    pub fn next_frame_cycles(&mut self) -> u32 {
+        let cpu_hz = self.cpu_hz as u64;
+        let frame_rate = FRAME_RATE as u64;
+        let start = self.frame * cpu_hz / frame_rate;
+        let end = (self.frame + 1) * cpu_hz / frame_rate;
+        self.frame += 1;
+        (end - start) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions_add_up_to_the_rate_every_60_frames() {
+        let mut scheduler = Scheduler::new(500, Instant::now());
+        let cycles: Vec<u32> = (0..FRAME_RATE)
+            .map(|_| scheduler.next_frame_cycles())
+            .collect();
+        assert_eq!(cycles.iter().sum::<u32>(), 500);
+        assert!(cycles.iter().all(|&n| n == 8 || n == 9));
+        let next: u32 = (0..FRAME_RATE).map(|_| scheduler.next_frame_cycles()).sum();
+        assert_eq!(next, 500);
+    }
+
+    #[test]
+    fn frames_are_due_at_the_frame_rate() {
+        let start = Instant::now();
+        let frame_time = Duration::from_secs(1) / FRAME_RATE;
+        let mut scheduler = Scheduler::new(500, start);
+        assert_eq!(scheduler.frames_due(start), 1);
+        assert_eq!(scheduler.frames_due(start), 0);
+        assert_eq!(scheduler.time_until_next_frame(start), frame_time);
+        assert_eq!(scheduler.frames_due(start + frame_time * 2), 2);
+    }
+
+    #[test]
+    fn a_long_stall_runs_at_most_the_catch_up_frames() {
+        let start = Instant::now();
+        let frame_time = Duration::from_secs(1) / FRAME_RATE;
+        let mut scheduler = Scheduler::new(500, start);
+        let now = start + frame_time * 100;
+        assert_eq!(scheduler.frames_due(now), MAX_CATCH_UP_FRAMES);
+        // The backlog is dropped: the next frame is one frame time away
+        assert_eq!(scheduler.frames_due(now), 0);
+        assert_eq!(scheduler.time_until_next_frame(now), frame_time);
+        assert_eq!(scheduler.frames_due(now + frame_time), 1);
+    }
+}
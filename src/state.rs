@@ -0,0 +1,372 @@
+use std::error;
+use std::fmt;
+
+/// Magic number starting every save state
+pub const STATE_MAGIC: [u8; 4] = *b"C8ST";
+/// Version of the save state format, incremented on every format change
+pub const STATE_VERSION: u16 = 2;
+
+/// Error returned when a save state cannot be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with `STATE_MAGIC`: it is not a save state
+    InvalidMagic,
+    /// The save state has been written by an incompatible version of the emulator
+    UnsupportedVersion(u16),
+    /// The save state ends unexpectedly
+    Truncated,
+    /// The save state contains an invalid value
+    InvalidValue(&'static str),
+    /// The save state contains data after its end
+    TrailingData,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::InvalidMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported save state version {} (expected {})",
+                version, STATE_VERSION
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::InvalidValue(field) => write!(f, "save state has an invalid {}", field),
+            StateError::TrailingData => write!(f, "save state has unexpected trailing data"),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+/// Writer of save state values, encoded in little endian
+#[derive(Default)]
+pub struct StateWriter {
+    /// The encoded save state
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Creates and returns a new `StateWriter` struct, with nothing written yet.
+    ///
+    /// # Returns
+    ///
+    /// A new `StateWriter` struct.
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    /// Writes a byte
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The byte to write
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    /// Writes a boolean, as a 0 or 1 byte
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The boolean to write
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    /// Writes a 16 bits value
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The value to write
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a 32 bits value
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The value to write
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a 64 bits value
+    ///
+    /// # Parameters
+    ///
+    /// - `value`: The value to write
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a sequence of bytes, without its length
+    ///
+    /// # Parameters
+    ///
+    /// - `bytes`: The bytes to write
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Ends the writing
+    ///
+    /// # Returns
+    ///
+    /// The encoded save state
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reader of the values of a save state, encoded in little endian
+pub struct StateReader<'a> {
+    /// The part of the save state not read yet
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Creates and returns a new `StateReader` struct, reading from the start of `data`.
+    ///
+    /// # Parameters
+    ///
+    /// - `data`: The encoded save state
+    ///
+    /// # Returns
+    ///
+    /// A new `StateReader` struct.
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    /// Reads a sequence of bytes
+    ///
+    /// # Parameters
+    ///
+    /// - `len`: The number of bytes to read
+    ///
+    /// # Returns
+    ///
+    /// The bytes, or `StateError::Truncated` if the save state is too short
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Reads a byte
+    ///
+    /// # Returns
+    ///
+    /// The byte, or `StateError::Truncated` if the save state is too short
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Reads a boolean
+    ///
+    /// # Parameters
+    ///
+    /// - `field`: The name of the value, reported if it is neither 0 nor 1
+    ///
+    /// # Returns
+    ///
+    /// The boolean, or a `StateError` if the save state is too short or the value is invalid
+    pub fn read_bool(&mut self, field: &'static str) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidValue(field)),
+        }
+    }
+
+    /// Reads a 16 bits value
+    ///
+    /// # Returns
+    ///
+    /// The value, or `StateError::Truncated` if the save state is too short
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads a 32 bits value
+    ///
+    /// # Returns
+    ///
+    /// The value, or `StateError::Truncated` if the save state is too short
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a 64 bits value
+    ///
+    /// # Returns
+    ///
+    /// The value, or `StateError::Truncated` if the save state is too short
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Checks that the whole save state has been read
+    ///
+    /// # Returns
+    ///
+    /// `StateError::TrailingData` if some bytes have not been read
+    pub fn finish(&self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::TrailingData)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+    use crate::rng::Rng;
+
+    /// Creates a machine which has run a few frames of a bundled ROM
+    fn running_chip8() -> Chip8 {
+        let rom = include_bytes!("../roms/BRIX");
+        let mut chip8 = Chip8::new();
+        chip8.set_rng(Rng::from_seed(0xC8));
+        chip8.load_rom(rom).unwrap();
+        chip8.set_key(0x4, true);
+        for _ in 0..30 {
+            chip8.run_frame(9).unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn save_load_save_is_identical() {
+        let state = running_chip8().save_state();
+
+        let mut chip8 = Chip8::new();
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.save_state(), state);
+    }
+
+    #[test]
+    fn loaded_state_runs_like_the_original() {
+        let mut original = running_chip8();
+        let mut restored = Chip8::new();
+        restored.load_state(&original.save_state()).unwrap();
+
+        for _ in 0..30 {
+            original.run_frame(9).unwrap();
+            restored.run_frame(9).unwrap();
+        }
+        assert_eq!(restored.save_state(), original.save_state());
+    }
+
+    #[test]
+    fn loaded_state_brings_its_own_quirks() {
+        // LD V1, #05; LD V2, #0C; SHR V1, V2; LD I, #2000; LD [I], V0
+        let program = [
+            0x61, 0x05, 0x62, 0x0C, 0x81, 0x26, 0xF0, 0x00, 0x20, 0x00, 0xF0, 0x55,
+        ];
+        let mut original = Chip8::with_quirks(Quirks::xo_chip());
+        original.load_rom(&program).unwrap();
+        original.step_instruction().unwrap();
+        original.step_instruction().unwrap();
+
+        let mut restored = Chip8::new();
+        restored.load_state(&original.save_state()).unwrap();
+        // Vy is shifted into Vx, I is incremented, and the 64 KiB memory is addressable
+        for _ in 0..3 {
+            restored.step_instruction().unwrap();
+        }
+        assert_eq!(restored.get_register(1), 0x06);
+        assert_eq!(restored.get_i(), 0x2001);
+        assert_eq!(restored.read_memory(0x2000), Ok(0));
+
+        let mut default = Chip8::new();
+        default.load_rom(&program).unwrap();
+        default.step_instruction().unwrap();
+        default.step_instruction().unwrap();
+        let mut restored = Chip8::with_quirks(Quirks::xo_chip());
+        restored.load_state(&default.save_state()).unwrap();
+        // Vx is shifted in place, and the 4 KiB memory ends before 0x2000
+        restored.step_instruction().unwrap();
+        assert_eq!(restored.get_register(1), 0x02);
+        assert!(restored.read_memory(0x2000).is_err());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut state = running_chip8().save_state();
+        state[0] = b'X';
+
+        assert_eq!(
+            Chip8::new().load_state(&state),
+            Err(StateError::InvalidMagic)
+        );
+        assert_eq!(
+            Chip8::new().load_state(&state[..2]),
+            Err(StateError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    fn bad_version_is_rejected() {
+        let mut state = running_chip8().save_state();
+        let version = (STATE_VERSION + 1).to_le_bytes();
+        state[STATE_MAGIC.len()..STATE_MAGIC.len() + 2].copy_from_slice(&version);
+
+        assert_eq!(
+            Chip8::new().load_state(&state),
+            Err(StateError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn truncated_state_is_rejected_and_leaves_the_machine_unchanged() {
+        let state = running_chip8().save_state();
+        let mut chip8 = Chip8::new();
+        let before = chip8.save_state();
+
+        for len in [
+            STATE_MAGIC.len() + 1,
+            STATE_MAGIC.len() + 2,
+            state.len() - 1,
+        ] {
+            assert_eq!(chip8.load_state(&state[..len]), Err(StateError::Truncated));
+        }
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    #[test]
+    fn trailing_data_is_rejected() {
+        let mut state = running_chip8().save_state();
+        state.push(0);
+
+        assert_eq!(
+            Chip8::new().load_state(&state),
+            Err(StateError::TrailingData)
+        );
+    }
+
+    #[test]
+    fn invalid_boolean_is_rejected() {
+        let mut reader = StateReader::new(&[1, 2]);
+
+        assert_eq!(reader.read_bool("flag"), Ok(true));
+        assert_eq!(
+            reader.read_bool("flag"),
+            Err(StateError::InvalidValue("flag"))
+        );
+        assert_eq!(reader.finish(), Ok(()));
+    }
+}
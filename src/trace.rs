@@ -0,0 +1,434 @@
+use crate::instruction;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Error returned when parsing an invalid trace filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTraceFilter(pub String);
+
+impl fmt::Display for InvalidTraceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid trace filter '{}'", self.0)
+    }
+}
+
+impl error::Error for InvalidTraceFilter {}
+
+/// A range of instruction addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcRange {
+    /// First address of the range
+    pub start: u16,
+    /// Last address of the range, included
+    pub end: u16,
+}
+
+impl FromStr for PcRange {
+    type Err = InvalidTraceFilter;
+
+    /// Parses `<START>[-<END>]`, hexadecimal addresses with an optional `#` or `0x` prefix,
+    /// e.g. `200-2FF`
+    fn from_str(spec: &str) -> Result<PcRange, InvalidTraceFilter> {
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (parse_hex(start), parse_hex(end)),
+            None => (parse_hex(spec), parse_hex(spec)),
+        };
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => Ok(PcRange { start, end }),
+            _ => Err(InvalidTraceFilter(spec.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for PcRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}-{:04X}", self.start, self.end)
+    }
+}
+
+/// A set of opcode families, a family being the first hexadecimal digit of the opcode as
+/// dispatched by the CPU: `8` for the register operations, `D` for `DRW`, `F` for the timers,
+/// keyboard and memory operations, ...
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeFamilies(u16);
+
+impl OpcodeFamilies {
+    /// Checks whether an opcode belongs to one of the families
+    ///
+    /// # Parameters
+    ///
+    /// - `opcode`: The opcode
+    ///
+    /// # Returns
+    ///
+    /// true if the family of the opcode is in the set
+    pub fn contains(&self, opcode: u16) -> bool {
+        self.0 & (1 << (opcode >> 12)) != 0
+    }
+}
+
+impl FromStr for OpcodeFamilies {
+    type Err = InvalidTraceFilter;
+
+    /// Parses a comma separated list of hexadecimal digits, e.g. `8,D,F`
+    fn from_str(spec: &str) -> Result<OpcodeFamilies, InvalidTraceFilter> {
+        let mut families = 0;
+        for family in spec.split(',').map(str::trim) {
+            match u8::from_str_radix(family, 16) {
+                Ok(family_digit) if family.len() == 1 => families |= 1 << family_digit,
+                _ => return Err(InvalidTraceFilter(spec.to_string())),
+            }
+        }
+        Ok(OpcodeFamilies(families))
+    }
+}
+
+/// Instructions logged by a `Tracer`. An instruction is logged when it matches every filter set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Addresses of the logged instructions; None logs every address
+    pub pc_range: Option<PcRange>,
+    /// Families of the logged instructions; None logs every family
+    pub families: Option<OpcodeFamilies>,
+    /// Number of instructions executed since the start of the trace after which nothing is
+    /// logged anymore; None logs until the end
+    pub limit: Option<u64>,
+}
+
+/// State of the machine after an executed instruction, formatted as a trace line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Address of the instruction
+    pub pc: u16,
+    /// Opcode of the instruction
+    pub opcode: u16,
+    /// Registers V0 to VF after the instruction
+    pub registers: [u8; 16],
+    /// I register after the instruction
+    pub i: u16,
+    /// Stack depth after the instruction
+    pub sp: u8,
+    /// Delay timer after the instruction
+    pub dt: u8,
+    /// Sound timer after the instruction
+    pub st: u8,
+}
+
+impl fmt::Display for TraceEntry {
+    /// Formats the entry as a fixed width line: address, opcode, mnemonic, then V0 to VF, I,
+    /// SP, DT and ST in hexadecimal, e.g. `0200 6A02 LD VA, #02  V=00 ... I=0000 SP=00 DT=00 ST=00`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match instruction::decode(self.opcode) {
+            Some(instruction) => instruction.to_string(),
+            None => String::from("???"),
+        };
+        write!(f, "{:04X} {:04X} {:<18} V=", self.pc, self.opcode, mnemonic)?;
+        for (x, value) in self.registers.iter().enumerate() {
+            if x > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02X}", value)?;
+        }
+        write!(
+            f,
+            " I={:04X} SP={:02X} DT={:02X} ST={:02X}",
+            self.i, self.sp, self.dt, self.st
+        )
+    }
+}
+
+/// Execution trace, logging one line per executed instruction matching its filter
+pub struct Tracer {
+    /// Destination of the trace
+    writer: Box<dyn Write>,
+    /// Logged instructions
+    filter: TraceFilter,
+    /// Number of instructions executed since the start of the trace
+    executed: u64,
+    /// First error that occurred while writing the trace
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Creates a trace file and returns a tracer writing to it
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: The path of the trace file
+    /// - `filter`: The logged instructions
+    ///
+    /// # Returns
+    ///
+    /// A new `Tracer` struct, or the error that prevented the file creation
+    pub fn create<P: AsRef<Path>>(path: P, filter: TraceFilter) -> io::Result<Tracer> {
+        Ok(Tracer::new(
+            Box::new(BufWriter::new(File::create(path)?)),
+            filter,
+        ))
+    }
+
+    /// Creates and returns a tracer writing to the given writer
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The destination of the trace
+    /// - `filter`: The logged instructions
+    ///
+    /// # Returns
+    ///
+    /// A new `Tracer` struct
+    pub fn new(writer: Box<dyn Write>, filter: TraceFilter) -> Tracer {
+        Tracer {
+            writer,
+            filter,
+            executed: 0,
+            error: None,
+        }
+    }
+
+    /// Counts an instruction about to be executed and checks whether it is logged
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the instruction
+    /// - `opcode`: The opcode of the instruction
+    ///
+    /// # Returns
+    ///
+    /// true if the instruction must be logged with `log` once executed
+    pub fn next_instruction(&mut self, pc: u16, opcode: u16) -> bool {
+        self.executed += 1;
+        let filter = &self.filter;
+        self.error.is_none()
+            && filter.limit.map_or(true, |limit| self.executed <= limit)
+            && filter
+                .pc_range
+                .map_or(true, |range| pc >= range.start && pc <= range.end)
+            && filter
+                .families
+                .map_or(true, |families| families.contains(opcode))
+    }
+
+    /// Writes the line of an executed instruction
+    ///
+    /// # Parameters
+    ///
+    /// - `entry`: The state of the machine after the instruction
+    pub fn log(&mut self, entry: &TraceEntry) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(error) = writeln!(self.writer, "{}", entry) {
+            self.error = Some(error);
+        }
+    }
+
+    /// Flushes the trace once the emulation is over
+    ///
+    /// # Returns
+    ///
+    /// The first error that occurred while writing the trace, if any
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()
+    }
+}
+
+/// Parses an hexadecimal address, with an optional `#` or `0x` prefix
+///
+/// # Parameters
+///
+/// - `text`: The address
+///
+/// # Returns
+///
+/// The address, or None if it is invalid
+fn parse_hex(text: &str) -> Option<u16> {
+    let lower = text.trim().to_ascii_lowercase();
+    let digits = lower
+        .strip_prefix('#')
+        .or_else(|| lower.strip_prefix("0x"))
+        .unwrap_or(&lower);
+    // `from_str_radix` accepts a leading sign
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Trace destination shared between a test and the `Tracer` writing to it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Trace destination failing on every write
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns a trace entry of an instruction, with every register cleared
+    fn entry(pc: u16, opcode: u16) -> TraceEntry {
+        TraceEntry {
+            pc,
+            opcode,
+            registers: [0; 16],
+            i: 0,
+            sp: 0,
+            dt: 0,
+            st: 0,
+        }
+    }
+
+    /// Runs the instructions through a tracer and returns the addresses of the logged ones
+    fn logged(filter: TraceFilter, instructions: &[(u16, u16)]) -> Vec<u16> {
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), filter);
+        for (pc, opcode) in instructions {
+            if tracer.next_instruction(*pc, *opcode) {
+                tracer.log(&entry(*pc, *opcode));
+            }
+        }
+        tracer.finish().unwrap();
+        let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        trace
+            .lines()
+            .map(|line| u16::from_str_radix(&line[..4], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn filters_are_combined() {
+        let filter = TraceFilter {
+            pc_range: Some("202-206".parse().unwrap()),
+            families: Some("6,7".parse().unwrap()),
+            limit: Some(5),
+        };
+        let instructions = [
+            (0x200, 0x6001),
+            (0x202, 0x6002),
+            (0x204, 0xA200),
+            (0x206, 0x7001),
+            (0x208, 0x6003),
+            (0x202, 0x6004),
+        ];
+        assert_eq!(logged(filter, &instructions), [0x202, 0x206]);
+    }
+
+    #[test]
+    fn limit_counts_executed_instructions() {
+        let filter = TraceFilter {
+            pc_range: Some("202".parse().unwrap()),
+            families: None,
+            limit: Some(3),
+        };
+        let instructions = [
+            (0x200, 0x6001),
+            (0x200, 0x6001),
+            (0x202, 0x6002),
+            (0x202, 0x6002),
+        ];
+        assert_eq!(logged(filter, &instructions), [0x202]);
+    }
+
+    #[test]
+    fn write_error_is_returned_by_finish() {
+        let mut tracer = Tracer::new(Box::new(FailingWriter), TraceFilter::default());
+        assert!(tracer.next_instruction(0x200, 0x00E0));
+        tracer.log(&entry(0x200, 0x00E0));
+        // Nothing is logged after an error
+        assert!(!tracer.next_instruction(0x202, 0x00E0));
+
+        let error = tracer.finish().unwrap_err();
+        assert_eq!(error.to_string(), "disk full");
+        assert!(tracer.finish().is_ok());
+    }
+
+    #[test]
+    fn entry_is_a_fixed_width_line() {
+        let mut registers = [0; 16];
+        registers[0xA] = 0x02;
+        registers[0xF] = 0x01;
+        let entry = TraceEntry {
+            pc: 0x200,
+            opcode: 0x6A02,
+            registers,
+            i: 0x300,
+            sp: 1,
+            dt: 0x3C,
+            st: 0,
+        };
+        assert_eq!(
+            entry.to_string(),
+            "0200 6A02 LD VA, #02         \
+             V=00 00 00 00 00 00 00 00 00 00 02 00 00 00 00 01 I=0300 SP=01 DT=3C ST=00"
+        );
+        assert_eq!(
+            TraceEntry {
+                opcode: 0x5001,
+                ..entry
+            }
+            .to_string()[..29],
+            *"0200 5001 ???                "
+        );
+    }
+
+    #[test]
+    fn filters_are_parsed() {
+        assert_eq!(
+            "0x200-#2FF".parse(),
+            Ok(PcRange {
+                start: 0x200,
+                end: 0x2FF
+            })
+        );
+        assert_eq!(
+            "300".parse(),
+            Ok(PcRange {
+                start: 0x300,
+                end: 0x300
+            })
+        );
+        for spec in ["2FF-200", "+200", "200-+2FF", "", "10000"] {
+            assert_eq!(
+                spec.parse::<PcRange>(),
+                Err(InvalidTraceFilter(spec.to_string()))
+            );
+        }
+
+        let families: OpcodeFamilies = "8, d,F".parse().unwrap();
+        assert!(families.contains(0x8123) && families.contains(0xD015));
+        assert!(families.contains(0xF033) && !families.contains(0x6000));
+        for spec in ["8,+", "10", "8,,D", "G"] {
+            assert!(spec.parse::<OpcodeFamilies>().is_err());
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Kind of memory access done by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The instruction read the byte
+    Read,
+    /// The instruction wrote the byte
+    Write,
+}
+
+/// Memory accesses reported by a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Reads only
+    Read,
+    /// Writes only
+    Write,
+    /// Reads and writes
+    ReadWrite,
+    /// Writes of the given value only
+    WriteValue(u8),
+}
+
+/// A watched memory range, reporting the accesses of the instructions to its bytes.
+///
+/// Instruction fetches are not accesses: breakpoints are meant for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// First address of the range
+    pub start: u16,
+    /// Last address of the range, included
+    pub end: u16,
+    /// Accesses reported by the watchpoint
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// Checks whether an access is reported by the watchpoint
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The accessed address
+    /// - `access`: The kind of access
+    /// - `value`: The value read or written
+    ///
+    /// # Returns
+    ///
+    /// true if the access is reported
+    pub fn matches(&self, address: u16, access: Access, value: u8) -> bool {
+        if address < self.start || address > self.end {
+            return false;
+        }
+        match (self.kind, access) {
+            (WatchKind::ReadWrite, _) => true,
+            (WatchKind::Read, Access::Read) => true,
+            (WatchKind::Write, Access::Write) => true,
+            (WatchKind::WriteValue(expected), Access::Write) => value == expected,
+            _ => false,
+        }
+    }
+}
+
+/// Error returned when parsing an invalid watchpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidWatchpoint(pub String);
+
+impl fmt::Display for InvalidWatchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid watchpoint '{}' (expected <ADDR>[-<END>][:r|w|rw|=<VALUE>], addresses and \
+             values being hexadecimal)",
+            self.0
+        )
+    }
+}
+
+impl error::Error for InvalidWatchpoint {}
+
+impl FromStr for Watchpoint {
+    type Err = InvalidWatchpoint;
+
+    /// Parses `<ADDR>[-<END>][:r|w|rw|=<VALUE>]`, hexadecimal numbers with an optional `#` or
+    /// `0x` prefix, e.g. `2F0`, `300-302:w` or `#3A0:=FF`. Without a kind, writes are reported.
+    fn from_str(spec: &str) -> Result<Watchpoint, InvalidWatchpoint> {
+        let invalid = || InvalidWatchpoint(spec.to_string());
+        let (range, kind) = match spec.split_once(':') {
+            Some((range, kind)) => (range, kind),
+            None => (spec, "w"),
+        };
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "r" => WatchKind::Read,
+            "w" => WatchKind::Write,
+            "rw" => WatchKind::ReadWrite,
+            value => match value.strip_prefix('=').map(parse_hex) {
+                Some(Some(value)) if value <= 0xFF => WatchKind::WriteValue(value as u8),
+                _ => return Err(invalid()),
+            },
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_hex(start), parse_hex(end)),
+            None => (parse_hex(range), parse_hex(range)),
+        };
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end && end <= 0xFFFF => Ok(Watchpoint {
+                start: start as u16,
+                end: end as u16,
+                kind,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}", self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:04X}", self.end)?;
+        }
+        match self.kind {
+            WatchKind::Read => write!(f, ":r"),
+            WatchKind::Write => write!(f, ":w"),
+            WatchKind::ReadWrite => write!(f, ":rw"),
+            WatchKind::WriteValue(value) => write!(f, ":={:02X}", value),
+        }
+    }
+}
+
+/// A watched access, with the instruction that did it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Address of the instruction
+    pub pc: u16,
+    /// Opcode of the instruction
+    pub opcode: u16,
+    /// Accessed address
+    pub address: u16,
+    /// Kind of access
+    pub access: Access,
+    /// Value of the byte before the access
+    pub old_value: u8,
+    /// Value of the byte after the access
+    pub new_value: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(
+                f,
+                "read of {:#06X} by {:#06X} at {:#05X}: {:#04X}",
+                self.address, self.opcode, self.pc, self.new_value
+            ),
+            Access::Write => write!(
+                f,
+                "write to {:#06X} by {:#06X} at {:#05X}: {:#04X} -> {:#04X}",
+                self.address, self.opcode, self.pc, self.old_value, self.new_value
+            ),
+        }
+    }
+}
+
+/// Watchpoints checked by the `Bus` on each memory access, and the hits they collected
+#[derive(Debug, Clone, Default)]
+pub struct WatchList {
+    /// The watchpoints
+    watchpoints: Vec<Watchpoint>,
+    /// Address of the running instruction
+    pc: u16,
+    /// Opcode of the running instruction
+    opcode: u16,
+    /// Hits not taken yet
+    hits: Vec<WatchHit>,
+}
+
+impl WatchList {
+    /// Creates and returns a new `WatchList` struct.
+    ///
+    /// # Parameters
+    ///
+    /// - `watchpoints`: The watchpoints
+    ///
+    /// # Returns
+    ///
+    /// A new `WatchList` struct, without hits.
+    pub fn new(watchpoints: Vec<Watchpoint>) -> WatchList {
+        WatchList {
+            watchpoints,
+            ..WatchList::default()
+        }
+    }
+
+    /// Gets the watchpoints
+    ///
+    /// # Returns
+    ///
+    /// The watchpoints
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Sets the instruction to which the next accesses are attributed
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the instruction
+    /// - `opcode`: The opcode of the instruction
+    pub fn set_instruction(&mut self, pc: u16, opcode: u16) {
+        self.pc = pc;
+        self.opcode = opcode;
+    }
+
+    /// Records a memory access if a watchpoint reports it
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The accessed address
+    /// - `access`: The kind of access
+    /// - `old_value`: The value of the byte before the access
+    /// - `new_value`: The value of the byte after the access
+    pub fn record(&mut self, address: u16, access: Access, old_value: u8, new_value: u8) {
+        if self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.matches(address, access, new_value))
+        {
+            self.hits.push(WatchHit {
+                pc: self.pc,
+                opcode: self.opcode,
+                address,
+                access,
+                old_value,
+                new_value,
+            });
+        }
+    }
+
+    /// Takes the hits recorded since the last call
+    ///
+    /// # Returns
+    ///
+    /// The hits, oldest first
+    pub fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.hits)
+    }
+}
+
+/// Parses an hexadecimal number, with an optional `#` or `0x` prefix
+///
+/// # Parameters
+///
+/// - `text`: The number
+///
+/// # Returns
+///
+/// The number, or None if it is invalid
+fn parse_hex(text: &str) -> Option<u32> {
+    let lower = text.trim().to_ascii_lowercase();
+    let digits = lower
+        .strip_prefix('#')
+        .or_else(|| lower.strip_prefix("0x"))
+        .unwrap_or(&lower);
+    u32::from_str_radix(digits, 16).ok()
+}
@@ -0,0 +1,577 @@
+//! Golden frame regression tests: each bundled ROM runs headless for a fixed number of frames,
+//! with a fixed seed and scripted keys, then its display is compared with a checked-in PNG image
+//! of `tests/golden`.
+//!
+//! On a mismatch, the actual display and a visual diff (expected, actual, then the differing
+//! pixels in red) are written to `target/tmp/golden`. Run `BLESS=1 cargo test --test golden` to
+//! write the golden images again after an intended change of the display.
+
+use chip8::display;
+use chip8::movie::LiveInput;
+use chip8::png::{self, Palette};
+use chip8::{Chip8, HeadlessConfig, Rng};
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Number of frames run before the comparison: 10 seconds
+const FRAMES: u32 = 600;
+
+/// Seed of the random number generator
+const SEED: u64 = 0xC8;
+
+/// Scale of the diff images
+const DIFF_SCALE: usize = 4;
+
+/// Colors of the diff images, indexed like `display::PALETTE`: off, on, unchanged on pixels in
+/// the diff, differing pixels
+const DIFF_PALETTE: Palette = Palette([0x000000, 0xFFFFFF, 0x555555, 0xFF0000]);
+
+/// Declares one golden test per ROM: `name: "ROM", "key script";`
+macro_rules! golden_tests {
+    ($($name:ident: $rom:expr, $keys:expr;)*) => {
+        $(
+            #[test]
+            fn $name() {
+                check_golden($rom, $keys);
+            }
+        )*
+    };
+}
+
+golden_tests! {
+    puzzle15: "15PUZZLE", "60:6,64:,120:8,124:,180:4,184:";
+    blinky: "BLINKY", "120:6,180:,240:8,300:";
+    blitz: "BLITZ", "120:5,124:,300:5,304:";
+    brix: "BRIX", "60:4,120:,180:6,300:";
+    connect4: "CONNECT4", "60:6,64:,120:5,124:,180:4,184:,240:5,244:";
+    guess: "GUESS", "60:5,64:,180:5,184:";
+    hidden: "HIDDEN", "60:6,64:,120:5,124:,240:8,244:,300:5,304:";
+    invaders: "INVADERS", "100:5,110:,200:4,260:,300:5,304:,400:6,460:";
+    kaleid: "KALEID", "30:6,90:,120:8,180:,240:4,300:";
+    maze: "MAZE", "";
+    merlin: "MERLIN", "";
+    missile: "MISSILE", "60:8,64:,180:8,184:,300:8,304:";
+    pong: "PONG", "30:1,90:,120:C,180:";
+    pong2: "PONG2", "30:1,90:,120:C,180:";
+    puzzle: "PUZZLE", "60:6,64:,120:8,124:";
+    syzygy: "SYZYGY", "60:F,64:,120:6,180:,240:8,300:";
+    tank: "TANK", "30:6,90:,120:5,124:,180:8,240:";
+    tetris: "TETRIS", "60:5,70:,120:4,124:,180:6,190:";
+    tictac: "TICTAC", "60:5,64:,180:1,184:,300:9,304:";
+    ufo: "UFO", "60:5,64:,180:4,184:,300:6,304:";
+    vbrix: "VBRIX", "60:7,64:,120:1,180:,240:4,300:";
+    vers: "VERS", "60:7,120:,180:2,240:";
+    wipeoff: "WIPEOFF", "60:4,120:,180:6,300:";
+}
+
+/// Runs a ROM and compares its display with its golden image, or writes the golden image when
+/// the `BLESS` environment variable is set
+///
+/// # Parameters
+///
+/// - `rom`: The name of the ROM, in `roms`
+/// - `keys`: The key script of the run
+fn check_golden(rom: &str, keys: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let golden_path = root
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", rom));
+
+    // Run the ROM
+    let buffer = fs::read(root.join("roms").join(rom)).expect("cannot read the ROM");
+    let mut chip8 = Chip8::new();
+    chip8.set_rng(Rng::from_seed(SEED));
+    chip8.load_rom(&buffer).expect("the ROM fits in memory");
+    let config = HeadlessConfig {
+        frames: FRAMES,
+        keys: keys.parse().expect("invalid key script"),
+        ..HeadlessConfig::default()
+    };
+    chip8
+        .run_headless(&config, &mut LiveInput)
+        .unwrap_or_else(|error| panic!("{} stopped: {}", rom, error));
+    let actual_png = chip8.screenshot(1, &Palette::default());
+
+    if env::var_os("BLESS").is_some() {
+        fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        fs::write(&golden_path, &actual_png).unwrap();
+        return;
+    }
+
+    let golden = match fs::read(&golden_path) {
+        Ok(golden) => golden,
+        Err(error) => panic!(
+            "cannot read '{}' ({}), run `BLESS=1 cargo test --test golden` to create it",
+            golden_path.display(),
+            error
+        ),
+    };
+    let expected = decode_png(&golden)
+        .unwrap_or_else(|error| panic!("invalid '{}': {}", golden_path.display(), error));
+    let actual = decode_png(&actual_png).expect("screenshots are valid PNG images");
+    if expected == actual {
+        return;
+    }
+
+    // Write the actual display and the diff for inspection
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&output).unwrap();
+    let actual_path = output.join(format!("{}-actual.png", rom));
+    let diff_path = output.join(format!("{}-diff.png", rom));
+    fs::write(&actual_path, &actual_png).unwrap();
+    fs::write(&diff_path, diff_image(&expected, &actual)).unwrap();
+    panic!(
+        "{} differs from '{}' ({}), see '{}' and '{}'; run `BLESS=1 cargo test --test golden` \
+         if the change is intended",
+        rom,
+        golden_path.display(),
+        describe_difference(&expected, &actual),
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+/// Pixels of a decoded image
+#[derive(Debug, PartialEq, Eq)]
+struct Image {
+    /// Width, in pixels
+    width: usize,
+    /// Height, in pixels
+    height: usize,
+    /// RGB color of each pixel, row by row
+    pixels: Vec<u32>,
+}
+
+/// Describes how two images differ
+///
+/// # Parameters
+///
+/// - `expected`: The golden image
+/// - `actual`: The image of the run
+///
+/// # Returns
+///
+/// The size change, or the number of differing pixels
+fn describe_difference(expected: &Image, actual: &Image) -> String {
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return format!(
+            "size {}x{} instead of {}x{}",
+            actual.width, actual.height, expected.width, expected.height
+        );
+    }
+    let count = expected
+        .pixels
+        .iter()
+        .zip(&actual.pixels)
+        .filter(|(expected, actual)| expected != actual)
+        .count();
+    format!("{} pixels differ", count)
+}
+
+/// Builds the diff image: the expected image, the actual image, then the actual image with the
+/// unchanged lit pixels in grey and the differing pixels in red, separated by grey columns
+///
+/// # Parameters
+///
+/// - `expected`: The golden image
+/// - `actual`: The image of the run
+///
+/// # Returns
+///
+/// The bytes of the PNG file
+fn diff_image(expected: &Image, actual: &Image) -> Vec<u8> {
+    // Display colors, which `DIFF_PALETTE` turns into black, white, grey and red
+    const OFF: u32 = display::PALETTE[0];
+    const ON: u32 = display::PALETTE[1];
+    const GREY: u32 = display::PALETTE[2];
+    const RED: u32 = display::PALETTE[3];
+
+    let width = expected.width.max(actual.width);
+    let height = expected.height.max(actual.height);
+    let pixel = |image: &Image, x: usize, y: usize| {
+        if x < image.width && y < image.height {
+            Some(image.pixels[y * image.width + x])
+        } else {
+            None
+        }
+    };
+    let lit = |color: Option<u32>| match color {
+        Some(color) if color != OFF => ON,
+        _ => OFF,
+    };
+
+    let mut buffer = Vec::with_capacity((width * 3 + 2) * height);
+    for y in 0..height {
+        for x in 0..width {
+            buffer.push(lit(pixel(expected, x, y)));
+        }
+        buffer.push(GREY);
+        for x in 0..width {
+            buffer.push(lit(pixel(actual, x, y)));
+        }
+        buffer.push(GREY);
+        for x in 0..width {
+            let (before, after) = (pixel(expected, x, y), pixel(actual, x, y));
+            buffer.push(if before != after {
+                RED
+            } else if lit(after) == ON {
+                GREY
+            } else {
+                OFF
+            });
+        }
+    }
+    png::encode(width * 3 + 2, height, &buffer, DIFF_SCALE, &DIFF_PALETTE)
+}
+
+/// Decodes an 8 bits indexed color PNG image, as written by `Chip8::screenshot`
+///
+/// # Parameters
+///
+/// - `bytes`: The bytes of the PNG file
+///
+/// # Returns
+///
+/// The image, or a message describing why it cannot be decoded
+fn decode_png(bytes: &[u8]) -> Result<Image, String> {
+    let mut chunks = bytes
+        .strip_prefix(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A][..])
+        .ok_or("not a PNG file")?;
+    let (mut width, mut height) = (0, 0);
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    while chunks.len() >= 12 {
+        let length = u32::from_be_bytes(chunks[..4].try_into().unwrap()) as usize;
+        let kind = &chunks[4..8];
+        let data = chunks.get(8..8 + length).ok_or("truncated chunk")?;
+        match kind {
+            b"IHDR" => {
+                width = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+                height = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+                if data[8..] != [8, 3, 0, 0, 0] {
+                    return Err(String::from(
+                        "only 8 bits indexed color images are supported",
+                    ));
+                }
+            }
+            b"PLTE" => {
+                palette = data
+                    .chunks(3)
+                    .map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))
+                    .collect()
+            }
+            b"IDAT" => compressed.extend_from_slice(data),
+            _ => {}
+        }
+        chunks = &chunks[12 + length..];
+    }
+
+    let data = inflate(compressed.get(2..).ok_or("missing image data")?)?;
+    if data.len() < (width + 1) * height {
+        return Err(String::from("truncated image data"));
+    }
+    let mut indexes = vec![0u8; width * height];
+    for y in 0..height {
+        let filter = data[y * (width + 1)];
+        let row = &data[y * (width + 1) + 1..(y + 1) * (width + 1)];
+        for x in 0..width {
+            let left = if x > 0 { indexes[y * width + x - 1] } else { 0 };
+            let up = if y > 0 {
+                indexes[(y - 1) * width + x]
+            } else {
+                0
+            };
+            let up_left = if x > 0 && y > 0 {
+                indexes[(y - 1) * width + x - 1]
+            } else {
+                0
+            };
+            let prediction = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(format!("invalid filter {}", filter)),
+            };
+            indexes[y * width + x] = row[x].wrapping_add(prediction);
+        }
+    }
+
+    let pixels = indexes
+        .iter()
+        .map(|&index| palette.get(index as usize).copied())
+        .collect::<Option<Vec<u32>>>()
+        .ok_or("pixel outside of the palette")?;
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Predicts a byte with the Paeth filter of the PNG format
+///
+/// # Parameters
+///
+/// - `left`: The byte on the left
+/// - `up`: The byte above
+/// - `up_left`: The byte above on the left
+///
+/// # Returns
+///
+/// The neighbour closest to `left + up - up_left`
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance = |value: u8| (estimate - value as i16).abs();
+    if distance(left) <= distance(up) && distance(left) <= distance(up_left) {
+        left
+    } else if distance(up) <= distance(up_left) {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Base lengths of the deflate length codes 257 to 285
+const LENGTH_BASES: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Number of extra bits of the deflate length codes 257 to 285
+const LENGTH_EXTRA_BITS: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances of the deflate distance codes
+const DISTANCE_BASES: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Number of extra bits of the deflate distance codes
+const DISTANCE_EXTRA_BITS: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order of the code length code lengths in the dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Canonical Huffman code of a deflate stream
+struct Huffman {
+    /// Number of codes of each length, from 0 to 15 bits
+    counts: [u16; 16],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the canonical Huffman code of the given code lengths
+    ///
+    /// # Parameters
+    ///
+    /// - `lengths`: The code length of each symbol, 0 for unused symbols
+    ///
+    /// # Returns
+    ///
+    /// The Huffman code
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..16 {
+            for (symbol, _) in lengths
+                .iter()
+                .enumerate()
+                .filter(|(_, &symbol_length)| symbol_length == length)
+            {
+                symbols.push(symbol as u16);
+            }
+        }
+        Huffman { counts, symbols }
+    }
+}
+
+/// Reader of a deflate bit stream
+struct BitReader<'a> {
+    /// The stream
+    bytes: &'a [u8],
+    /// Position of the next bit
+    position: usize,
+}
+
+impl BitReader<'_> {
+    /// Reads a value, least significant bit first
+    ///
+    /// # Parameters
+    ///
+    /// - `count`: The number of bits of the value
+    ///
+    /// # Returns
+    ///
+    /// The value, or an error at the end of the stream
+    fn bits(&mut self, count: u32) -> Result<usize, String> {
+        let mut value = 0;
+        for bit in 0..count {
+            let byte = self
+                .bytes
+                .get(self.position / 8)
+                .ok_or("truncated deflate stream")?;
+            value |= ((byte >> (self.position % 8)) as usize & 1) << bit;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    /// Reads a Huffman coded symbol
+    ///
+    /// # Parameters
+    ///
+    /// - `huffman`: The Huffman code
+    ///
+    /// # Returns
+    ///
+    /// The symbol, or an error if the code is invalid
+    fn symbol(&mut self, huffman: &Huffman) -> Result<usize, String> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &huffman.counts[1..] {
+            code |= self.bits(1)?;
+            let count = count as usize;
+            if code < first + count {
+                return Ok(huffman.symbols[index + code - first] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(String::from("invalid Huffman code"))
+    }
+}
+
+/// Decompresses a raw deflate stream
+///
+/// # Parameters
+///
+/// - `bytes`: The stream, without the zlib header
+///
+/// # Returns
+///
+/// The decompressed data, or a message describing why the stream is invalid
+fn inflate(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader { bytes, position: 0 };
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                // Stored block, aligned on a byte
+                let start = (reader.position + 7) / 8;
+                let header = bytes
+                    .get(start..start + 4)
+                    .ok_or("truncated stored block")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let data = bytes
+                    .get(start + 4..start + 4 + length)
+                    .ok_or("truncated stored block")?;
+                output.extend_from_slice(data);
+                reader.position = (start + 4 + length) * 8;
+            }
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(
+                    &mut reader,
+                    &mut output,
+                    &Huffman::new(&lengths),
+                    &Huffman::new(&[5; 30]),
+                )?;
+            }
+            2 => {
+                let literal_count = reader.bits(5)? + 257;
+                let distance_count = reader.bits(5)? + 1;
+                let code_length_count = reader.bits(4)? + 4;
+                let mut code_lengths = [0; 19];
+                for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+                    code_lengths[symbol] = reader.bits(3)? as u8;
+                }
+                let code_length_code = Huffman::new(&code_lengths);
+
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (value, repeat) = match reader.symbol(&code_length_code)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (
+                            *lengths.last().ok_or("repeat without a previous length")?,
+                            3 + reader.bits(2)?,
+                        ),
+                        17 => (0, 3 + reader.bits(3)?),
+                        _ => (0, 11 + reader.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat(value).take(repeat));
+                }
+                inflate_block(
+                    &mut reader,
+                    &mut output,
+                    &Huffman::new(&lengths[..literal_count]),
+                    &Huffman::new(&lengths[literal_count..]),
+                )?;
+            }
+            _ => return Err(String::from("invalid block type")),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// Decompresses a Huffman coded deflate block
+///
+/// # Parameters
+///
+/// - `reader`: The stream, after the block header
+/// - `output`: The data decompressed so far
+/// - `literals`: The literal/length code
+/// - `distances`: The distance code
+///
+/// # Returns
+///
+/// A message describing why the block is invalid, if it is
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = reader.symbol(literals)?;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                let length = LENGTH_BASES.get(code).ok_or("invalid length code")?
+                    + reader.bits(LENGTH_EXTRA_BITS[code])?;
+                let code = reader.symbol(distances)?;
+                let distance = DISTANCE_BASES.get(code).ok_or("invalid distance code")?
+                    + reader.bits(DISTANCE_EXTRA_BITS[code])?;
+                if distance > output.len() {
+                    return Err(String::from("distance before the start of the data"));
+                }
+                for _ in 0..length {
+                    output.push(output[output.len() - distance]);
+                }
+            }
+        }
+    }
+}